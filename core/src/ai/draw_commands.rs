@@ -1,4 +1,4 @@
-use crossy_multi_core::PreciseCoords;
+use crate::PreciseCoords;
 use serde::Serialize;
 
 
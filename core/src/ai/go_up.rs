@@ -1,15 +1,12 @@
-use std::hash::Hash;
-use std::fmt::Debug;
-
-use crossy_multi_core::map::obstacle_row::ObstaclePublic;
-use crossy_multi_core::{GameState, PlayerId, Input, CoordPos, PreciseCoords, player, Pos, crossy_ruleset};
-use crossy_multi_core::map::{Map, RowType};
-use crossy_multi_core::player::MoveState;
+use crate::map::obstacle_row::ObstaclePublic;
+use crate::{GameState, PlayerId, Input, CoordPos, PreciseCoords, Pos, crossy_ruleset};
+use crate::map::{Map, RowType};
+use crate::player::MoveState;
 
 use froggy_rand::FroggyRand;
 
 use crate::ai::*;
-use crate::draw_commands::{DrawCommand, DrawCoords, DrawType, DrawColour};
+use crate::ai::draw_commands::{DrawCommand, DrawCoords, DrawType, DrawColour};
 
 #[derive(Debug)]
 pub struct GoUpAI
@@ -18,7 +15,8 @@ pub struct GoUpAI
     rng : FroggyRand,
     rng_t : u64,
     careful_t : i32,
-    draw_state : DrawCommands,
+    // Only populated when debug drawing is turned on
+    draw_state : Option<DrawCommands>,
 }
 
 impl GoUpAI {
@@ -28,20 +26,21 @@ impl GoUpAI {
             rng: FroggyRand::new(1234 + 555*(player_id.0 as u64)),
             rng_t : 0,
             careful_t : 0,
-            draw_state : DrawCommands::default(),
+            draw_state : None,
         }
     }
 
-    fn side_random<T : Hash + Debug>(&self, x : T) -> Input {
-        if (self.rng.gen_unit(("do_nothing", self.rng_t, &x)) < 0.9) {
-            Input::None
-        }
-        else {
-            *self.rng.choose(("side_random", self.rng_t, &x), &[Input::Left, Input::Right])
-        }
+    pub fn with_seed(mut self, seed : u32) -> Self {
+        self.rng = FroggyRand::from_hash((seed, self.player_id.0, "go_up_ai"));
+        self
     }
 
-    fn think_lobby(&mut self, game_state : &GameState, map : &Map) -> Input
+    pub fn with_debug_draw(mut self) -> Self {
+        self.draw_state = Some(DrawCommands::default());
+        self
+    }
+
+    fn think_lobby(&mut self, game_state : &GameState, _map : &Map) -> Input
     {
         let maybe_player_state = game_state.get_player(self.player_id);
         if let Some(player_state) = maybe_player_state {
@@ -78,13 +77,13 @@ impl GoUpAI {
                 MoveState::Moving(moving_state) => moving_state.target,
             };
 
-            let precise_pos = map.realise_pos(game_state.time_us, &player_pos);
+            let precise_pos = map.realise_pos(game_state.time_us, &player_pos, &game_state.rules_state.fst);
             let player_pos_coords = precise_pos.to_coords();
             let y_up = precise_pos.y - 1;
             let test_pos = CoordPos { x : precise_pos.x.round() as i32, y : y_up };
-            if (is_safe(&test_pos, game_state, map, &mut self.draw_state))
+            if (is_safe(&test_pos, self.player_id, game_state, map, &mut self.draw_state))
             {
-                let self_pos_safe = is_safe(&player_pos_coords, game_state, map, &mut self.draw_state);
+                let self_pos_safe = is_safe(&player_pos_coords, self.player_id, game_state, map, &mut self.draw_state);
 
                 if (self_pos_safe && should_be_careful(&test_pos, game_state, map) && self.careful_t > 0) {
                     self.careful_t -= 1;
@@ -113,14 +112,14 @@ impl GoUpAI {
 
             for input in &to_try {
                 let try_pos = player_pos_coords.apply_input(*input);
-                if (is_safe(&try_pos, game_state, map, &mut self.draw_state)) {
-                    log!("AGENT {:?} picking shuffled {:?} as safe", self.player_id, input);
+                if (is_safe(&try_pos, self.player_id, game_state, map, &mut self.draw_state)) {
+                    verbose_log!("AGENT {:?} picking shuffled {:?} as safe", self.player_id, input);
                     return *input;
                 }
             }
 
             // Last resort pick random
-            log!("AGENT {:?} resorting to random", self.player_id);
+            verbose_log!("AGENT {:?} resorting to random", self.player_id);
             return *(to_try.first().unwrap());
         }
 
@@ -134,7 +133,22 @@ fn should_be_careful(coordpos : &CoordPos, game_state : &GameState, map : &Map)
     row_type.is_dangerous()
 }
 
-fn is_safe_inner(coordpos : &CoordPos, game_state : &GameState, map : &Map, draw_state : &mut DrawCommands) -> bool {
+fn push_draw(draw_state : &mut Option<DrawCommands>, command : DrawCommand) {
+    if let Some(draw_state) = draw_state.as_mut() {
+        draw_state.commands.push(command);
+    }
+}
+
+fn is_safe_inner(coordpos : &CoordPos, player_id : PlayerId, game_state : &GameState, map : &Map, draw_state : &mut Option<DrawCommands>) -> bool {
+    // Walls, and other players we would just end up in a pushing match with.
+    if (map.solid(game_state.time_us, &game_state.rules_state, *coordpos)) {
+        return false;
+    }
+
+    if (game_state.space_occupied_with_player(Pos::Coord(*coordpos), Some(player_id))) {
+        return false;
+    }
+
     let current_row = map.get_row(game_state.get_round_id(), coordpos.y);
     match &current_row.row_type {
         RowType::River(_)  => {
@@ -169,7 +183,7 @@ fn is_safe_inner(coordpos : &CoordPos, game_state : &GameState, map : &Map, draw
 
                 let dist = (frog_x - car_x + dist_from_movement).abs();
                 if (dist < MIN_CAR_DIST) {
-                    draw_state.commands.push(DrawCommand {
+                    push_draw(draw_state, DrawCommand {
                         pos: DrawCoords::from_precise(car_precise),
                         draw_type : DrawType::Circle,
                         colour : DrawColour::Red,
@@ -177,7 +191,7 @@ fn is_safe_inner(coordpos : &CoordPos, game_state : &GameState, map : &Map, draw
                     result = false;
                 }
                 else {
-                    draw_state.commands.push(DrawCommand {
+                    push_draw(draw_state, DrawCommand {
                         pos: DrawCoords::from_precise(car_precise),
                         draw_type : DrawType::Circle,
                         colour : DrawColour::Green,
@@ -191,18 +205,18 @@ fn is_safe_inner(coordpos : &CoordPos, game_state : &GameState, map : &Map, draw
     }
 }
 
-fn is_safe(coordpos : &CoordPos, game_state : &GameState, map : &Map, draw_state : &mut DrawCommands) -> bool {
-    let result = is_safe_inner(coordpos, game_state, map, draw_state);
+fn is_safe(coordpos : &CoordPos, player_id : PlayerId, game_state : &GameState, map : &Map, draw_state : &mut Option<DrawCommands>) -> bool {
+    let result = is_safe_inner(coordpos, player_id, game_state, map, draw_state);
 
     if (result) {
-        draw_state.commands.push(DrawCommand {
+        push_draw(draw_state, DrawCommand {
             pos: DrawCoords::from_precise(coordpos.to_precise()),
             draw_type : DrawType::Tick,
             colour : DrawColour::Green,
         });
     }
     else {
-        draw_state.commands.push(DrawCommand {
+        push_draw(draw_state, DrawCommand {
             pos: DrawCoords::from_precise(coordpos.to_precise()),
             draw_type : DrawType::Cross,
             colour : DrawColour::Red,
@@ -217,7 +231,9 @@ impl AIAgent for GoUpAI
     fn think(&mut self, game_state : &GameState, map : &Map) -> Input
     {
         self.rng_t += 1;
-        self.draw_state.commands.clear();
+        if let Some(draw_state) = self.draw_state.as_mut() {
+            draw_state.commands.clear();
+        }
 
         match game_state.get_rule_state().fst
         {
@@ -230,7 +246,7 @@ impl AIAgent for GoUpAI
         }
     }
 
-    fn get_drawstate(&self) -> &DrawCommands {
-        &self.draw_state
+    fn get_drawstate(&self) -> Option<&DrawCommands> {
+        self.draw_state.as_ref()
     }
 }
\ No newline at end of file
@@ -0,0 +1,457 @@
+use std::collections::HashSet;
+
+use crate::crossy_ruleset::{death_cause, AliveState, CrossyRulesetFST};
+use crate::map::{Map, RowType};
+use crate::player::{self, MoveState, MovingState, PlayerState, Powerup, BOOSTED_MOVE_DUR, MOVE_DUR};
+use crate::timeline::TICK_INTERVAL_US;
+use crate::{GameState, Input, LillipadId, PlayerId, Pos};
+
+use crate::ai::AIAgent;
+
+// Searches over (Pos, time_us), cars and lillipads are a pure function of time so we can
+// see exactly where they will be.
+// Each step of the search is either a move, which takes MOVE_DUR or less with a speed boost, or a short wait.
+// We pick the first action of the plan that gets furthest up the screen while surviving
+// to the end of the search.
+
+const WAIT_TICKS : u32 = 4;
+
+// After the last step make sure we aren't about to be hit.
+const LANDING_TICKS : u32 = 3;
+
+const SCREEN_CENTRE_X : f64 = (crate::SCREEN_SIZE as f64 - 1.0) / 2.0;
+
+// Lillipads kill a little way offscreen, but by then there is nowhere to jump to.
+// Treat them as deadly well before that so we get off in time.
+const LILLIPAD_EDGE_MARGIN : f64 = 1.0;
+
+// Lillipads drift much slower than we can search, so a plan that ends on one about to reach
+// the edge scores as if it were a couple of rows further back.
+const LILLIPAD_DOOMED_TICKS : u32 = 60;
+const LILLIPAD_DOOMED_PENALTY : i32 = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LookaheadDifficulty {
+    // Number of moves / waits to search ahead.
+    pub max_depth : u32,
+    // Frames between seeing the world and acting on it.
+    // The plan is made for "now" so a long delay leads to mistakes.
+    pub reaction_delay_frames : u32,
+}
+
+impl LookaheadDifficulty {
+    pub const EASY : Self = Self {
+        max_depth : 2,
+        reaction_delay_frames : 10,
+    };
+
+    pub const MEDIUM : Self = Self {
+        max_depth : 4,
+        reaction_delay_frames : 4,
+    };
+
+    pub const HARD : Self = Self {
+        max_depth : 6,
+        reaction_delay_frames : 0,
+    };
+}
+
+#[derive(Debug)]
+pub struct LookaheadAI
+{
+    player_id : PlayerId,
+    difficulty : LookaheadDifficulty,
+    // Decided input waiting on the reaction delay, and the frame to send it on.
+    pending : Option<(u32, Input)>,
+    // When we decide to wait don't search again straight away.
+    next_think_frame : u32,
+}
+
+impl LookaheadAI {
+    pub fn new(player_id : PlayerId, difficulty : LookaheadDifficulty) -> Self {
+        Self {
+            player_id,
+            difficulty,
+            pending : None,
+            next_think_frame : 0,
+        }
+    }
+}
+
+impl AIAgent for LookaheadAI
+{
+    fn think(&mut self, game_state : &GameState, map : &Map) -> Input
+    {
+        if let Some((frame_id, input)) = self.pending {
+            if (game_state.frame_id >= frame_id) {
+                self.pending = None;
+                return input;
+            }
+
+            return Input::None;
+        }
+
+        match &game_state.rules_state.fst {
+            CrossyRulesetFST::Round(_) | CrossyRulesetFST::RoundCooldown(_) => {},
+            _ => return Input::None,
+        }
+
+        if (game_state.rules_state.fst.get_player_alive(self.player_id) != AliveState::Alive) {
+            return Input::None;
+        }
+
+        let Some(player_state) = game_state.get_player(self.player_id) else {
+            return Input::None;
+        };
+
+        if (!player_state.can_move() || game_state.frame_id < self.next_think_frame) {
+            return Input::None;
+        }
+
+        let input = Search::new(self.player_id, game_state, map).best_input(self.difficulty.max_depth);
+        if (input == Input::None) {
+            self.next_think_frame = game_state.frame_id + WAIT_TICKS / 2;
+        }
+
+        if (self.difficulty.reaction_delay_frames == 0 || input == Input::None) {
+            input
+        }
+        else {
+            self.pending = Some((game_state.frame_id + self.difficulty.reaction_delay_frames, input));
+            Input::None
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Node {
+    pos : Pos,
+    time_us : u32,
+    first_action : Input,
+}
+
+impl Node {
+    fn key(&self) -> (u8, i32, i32, u32) {
+        match self.pos {
+            Pos::Coord(coord) => (0, coord.x, coord.y, self.time_us),
+            Pos::Lillipad(lillipad) => (1, lillipad.id as i32, lillipad.y, self.time_us),
+            Pos::Absolute(_) => unreachable!(),
+        }
+    }
+
+    fn y(&self) -> i32 {
+        self.pos.get_y_grid()
+    }
+}
+
+struct Search<'a> {
+    player_id : PlayerId,
+    game_state : &'a GameState,
+    map : &'a Map,
+    round_id : u8,
+    screen_y : i32,
+    lillipad_margin : Option<f64>,
+    // Moves starting before this are sped up.
+    boost_until_us : u32,
+}
+
+impl<'a> Search<'a> {
+    fn new(player_id : PlayerId, game_state : &'a GameState, map : &'a Map) -> Self {
+        let boost_until_us = match game_state.get_player(player_id).and_then(|x| x.powerup) {
+            Some(Powerup::SpeedBoost { remaining_us }) => game_state.time_us + remaining_us,
+            _ => 0,
+        };
+
+        Self {
+            player_id,
+            game_state,
+            map,
+            round_id : game_state.get_round_id(),
+            screen_y : game_state.rules_state.fst.get_screen_y(),
+            lillipad_margin : Some(LILLIPAD_EDGE_MARGIN),
+            boost_until_us,
+        }
+    }
+
+    fn best_input(&mut self, max_depth : u32) -> Input {
+        if let Some(input) = self.search(max_depth) {
+            return input;
+        }
+
+        // Already too close to the edge for the margin, see if we can still save ourselves.
+        self.lillipad_margin = None;
+        self.search(max_depth).unwrap_or(Input::None)
+    }
+
+    fn search(&self, max_depth : u32) -> Option<Input> {
+        let player_state = self.game_state.get_player(self.player_id).unwrap();
+
+        // Our input gets applied on the next tick
+        let root = Node {
+            pos : player_state.pos,
+            time_us : self.game_state.time_us + TICK_INTERVAL_US,
+            first_action : Input::None,
+        };
+
+        let mut layer = vec![root];
+        let mut best_layer = Vec::new();
+        let mut deepest = 0;
+
+        for depth in 0..max_depth.max(1) {
+            let mut next = Vec::new();
+            let mut seen = HashSet::new();
+
+            for node in &layer {
+                // Moves come before waiting so that when acting now or later both reach
+                // the same place we act now, otherwise we put things off until it's too late.
+                for action in [Input::Up, Input::Left, Input::Right, Input::Down, Input::None] {
+                    if let Some(mut child) = self.expand(node, action, depth == 0) {
+                        if (depth == 0) {
+                            child.first_action = action;
+                        }
+
+                        if (seen.insert(child.key())) {
+                            next.push(child);
+                        }
+                    }
+                }
+            }
+
+            if (next.is_empty()) {
+                // Nothing survives this far, go with the longest surviving plan.
+                break;
+            }
+
+            best_layer = next.clone();
+            layer = next;
+            deepest = depth + 1;
+        }
+
+        verbose_log!("Lookahead {:?} searched to depth {} with {} plans", self.player_id, deepest, best_layer.len());
+
+        // Furthest up, then closest to the middle so we don't get carried into walls or off the edge.
+        // Ties keep the first found, which prefers going up.
+        let mut best : Option<(i32, f64, Input)> = None;
+        for node in &best_layer {
+            let x = self.map.realise_pos(node.time_us, &node.pos, &self.game_state.rules_state.fst).x;
+            let from_centre = (x - SCREEN_CENTRE_X).abs();
+
+            let mut y = node.y();
+            if let Pos::Lillipad(lillipad) = &node.pos {
+                if (self.lillipad_near_edge(lillipad, node.time_us + LILLIPAD_DOOMED_TICKS * TICK_INTERVAL_US, LILLIPAD_EDGE_MARGIN)) {
+                    y += LILLIPAD_DOOMED_PENALTY;
+                }
+            }
+
+            let better = match best {
+                Some((best_y, best_from_centre, _)) => y < best_y || (y == best_y && from_centre < best_from_centre - 0.5),
+                None => true,
+            };
+
+            if (better) {
+                best = Some((y, from_centre, node.first_action));
+            }
+        }
+
+        best.map(|x| x.2)
+    }
+
+    fn dies(&self, pos : Pos, move_target : Option<Pos>, time_us : u32) -> bool {
+        // Go through the same check the ruleset uses to kill players.
+        let move_state = match move_target {
+            Some(target) => MoveState::Moving(MovingState::new(0, target)),
+            None => MoveState::Stationary,
+        };
+
+        let player_state = PlayerState {
+            id : self.player_id,
+            move_state,
+            move_cooldown : 0,
+            pos,
+            powerup : None,
+        };
+
+        if (death_cause(time_us, self.round_id, self.map, &player_state, self.screen_y, &self.game_state.rules_state.fst).is_some()) {
+            return true;
+        }
+
+        match (&pos, self.lillipad_margin) {
+            (Pos::Lillipad(lillipad), Some(margin)) => self.lillipad_near_edge(lillipad, time_us, margin),
+            _ => false,
+        }
+    }
+
+    fn lillipad_near_edge(&self, lillipad : &LillipadId, time_us : u32, margin : f64) -> bool {
+        let x = self.map.get_lillipad_screen_x(time_us, lillipad, &self.game_state.rules_state.fst);
+        x < margin || x > crate::SCREEN_SIZE as f64 - 1.0 - margin
+    }
+
+    fn move_ticks(&self, time_us : u32) -> u32 {
+        if (time_us <= self.boost_until_us) {
+            player::move_ticks(BOOSTED_MOVE_DUR)
+        }
+        else {
+            player::move_ticks(MOVE_DUR)
+        }
+    }
+
+    fn is_water(&self, pos : Pos) -> bool {
+        match pos {
+            Pos::Coord(coord) => matches!(self.map.get_row(self.round_id, coord.y).row_type, RowType::River(_)),
+            _ => false,
+        }
+    }
+
+    // Jumping into the water, see if a lillipad drifts into reach before our next wait would end.
+    // Returns how long to wait and the lillipad to jump to.
+    fn wait_for_lillipad(&self, node : &Node, action : Input) -> Option<(u32, Pos)> {
+        let fst = &self.game_state.rules_state.fst;
+        (1..WAIT_TICKS).find_map(|ticks| {
+            let time_us = node.time_us + ticks * TICK_INTERVAL_US;
+            let precise = self.map.realise_pos(time_us, &node.pos, fst).apply_input(action);
+            let lillipad = self.map.lillipad_at_pos(self.round_id, time_us, precise, &self.game_state.rules_state)?;
+            Some((ticks, Pos::Lillipad(lillipad)))
+        })
+    }
+
+    fn survives_ticks(&self, pos : Pos, move_target : Option<Pos>, time_us : u32, ticks : u32) -> bool {
+        (0..ticks).all(|i| !self.dies(pos, move_target, time_us + i * TICK_INTERVAL_US))
+    }
+
+    fn expand(&self, node : &Node, action : Input, is_root : bool) -> Option<Node> {
+        if (action == Input::None) {
+            if (!self.survives_ticks(node.pos, None, node.time_us, WAIT_TICKS)) {
+                return None;
+            }
+
+            return Some(Node {
+                time_us : node.time_us + WAIT_TICKS * TICK_INTERVAL_US,
+                ..*node
+            });
+        }
+
+        let mut target = self.map.try_apply_input(node.time_us, &self.game_state.rules_state, &node.pos, action)?;
+        let mut time_us = node.time_us;
+
+        // Rather than drown, wait for a lillipad. From the root we have to act now,
+        // we will search again next tick anyway.
+        if (!is_root && self.is_water(target)) {
+            if let Some((wait_ticks, lillipad)) = self.wait_for_lillipad(node, action) {
+                if (!self.survives_ticks(node.pos, None, time_us, wait_ticks)) {
+                    return None;
+                }
+
+                time_us += wait_ticks * TICK_INTERVAL_US;
+                target = lillipad;
+            }
+        }
+
+        // We can only see where other players are now, so only avoid them for the first move.
+        if (is_root && self.game_state.space_occupied_with_player(target, Some(self.player_id))) {
+            return None;
+        }
+
+        let mut pos = node.pos;
+
+        loop {
+            let move_ticks = self.move_ticks(time_us);
+            if (!self.survives_ticks(pos, Some(target), time_us, move_ticks)) {
+                return None;
+            }
+
+            time_us += move_ticks * TICK_INTERVAL_US;
+            pos = target;
+
+            // Arriving on ice carries on sliding in the same direction, mirrors PlayerState::tick_iterate
+            let Pos::Coord(coord) = pos else {
+                break;
+            };
+
+            if let RowType::IcyRow(_) = self.map.get_row(self.round_id, coord.y).row_type {
+                match self.map.try_apply_input(time_us, &self.game_state.rules_state, &pos, action) {
+                    Some(next) => target = next,
+                    None => break,
+                }
+            }
+            else {
+                break;
+            }
+        }
+
+        if (!self.survives_ticks(pos, None, time_us, LANDING_TICKS)) {
+            return None;
+        }
+
+        Some(Node {
+            pos,
+            time_us,
+            first_action : node.first_action,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crossy_ruleset::GameConfig;
+    use crate::timeline::Timeline;
+
+    // Runs a bot alone through the first round.
+    // Returns how far up it got and how many frames it survived.
+    fn run_solo(seed : &str, difficulty : LookaheadDifficulty, max_frames : u32) -> (i32, u32) {
+        let config = GameConfig {
+            bypass_lobby : true,
+            minimum_players : 1,
+            ..Default::default()
+        };
+
+        let mut timeline = Timeline::from_seed(config, seed);
+        let player_id = PlayerId(1);
+        timeline.add_player(player_id, Pos::new_coord(10, 13));
+        let mut bot = LookaheadAI::new(player_id, difficulty);
+
+        let mut min_y = i32::MAX;
+        let mut round_frames = 0;
+        for _ in 0..max_frames {
+            let mut inputs = crate::PlayerInputs::new();
+            inputs.set(player_id, bot.think(timeline.top_state(), &timeline.map));
+            timeline.tick(Some(inputs), TICK_INTERVAL_US);
+
+            let top = timeline.top_state();
+            match &top.rules_state.fst {
+                CrossyRulesetFST::Round(_) => {
+                    min_y = min_y.min(top.get_player(player_id).unwrap().pos.get_y_grid());
+                    round_frames += 1;
+                },
+                CrossyRulesetFST::RoundCooldown(_) => {
+                    break;
+                },
+                _ => {},
+            }
+        }
+
+        (min_y, round_frames)
+    }
+
+    #[test]
+    fn lookahead_harder_gets_further() {
+        let seeds = ["a", "b", "c", "d", "e", "f", "g", "h"];
+        let max_frames = 60 * 60;
+
+        let mut easy_progress = 0;
+        let mut hard_progress = 0;
+        let mut hard_frames = 0;
+        let mut easy_frames = 0;
+        for seed in seeds {
+            let (easy_y, easy_survived) = run_solo(seed, LookaheadDifficulty::EASY, max_frames);
+            let (hard_y, hard_survived) = run_solo(seed, LookaheadDifficulty::HARD, max_frames);
+            easy_progress -= easy_y;
+            hard_progress -= hard_y;
+            easy_frames += easy_survived;
+            hard_frames += hard_survived;
+        }
+
+        assert!(hard_progress > easy_progress, "Hard got {} rows, easy got {}", hard_progress, easy_progress);
+        assert!(hard_frames > easy_frames, "Hard survived {} frames, easy survived {}", hard_frames, easy_frames);
+    }
+}
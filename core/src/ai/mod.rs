@@ -0,0 +1,121 @@
+pub mod go_up;
+pub mod lookahead;
+pub mod draw_commands;
+
+use froggy_rand::FroggyRand;
+
+use crate::{PlayerId, GameState, Input};
+use crate::crossy_ruleset::AliveState;
+use crate::map::Map;
+
+use draw_commands::DrawCommands;
+
+// Bots only get to see what any client sees, the game state and the map.
+// So they can be driven from the wasm client, the desktop client, the server or the simulator.
+pub trait AIAgent : std::fmt::Debug
+{
+    fn think(&mut self, game_state: &GameState, map: &Map) -> Input;
+
+    // Debug visualisation, only agents that have drawing enabled return anything.
+    fn get_drawstate(&self) -> Option<&DrawCommands> {
+        None
+    }
+}
+
+pub const AGENT_NAMES : [&str; 6] = ["go_up", "back_and_forth", "random", "lookahead_easy", "lookahead", "lookahead_hard"];
+
+pub fn create_agent(name : &str, player_id : PlayerId, seed : u32) -> Option<Box<dyn AIAgent>> {
+    match name {
+        "go_up" => Some(Box::new(go_up::GoUpAI::new(player_id).with_seed(seed))),
+        "back_and_forth" => Some(Box::new(BackAndForth::new(player_id))),
+        "random" => Some(Box::new(RandomAI::new(player_id).with_seed(seed))),
+        "lookahead_easy" => Some(Box::new(lookahead::LookaheadAI::new(player_id, lookahead::LookaheadDifficulty::EASY))),
+        "lookahead" => Some(Box::new(lookahead::LookaheadAI::new(player_id, lookahead::LookaheadDifficulty::MEDIUM))),
+        "lookahead_hard" => Some(Box::new(lookahead::LookaheadAI::new(player_id, lookahead::LookaheadDifficulty::HARD))),
+        _ => None,
+    }
+}
+
+#[derive(Debug)]
+pub struct BackAndForth
+{
+    player_id : PlayerId,
+}
+
+impl BackAndForth
+{
+    pub fn new(player_id : PlayerId) -> Self {
+        Self {
+            player_id,
+        }
+    }
+}
+
+impl AIAgent for BackAndForth
+{
+    fn think(&mut self, game_state : &GameState, _ : &Map) -> Input
+    {
+        /*
+        if (game_state.frame_id % 60 == 0) {
+            Input::Left
+        }
+        else if (game_state.frame_id % 60 == 30) {
+            Input::Right
+        }
+        else {
+            Input::None
+        }
+        */
+
+        if (game_state.rules_state.fst.get_player_alive(self.player_id) != AliveState::Alive) {
+            return Input::None;
+        }
+
+        if (game_state.frame_id / 60) % 2 == 0 {
+            Input::Left
+        }
+        else {
+            Input::Right
+        }
+
+    }
+}
+
+// Mashes buttons, useful as a baseline.
+#[derive(Debug)]
+pub struct RandomAI
+{
+    player_id : PlayerId,
+    rng : FroggyRand,
+}
+
+impl RandomAI
+{
+    pub fn new(player_id : PlayerId) -> Self {
+        Self {
+            player_id,
+            rng : FroggyRand::new(4321 + 555*(player_id.0 as u64)),
+        }
+    }
+
+    pub fn with_seed(mut self, seed : u32) -> Self {
+        self.rng = FroggyRand::from_hash((seed, self.player_id.0, "random_ai"));
+        self
+    }
+}
+
+impl AIAgent for RandomAI
+{
+    fn think(&mut self, game_state : &GameState, _ : &Map) -> Input
+    {
+        if (game_state.rules_state.fst.get_player_alive(self.player_id) != AliveState::Alive) {
+            return Input::None;
+        }
+
+        if (self.rng.gen_unit(("idle", game_state.frame_id)) < 0.85) {
+            return Input::None;
+        }
+
+        *self.rng.choose(("input", game_state.frame_id), &[Input::Up, Input::Up, Input::Left, Input::Right, Input::Down])
+    }
+}
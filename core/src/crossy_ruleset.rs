@@ -1,15 +1,28 @@
 use serde::{Deserialize, Serialize};
 
 use crate::game::{PlayerId, Pos, CoordPos};
+use crate::game_rules::{GameMode, GameRules};
 use crate::player::PlayerState;
 use crate::player_id_map::PlayerIdMap;
 use crate::map::{Map, RowType};
+use crate::map::level::LevelPreset;
+use crate::map::profile::{MapGenPreset, MapGenProfile};
 
 #[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
 pub struct GameConfig {
     pub required_win_count : u8,
     pub minimum_players : u8,
     pub bypass_lobby : bool,
+    #[serde(default)]
+    pub map_gen : MapGenPreset,
+    #[serde(default)]
+    pub game_mode : GameMode,
+    // Past this the screen starts scrolling on its own so rounds can't stall.
+    #[serde(default)]
+    pub round_time_limit_us : Option<u32>,
+    // Play an authored level every round instead of generating the map.
+    #[serde(default)]
+    pub level : Option<LevelPreset>,
 }
 
 impl Default for GameConfig {
@@ -18,6 +31,24 @@ impl Default for GameConfig {
             required_win_count : 3,
             minimum_players : 2,
             bypass_lobby: false,
+            map_gen : MapGenPreset::Standard,
+            game_mode : GameMode::LastFrogStanding,
+            round_time_limit_us : None,
+            level : None,
+        }
+    }
+}
+
+impl GameConfig {
+    // The map everyone generates from the seed, the mode gets a say on top of the preset.
+    pub fn map_profile(&self) -> MapGenProfile {
+        self.game_mode.rules().map_profile(self.map_gen.profile())
+    }
+
+    pub fn create_map(&self, seed : u32) -> Map {
+        match self.level {
+            Some(level) => Map::exact_seed_from_level(seed, self.map_profile(), level.level()),
+            None => Map::exact_seed_with_profile(seed, self.map_profile()),
         }
     }
 }
@@ -43,6 +74,9 @@ pub struct WarmupState {
     pub alive_states : PlayerIdMap<AliveState>,
     pub win_counts : PlayerIdMap<u8>,
     pub round_id : u8,
+    // Team modes, which team everyone is on. Empty for free for all.
+    #[serde(default)]
+    pub teams : PlayerIdMap<u8>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
@@ -51,6 +85,17 @@ pub struct RoundState {
     pub alive_states : PlayerIdMap<AliveState>,
     pub win_counts : PlayerIdMap<u8>,
     pub round_id : u8,
+    // Race mode, players who crossed the finish line in the order they did it.
+    #[serde(default)]
+    pub finish_order : Vec<PlayerId>,
+    #[serde(default)]
+    pub teams : PlayerIdMap<u8>,
+    // Time since the round started, for GameConfig.round_time_limit_us.
+    #[serde(default)]
+    pub round_time_us : u32,
+    // Rows whose pickup has been taken, each can only be taken once a round.
+    #[serde(default)]
+    pub taken_pickups : Vec<i32>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
@@ -63,15 +108,29 @@ pub struct CooldownState {
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct EndWinnerState {
+    // In team modes this is one of the winning team.
     pub winner_id : PlayerId,
     pub remaining_us : u32,
+    #[serde(default)]
+    pub winner_team : Option<u8>,
+    #[serde(default)]
+    pub teams : PlayerIdMap<u8>,
 }
 
 impl EndWinnerState {
-    fn new(winner_id : PlayerId) -> Self {
+    fn new(winner_id : PlayerId, teams : PlayerIdMap<u8>) -> Self {
         Self {
             winner_id,
             remaining_us: WINNER_TIME_US,
+            winner_team : teams.get_copy(winner_id),
+            teams,
+        }
+    }
+
+    pub fn is_winner(&self, player_id : PlayerId) -> bool {
+        match self.winner_team {
+            Some(team) => self.teams.get_copy(player_id) == Some(team),
+            None => player_id == self.winner_id,
         }
     }
 }
@@ -125,6 +184,17 @@ const COOLDOWN_TIME_US : u32 = 4 * 1_000_000;
 pub const WINNER_TIME_US : u32 = 3 * 1_000_000;
 const RIVER_SPAWN_Y_OFFSET : i32 = 12;
 
+// Once a round goes over its time limit the screen moves up on its own, getting faster until it
+// catches everyone.
+const OVERTIME_SCROLL_ROWS_PER_S : f64 = 0.5;
+const OVERTIME_SCROLL_ACCEL : f64 = 0.25;
+
+// How far the screen has been pushed up, some time after the limit.
+fn overtime_scroll_rows(overtime_us : u32) -> i32 {
+    let t = overtime_us as f64 / 1_000_000.0;
+    (OVERTIME_SCROLL_ROWS_PER_S * t + 0.5 * OVERTIME_SCROLL_ACCEL * t * t) as i32
+}
+
 use CrossyRulesetFST::*;
 
 impl RulesState
@@ -199,6 +269,11 @@ impl CrossyRulesetFST
                             // Initialize to all zero
                             let win_counts = PlayerIdMap::seed_from(player_states, 0);
                             let alive_states = PlayerIdMap::seed_from(player_states, AliveState::Alive);
+
+                            // Before we move everyone off the raft
+                            let mut teams = PlayerIdMap::new();
+                            game_config.game_mode.rules().update_teams(&mut teams, player_states);
+
                             reset_positions(player_states, ResetPositionTarget::RacePositions);
 
                             RoundWarmup(WarmupState {
@@ -207,6 +282,7 @@ impl CrossyRulesetFST
                                 remaining_us : INTRO_COUNTDOWN_TIME_US,
                                 time_full_us: INTRO_COUNTDOWN_TIME_US,
                                 round_id : 1,
+                                teams,
                             })
                         }
                         else {
@@ -239,16 +315,23 @@ impl CrossyRulesetFST
                             alive_states : state.alive_states.clone(),
                             win_counts : state.win_counts.clone(),
                             round_id : state.round_id,
+                            teams : state.teams.clone(),
                         })
                     }
                     _ => {
                         let alive_states = PlayerIdMap::seed_from(player_states, AliveState::Alive);
-                        Round(RoundState {
+                        let mut round_state = RoundState {
                             screen_y : 0,
                             alive_states,
                             win_counts: state.win_counts.clone(),
                             round_id : state.round_id,
-                        })
+                            finish_order : Vec::new(),
+                            teams : state.teams.clone(),
+                            round_time_us : 0,
+                            taken_pickups : Vec::new(),
+                        };
+                        game_config.game_mode.rules().start_round(&mut round_state, player_states);
+                        Round(round_state)
                     }
                 }
             },
@@ -259,21 +342,29 @@ impl CrossyRulesetFST
                     return EndAllLeft(EndAllLeftState::default());
                 }
 
+                let rules = game_config.game_mode.rules();
                 let mut new_state = state.clone();
                 // New player joined?
                 new_state.alive_states.seed_missing(player_states, AliveState::NotInGame);
-                new_state.screen_y = update_screen_y(new_state.screen_y, player_states, &new_state.alive_states);
-
-                kill_players(time_us, new_state.round_id, &mut new_state.alive_states, map, player_states, new_state.screen_y, self);
+                new_state.round_time_us = state.round_time_us.saturating_add(dt);
+                new_state.screen_y = rules.update_screen_y(&new_state, player_states);
+
+                if let Some(limit_us) = game_config.round_time_limit_us {
+                    // Frame by frame differences add up to exactly overtime_scroll_rows
+                    let forced_rows = overtime_scroll_rows(new_state.round_time_us.saturating_sub(limit_us))
+                        - overtime_scroll_rows(state.round_time_us.saturating_sub(limit_us));
+                    new_state.screen_y = new_state.screen_y.min(state.screen_y - forced_rows);
+                }
 
-                let alive_player_count = new_state.alive_states.iter().filter(|(_, x)| **x == AliveState::Alive).count();
+                kill_players(time_us, &mut new_state, map, player_states, self, rules);
+                rules.tick_round(&mut new_state, player_states);
 
                 // Update spawn times
                 // Force evaluation up to screen top
                 let spawn_to_y = new_state.screen_y - RIVER_SPAWN_Y_OFFSET;
                 let _ = map.get_row(new_state.round_id, spawn_to_y);
 
-                if (alive_player_count < game_config.minimum_players as usize) {
+                if (rules.round_over(&new_state, game_config)) {
                     RoundCooldown(CooldownState {
                         remaining_us : COOLDOWN_TIME_US,
                         round_state : new_state,
@@ -284,10 +375,11 @@ impl CrossyRulesetFST
                 }
             },
             RoundCooldown(state) => {
+                let rules = game_config.game_mode.rules();
                 let mut new_state = state.clone();
                 new_state.round_state.alive_states.seed_missing(player_states, AliveState::NotInGame);
-                new_state.round_state.screen_y = update_screen_y(new_state.round_state.screen_y, player_states, &new_state.round_state.alive_states);
-                kill_players(time_us, state.round_state.round_id, &mut new_state.round_state.alive_states, map, player_states, new_state.round_state.screen_y, &self);
+                new_state.round_state.screen_y = rules.update_screen_y(&new_state.round_state, player_states);
+                kill_players(time_us, &mut new_state.round_state, map, player_states, self, rules);
 
                 match state.remaining_us.checked_sub(dt) {
                     Some(remaining_us) => {
@@ -297,28 +389,23 @@ impl CrossyRulesetFST
                         })
                     }
                     _ => {
-                        // We know up to one person is alive here
-                        let winner = new_state.round_state.alive_states.iter().filter(|(_, x)| **x == AliveState::Alive).map(|(id, _)| id).next();
-
                         let mut win_counts = new_state.round_state.win_counts.clone();
+                        rules.score_round(&new_state.round_state, &mut win_counts);
 
-                        if let Some(winner_id) = winner {
-                            let new_count = win_counts.get(winner_id).copied().unwrap_or(0) + 1;
-                            debug_log!("Going to next round, winner player={:?} count={}", winner_id, new_count);
-                            if (new_count >= game_config.required_win_count) {
-                                debug_log!("Going to end state");
-                                reset_positions(player_states, ResetPositionTarget::LobbyPositions);
-                                return EndWinner(EndWinnerState::new(winner_id));
-                            }
-                            win_counts.set(winner_id, new_count);
+                        if let Some(winner_id) = rules.match_winner(&win_counts, game_config) {
+                            debug_log!("Going to end state, winner player={:?}", winner_id);
+                            reset_positions(player_states, ResetPositionTarget::LobbyPositions);
+                            return EndWinner(EndWinnerState::new(winner_id, new_state.round_state.teams.clone()));
                         }
 
                         // Take into account all players that have joined during the round
                         let alive_states = PlayerIdMap::seed_from(player_states, AliveState::Alive);
                         win_counts.seed_missing(player_states, 0);
-                        println!("CALLING RESET_POSITIONS BEFORE {:#?}", player_states);
+                        let mut teams = new_state.round_state.teams.clone();
+                        rules.update_teams(&mut teams, player_states);
+                        verbose_log!("CALLING RESET_POSITIONS BEFORE {:#?}", player_states);
                         reset_positions(player_states, ResetPositionTarget::RacePositions);
-                        println!("CALLING RESET_POSITIONS AFTER {:#?}", player_states);
+                        verbose_log!("CALLING RESET_POSITIONS AFTER {:#?}", player_states);
 
                         RoundWarmup(WarmupState {
                             remaining_us : COUNTDOWN_TIME_US,
@@ -326,6 +413,7 @@ impl CrossyRulesetFST
                             win_counts,
                             alive_states,
                             round_id : new_state.round_state.round_id + 1,
+                            teams,
                         })
                     }
                 }
@@ -335,7 +423,7 @@ impl CrossyRulesetFST
                     Some(remaining_us) => {
                         EndWinner(EndWinnerState {
                             remaining_us,
-                            winner_id : state.winner_id,
+                            ..state.clone()
                         })
                     }
                     _ => {
@@ -385,7 +473,7 @@ impl CrossyRulesetFST
                 state.round_state.alive_states.get_copy(player_id).unwrap_or(AliveState::NotInGame)
             },
             EndWinner(state) => {
-                if player_id == state.winner_id
+                if state.is_winner(player_id)
                 {
                     AliveState::Alive
                 }
@@ -398,6 +486,32 @@ impl CrossyRulesetFST
         }
     }
 
+    pub fn get_taken_pickups(&self) -> Option<&Vec<i32>> {
+        match self {
+            Round(state) => Some(&state.taken_pickups),
+            RoundCooldown(state) => Some(&state.round_state.taken_pickups),
+            _ => None,
+        }
+    }
+
+    pub fn get_taken_pickups_mut(&mut self) -> Option<&mut Vec<i32>> {
+        match self {
+            Round(state) => Some(&mut state.taken_pickups),
+            RoundCooldown(state) => Some(&mut state.round_state.taken_pickups),
+            _ => None,
+        }
+    }
+
+    pub fn get_player_team(&self, player_id : PlayerId) -> Option<u8> {
+        match self {
+            RoundWarmup(state) => state.teams.get_copy(player_id),
+            Round(state) => state.teams.get_copy(player_id),
+            RoundCooldown(state) => state.round_state.teams.get_copy(player_id),
+            EndWinner(state) => state.teams.get_copy(player_id),
+            _ => None,
+        }
+    }
+
     pub fn winner_counts(&self) -> PlayerIdMap<u8> {
         match self {
             CrossyRulesetFST::Round(state) => {
@@ -444,6 +558,18 @@ impl CrossyRulesetFST
             _ => false,
         }
     }
+
+    // For lobby listings and logs.
+    pub fn phase_name(&self) -> &'static str {
+        match self {
+            Lobby{..} => "Lobby",
+            RoundWarmup(_) => "RoundWarmup",
+            Round(_) => "Round",
+            RoundCooldown(_) => "RoundCooldown",
+            EndWinner(_) => "EndWinner",
+            EndAllLeft(_) => "EndAllLeft",
+        }
+    }
 }
 
 enum ResetPositionTarget {
@@ -470,7 +596,7 @@ fn reset_positions(player_states : &mut PlayerIdMap<PlayerState>, target : Reset
     }
 }
 
-fn update_screen_y(mut screen_y : i32, player_states : &PlayerIdMap<PlayerState>, alive_states : &PlayerIdMap<AliveState>) -> i32 {
+pub fn update_screen_y(mut screen_y : i32, player_states : &PlayerIdMap<PlayerState>, alive_states : &PlayerIdMap<AliveState>) -> i32 {
     const SCREEN_Y_BUFFER : i32 = 6;
     for (id, player) in player_states.iter() {
         if let Some(AliveState::Alive) = alive_states.get_copy(id) {
@@ -489,7 +615,24 @@ fn update_screen_y(mut screen_y : i32, player_states : &PlayerIdMap<PlayerState>
     screen_y
 }
 
-fn should_kill(time_us : u32, round_id : u8, map : &Map, player_state : &PlayerState, screen_y : i32, ruleset_fst: &CrossyRulesetFST) -> bool{
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum DeathCause {
+    // Fell off the bottom of the screen
+    Screen,
+    River,
+    Car,
+    // Rode a lillipad off the side
+    LillipadOffscreen,
+}
+
+// How far a lillipad can carry you past the edge of the screen before you die.
+const KILL_OFF_MAP_THRESH : f64 = 2.5;
+
+pub fn lillipad_offscreen(precise_x : f64) -> bool {
+    precise_x < -KILL_OFF_MAP_THRESH || precise_x > (160.0 / 8.0 + KILL_OFF_MAP_THRESH)
+}
+
+pub fn death_cause(time_us : u32, round_id : u8, map : &Map, player_state : &PlayerState, screen_y : i32, ruleset_fst: &CrossyRulesetFST) -> Option<DeathCause> {
     // TODO also check position you are moving to
     //if let Stationary = player_state.move_state {
         match &player_state.pos {
@@ -498,35 +641,32 @@ fn should_kill(time_us : u32, round_id : u8, map : &Map, player_state : &PlayerS
                 const SCREEN_KILL_BUFFER : i32 = 4;
                 if y > screen_y + crate::SCREEN_SIZE + SCREEN_KILL_BUFFER {
                     debug_log!("Killing, off the end of the screen {:?} {:?}", player_state.id, player_state.pos);
-                    return true;
+                    return Some(DeathCause::Screen);
                 }
 
                 let row = map.get_row(round_id, y);
                 if let RowType::River(_) = row.row_type {
                     debug_log!("Killing, walked into river {:?} {:?}", player_state.id, player_state.pos);
-                    return true;
+                    return Some(DeathCause::River);
                 }
 
-                let mut coord_pos_to_check_car_collision = *coord_pos;
+                let coord_pos_to_check_car_collision = player_state.car_check_pos().unwrap_or(*coord_pos);
 
-                // When the player is moving between spots be more generous to player
-                // Check for car collisions at the point they are moving to.
-                if let crate::player::MoveState::Moving(moving_state) = &player_state.move_state {
-                    if let Pos::Coord(moving_to_coord_pos) = moving_state.target {
-                        coord_pos_to_check_car_collision = moving_to_coord_pos;
-                    }
+                // Shields get used up in PlayerState::update_pickups
+                if !player_state.shielded() && map.collides_car(time_us, round_id, coord_pos_to_check_car_collision) {
+                    return Some(DeathCause::Car);
                 }
 
-                if map.collides_car(time_us, round_id, coord_pos_to_check_car_collision) {
-                    return true;
-                }
-
-                false
+                None
             },
             Pos::Lillipad(lillypad_id) => {
                 let precise_pos = map.get_lillipad_screen_x(time_us, lillypad_id, ruleset_fst);
-                const KILL_OFF_MAP_THRESH : f64 = 2.5;
-                precise_pos < -KILL_OFF_MAP_THRESH || precise_pos > (160.0 / 8.0 + KILL_OFF_MAP_THRESH)
+                if lillipad_offscreen(precise_pos) {
+                    Some(DeathCause::LillipadOffscreen)
+                }
+                else {
+                    None
+                }
             },
             _ => {
                 unreachable!()
@@ -535,15 +675,15 @@ fn should_kill(time_us : u32, round_id : u8, map : &Map, player_state : &PlayerS
     //}
 }
 
-fn kill_players(time_us : u32, round_id : u8, alive_states : &mut PlayerIdMap<AliveState>, map : &Map, player_states : &mut PlayerIdMap<PlayerState>, screen_y : i32, ruleset_fst: &CrossyRulesetFST) {
+fn kill_players(time_us : u32, round_state : &mut RoundState, map : &Map, player_states : &PlayerIdMap<PlayerState>, ruleset_fst: &CrossyRulesetFST, rules : &dyn GameRules) {
     for id in player_states.valid_ids() {
-        let alive = alive_states.get_copy(id).unwrap_or(AliveState::NotInGame);
+        let alive = round_state.alive_states.get_copy(id).unwrap_or(AliveState::NotInGame);
         if (alive != AliveState::Alive) {
             continue;
         }
 
-        if should_kill(time_us, round_id, map, player_states.get(id).unwrap(), screen_y, ruleset_fst) {
-            alive_states.set(id, AliveState::Dead);
+        if rules.death_cause(time_us, round_state, map, player_states.get(id).unwrap(), ruleset_fst).is_some() {
+            round_state.alive_states.set(id, AliveState::Dead);
         }
     }
 }
@@ -338,6 +338,7 @@ impl GameState {
             pos,
             move_state: MoveState::Stationary,
             move_cooldown: 0,
+            powerup: None,
         };
 
         new.set_player_state(id, state);
@@ -388,9 +389,34 @@ impl GameState {
             }
         }
 
+        for id in self.player_states.valid_ids() {
+            if self.rules_state.fst.get_player_alive(id) != AliveState::Alive {
+                continue;
+            }
+
+            let player_state = self.player_states.get(id).unwrap();
+            let mut taken_pickup = None;
+            let updated = player_state.update_pickups(self, map, &mut taken_pickup);
+            self.set_player_state(id, updated);
+
+            if let (Some(y), Some(taken_pickups)) = (taken_pickup, self.rules_state.fst.get_taken_pickups_mut()) {
+                taken_pickups.push(y);
+            }
+        }
+
         self.rules_state = self.rules_state.tick(dt_us, self.time_us, &mut self.player_states, map);
     }
 
+    // Each pickup can only be taken once a round.
+    pub fn pickup_taken(&self, y : i32) -> bool {
+        self.rules_state.fst.get_taken_pickups().map(|x| x.contains(&y)).unwrap_or(false)
+    }
+
+    // Nothing can be taken outside of a round.
+    pub fn can_take_pickup(&self, y : i32) -> bool {
+        self.rules_state.fst.get_taken_pickups().map(|x| !x.contains(&y)).unwrap_or(false)
+    }
+
     pub fn space_occupied_with_player(&self, pos : Pos, ignore_id : Option<PlayerId>) -> bool {
         for (_, player) in self.player_states.iter().filter(|(id, _)| Some(*id) != ignore_id) {
             if player.pos == pos {
@@ -449,6 +475,7 @@ mod tests {
                 move_state : MoveState::Stationary,
                 move_cooldown : 0,
                 pos : Pos::new_coord(0, 0),
+                powerup : None,
             }
         ];
 
@@ -478,12 +505,14 @@ mod tests {
                 move_state : MoveState::Stationary,
                 move_cooldown : 0,
                 pos : Pos::new_coord(0, 0),
+                powerup : None,
             },
             PlayerState {
                 id : PlayerId(1),
                 move_state : MoveState::Stationary,
                 move_cooldown : 0,
                 pos : Pos::new_coord(1, 0),
+                powerup : None,
             },
         ];
 
@@ -513,12 +542,14 @@ mod tests {
                 move_state : MoveState::Stationary,
                 move_cooldown : 0,
                 pos : Pos::new_coord(0, 0),
+                powerup : None,
             },
             PlayerState {
                 id : PlayerId(1),
                 move_state : MoveState::Moving(MovingState::new(1, Pos::new_coord(1, 1))),
                 move_cooldown : 0,
                 pos : Pos::new_coord(0, 1),
+                powerup : None,
             },
         ];
 
@@ -547,12 +578,14 @@ mod tests {
                 move_state : MoveState::Stationary,
                 move_cooldown : 0,
                 pos : Pos::new_coord(0, 0),
+                powerup : None,
             },
             PlayerState {
                 id : PlayerId(1),
                 move_state : MoveState::Moving(MovingState::new(1, Pos::new_coord(0, 1))),
                 move_cooldown : 0,
                 pos : Pos::new_coord(1, 1),
+                powerup : None,
             },
         ];
 
@@ -571,4 +604,261 @@ mod tests {
             _ => {},
         }
     }
+    // Ice sliding, rows from the top
+    //   y=6   path
+    //   y=7   icy ....................
+    //   y=8   icy .....X..............
+    //   y=9   icy ....................
+    //   y=10  icy ....................
+    //   y=11  path
+    const ICE_LEVEL : &str = "
+        path 2
+        icy 2 ....................
+        icy 2 .....X..............
+        icy 2 ....................
+        icy 2 ....................
+        path 2
+        ";
+
+    fn make_ice_world(states : Vec<PlayerState>) -> (GameState, Map) {
+        use crate::map::level::Level;
+
+        let map = Map::from_level("ice_test", Level::parse(ICE_LEVEL).unwrap());
+        (make_round_world(states), map)
+    }
+
+    fn make_round_world(states : Vec<PlayerState>) -> GameState {
+        use crate::crossy_ruleset::{CrossyRulesetFST, RoundState};
+
+        let mut world = make_gamestate(states);
+        world.rules_state.config.minimum_players = 1;
+
+        let mut alive_states = PlayerIdMap::new();
+        for id in world.player_states.valid_ids() {
+            alive_states.set(id, AliveState::Alive);
+        }
+
+        world.rules_state.fst = CrossyRulesetFST::Round(RoundState {
+            screen_y : 0,
+            alive_states,
+            win_counts : PlayerIdMap::new(),
+            round_id : 1,
+            finish_order : Vec::new(),
+            teams : PlayerIdMap::new(),
+            round_time_us : 0,
+            taken_pickups : Vec::new(),
+        });
+
+        world
+    }
+
+    fn stationary_at(id : u8, x : i32, y : i32) -> PlayerState {
+        PlayerState {
+            id : PlayerId(id),
+            move_state : MoveState::Stationary,
+            move_cooldown : 0,
+            pos : Pos::new_coord(x, y),
+            powerup : None,
+        }
+    }
+
+    // Press once then let everything settle
+    fn press_and_settle(world : GameState, map : &Map, id : PlayerId, input : Input) -> GameState {
+        let mut inputs = PlayerInputs::default();
+        inputs.set(id, input);
+
+        let mut world = world.simulate(Some(inputs), 16_666, map);
+        for _ in 0..120 {
+            world = world.simulate(None, 16_666, map);
+        }
+
+        world
+    }
+
+    #[test]
+    fn ice_slide_until_block() {
+        let (world, map) = make_ice_world(vec![stationary_at(0, 5, 11)]);
+        let world = press_and_settle(world, &map, PlayerId(0), Input::Up);
+
+        let player = world.get_player(PlayerId(0)).unwrap();
+        assert_eq!(player.move_state, MoveState::Stationary);
+        assert_eq!(player.pos, Pos::new_coord(5, 9));
+    }
+
+    #[test]
+    fn ice_slide_off_onto_path() {
+        let (world, map) = make_ice_world(vec![stationary_at(0, 6, 11)]);
+        let world = press_and_settle(world, &map, PlayerId(0), Input::Up);
+
+        let player = world.get_player(PlayerId(0)).unwrap();
+        assert_eq!(player.pos, Pos::new_coord(6, 6));
+    }
+
+    #[test]
+    fn ice_slide_into_wall() {
+        let (world, map) = make_ice_world(vec![stationary_at(0, 8, 9)]);
+        let world = press_and_settle(world, &map, PlayerId(0), Input::Left);
+
+        let player = world.get_player(PlayerId(0)).unwrap();
+        assert_eq!(player.pos, Pos::new_coord(3, 9));
+    }
+
+    #[test]
+    fn ice_slide_blocked_by_player() {
+        let (world, map) = make_ice_world(vec![
+            stationary_at(0, 8, 9),
+            stationary_at(1, 4, 9),
+        ]);
+        let world = press_and_settle(world, &map, PlayerId(0), Input::Left);
+
+        // Stops next to the other player without pushing them
+        assert_eq!(world.get_player(PlayerId(0)).unwrap().pos, Pos::new_coord(5, 9));
+        assert_eq!(world.get_player(PlayerId(1)).unwrap().pos, Pos::new_coord(4, 9));
+    }
+
+    #[test]
+    fn ice_pushed_player_slides() {
+        let (world, map) = make_ice_world(vec![
+            stationary_at(0, 10, 11),
+            stationary_at(1, 10, 10),
+        ]);
+        let world = press_and_settle(world, &map, PlayerId(0), Input::Up);
+
+        // Pushed off the top of the ice, the pusher stops where they were
+        assert_eq!(world.get_player(PlayerId(1)).unwrap().pos, Pos::new_coord(10, 6));
+        assert_eq!(world.get_player(PlayerId(0)).unwrap().pos, Pos::new_coord(10, 10));
+    }
+
+    // Every generated row is a path with a pickup on it.
+    fn pickup_map() -> Map {
+        use crate::map::profile::{DifficultyRamp, MapGenProfile};
+
+        Map::new_with_profile("pickup_test", MapGenProfile {
+            feature_chance : 0.0,
+            bush_chance : 0.0,
+            pickup_chance : 1.0,
+            ramp : DifficultyRamp::NONE,
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn pickup_taken_once() {
+        let map = pickup_map();
+        let pickup = map.get_pickup(1, 10).unwrap();
+        let x = pickup.x;
+        let other_x = if (x < 10) { x + 1 } else { x - 1 };
+
+        let world = make_round_world(vec![
+            stationary_at(0, x, 11),
+            stationary_at(1, other_x, 10),
+        ]);
+
+        let world = press_and_settle(world, &map, PlayerId(0), Input::Up);
+        let player = world.get_player(PlayerId(0)).unwrap();
+        assert_eq!(player.powerup, Some(crate::player::Powerup::from_pickup(pickup.kind)));
+        assert!(world.pickup_taken(10));
+
+        // Someone else walking over it afterwards gets nothing
+        let world = press_and_settle(world, &map, PlayerId(0), Input::Up);
+        let dir = if (x < 10) { Input::Left } else { Input::Right };
+        let world = press_and_settle(world, &map, PlayerId(1), dir);
+        let player = world.get_player(PlayerId(1)).unwrap();
+        assert_eq!(player.pos, Pos::new_coord(x, 10));
+        assert_eq!(player.powerup, None);
+    }
+
+    #[test]
+    fn pickup_stays_taken_after_taker_leaves() {
+        let map = pickup_map();
+        let x = map.get_pickup(1, 10).unwrap().x;
+
+        let world = make_round_world(vec![
+            stationary_at(0, x, 11),
+            stationary_at(1, x, 12),
+        ]);
+        let world = press_and_settle(world, &map, PlayerId(0), Input::Up);
+        assert!(world.pickup_taken(10));
+
+        let world = world.remove_player(PlayerId(0));
+        assert!(world.pickup_taken(10));
+
+        // Walking onto the spot they took it from gets nothing
+        let world = press_and_settle(world, &map, PlayerId(1), Input::Up);
+        let world = press_and_settle(world, &map, PlayerId(1), Input::Up);
+        let player = world.get_player(PlayerId(1)).unwrap();
+        assert_eq!(player.pos, Pos::new_coord(x, 10));
+        assert_eq!(player.powerup, None);
+    }
+
+    #[test]
+    fn speed_boost_shortens_moves() {
+        use crate::player::{Powerup, BOOSTED_MOVE_DUR};
+
+        let mut player = stationary_at(0, 5, 11);
+        player.powerup = Some(Powerup::SpeedBoost { remaining_us : 1_000_000 });
+        let (world, map) = make_ice_world(vec![player]);
+
+        let mut inputs = PlayerInputs::default();
+        inputs.set(PlayerId(0), Input::Up);
+        let world = world.simulate(Some(inputs), 16_666, &map);
+
+        match &world.get_player(PlayerId(0)).unwrap().move_state {
+            MoveState::Moving(state) => {
+                assert_eq!(state.remaining_us, BOOSTED_MOVE_DUR);
+                assert_eq!(state.duration_us, BOOSTED_MOVE_DUR);
+            },
+            _ => panic!("Expected to be moving"),
+        }
+    }
+
+    #[test]
+    fn super_push_two_tiles() {
+        use crate::player::Powerup;
+
+        let mut pusher = stationary_at(0, 8, 11);
+        pusher.powerup = Some(Powerup::SuperPush { remaining_us : 1_000_000 });
+        let (world, map) = make_ice_world(vec![pusher, stationary_at(1, 9, 11)]);
+        let world = press_and_settle(world, &map, PlayerId(0), Input::Right);
+
+        assert_eq!(world.get_player(PlayerId(0)).unwrap().pos, Pos::new_coord(9, 11));
+        assert_eq!(world.get_player(PlayerId(1)).unwrap().pos, Pos::new_coord(11, 11));
+    }
+
+    #[test]
+    fn shield_survives_one_car() {
+        use crate::map::level::Level;
+        use crate::player::Powerup;
+
+        const ROAD_LEVEL : &str = "
+            path 2
+            road > gaps=1.0
+            path 2
+            ";
+
+        let map = Map::from_level("shield_test", Level::parse(ROAD_LEVEL).unwrap());
+        let pos = CoordPos { x : 10, y : 10 };
+
+        let mut player = stationary_at(0, pos.x, pos.y);
+        player.powerup = Some(Powerup::Shield);
+        let mut world = make_round_world(vec![player]);
+
+        // Wait for the first car
+        while !map.collides_car(world.time_us + 16_666, 1, pos) {
+            world.time_us += 16_666;
+        }
+
+        world = world.simulate(None, 16_666, &map);
+        assert_eq!(world.rules_state.fst.get_player_alive(PlayerId(0)), AliveState::Alive);
+        assert!(matches!(world.get_player(PlayerId(0)).unwrap().powerup, Some(Powerup::ShieldBroken { .. })));
+
+        // Standing still until the car comes round again
+        let mut frames = 0;
+        while world.rules_state.fst.get_player_alive(PlayerId(0)) == AliveState::Alive {
+            world = world.simulate(None, 16_666, &map);
+            frames += 1;
+            assert!(frames < 1200, "Never hit by the second car");
+        }
+        assert_eq!(world.get_player(PlayerId(0)).unwrap().powerup, None);
+    }
 }
\ No newline at end of file
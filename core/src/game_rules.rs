@@ -0,0 +1,449 @@
+use serde::{Deserialize, Serialize};
+
+use crate::crossy_ruleset::{self, AliveState, CrossyRulesetFST, DeathCause, GameConfig, RoundState};
+use crate::game::{PlayerId, Pos};
+use crate::map::Map;
+use crate::map::profile::MapGenProfile;
+use crate::player::PlayerState;
+use crate::player_id_map::PlayerIdMap;
+
+// What decides a round and a match.
+// The lobby -> warmup -> round -> cooldown -> end flow in CrossyRulesetFST is shared by every mode,
+// it calls into these at each step.
+//
+// Rules are stateless, anything a mode needs to remember goes in RoundState so it gets
+// rolled back with everything else.
+pub trait GameRules {
+    // Every client builds the map from the config, so this can only depend on the profile.
+    fn map_profile(&self, profile : MapGenProfile) -> MapGenProfile {
+        profile
+    }
+
+    // Put anyone without a team on one, called as the lobby ends and again before each round
+    // for people who joined late. Free for all modes leave teams empty.
+    fn update_teams(&self, _teams : &mut PlayerIdMap<u8>, _player_states : &PlayerIdMap<PlayerState>) {
+    }
+
+    // Warmup is over and everyone is on the start line.
+    fn start_round(&self, _round_state : &mut RoundState, _player_states : &PlayerIdMap<PlayerState>) {
+    }
+
+    // Bottom of the screen, falling off it kills.
+    fn update_screen_y(&self, round_state : &RoundState, player_states : &PlayerIdMap<PlayerState>) -> i32 {
+        crossy_ruleset::update_screen_y(round_state.screen_y, player_states, &round_state.alive_states)
+    }
+
+    fn death_cause(&self, time_us : u32, round_state : &RoundState, map : &Map, player_state : &PlayerState, ruleset_fst : &CrossyRulesetFST) -> Option<DeathCause> {
+        crossy_ruleset::death_cause(time_us, round_state.round_id, map, player_state, round_state.screen_y, ruleset_fst)
+    }
+
+    // Every frame of a round, after anyone who died has been killed.
+    fn tick_round(&self, _round_state : &mut RoundState, _player_states : &PlayerIdMap<PlayerState>) {
+    }
+
+    // Checked every frame of a round, true moves on to the cooldown.
+    fn round_over(&self, round_state : &RoundState, config : &GameConfig) -> bool;
+
+    // End of the cooldown, add this round's points.
+    fn score_round(&self, round_state : &RoundState, win_counts : &mut PlayerIdMap<u8>);
+
+    // After scoring, Some ends the match.
+    fn match_winner(&self, win_counts : &PlayerIdMap<u8>, config : &GameConfig) -> Option<PlayerId> {
+        most_wins_reaching(win_counts, config.required_win_count)
+    }
+}
+
+fn most_wins_reaching(win_counts : &PlayerIdMap<u8>, threshold : u8) -> Option<PlayerId> {
+    // Ties go to the lowest id so every client agrees.
+    win_counts.iter()
+        .filter(|(_, x)| **x >= threshold)
+        .max_by_key(|(id, x)| (**x, std::cmp::Reverse(id.0)))
+        .map(|(id, _)| id)
+}
+
+// Named so GameConfig stays small, same as MapGenPreset.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
+pub enum GameMode {
+    #[default]
+    LastFrogStanding,
+    Race,
+    Teams,
+}
+
+pub const GAME_MODE_NAMES : [&str; 3] = ["last_frog_standing", "race", "teams"];
+
+impl GameMode {
+    pub fn from_name(name : &str) -> Option<Self> {
+        match name {
+            "last_frog_standing" => Some(GameMode::LastFrogStanding),
+            "race" => Some(GameMode::Race),
+            "teams" => Some(GameMode::Teams),
+            _ => None,
+        }
+    }
+
+    pub fn rules(self) -> &'static dyn GameRules {
+        match self {
+            GameMode::LastFrogStanding => &LastFrogStanding,
+            GameMode::Race => &Race,
+            GameMode::Teams => &Teams,
+        }
+    }
+}
+
+// The original mode, the round ends when too few are alive and the survivor gets a point.
+pub struct LastFrogStanding;
+
+impl GameRules for LastFrogStanding {
+    fn round_over(&self, round_state : &RoundState, config : &GameConfig) -> bool {
+        let alive_player_count = round_state.alive_states.iter().filter(|(_, x)| **x == AliveState::Alive).count();
+        alive_player_count < config.minimum_players as usize
+    }
+
+    fn score_round(&self, round_state : &RoundState, win_counts : &mut PlayerIdMap<u8>) {
+        // We know up to one person is alive here
+        let winner = round_state.alive_states.iter().filter(|(_, x)| **x == AliveState::Alive).map(|(id, _)| id).next();
+        if let Some(winner_id) = winner {
+            let new_count = win_counts.get(winner_id).copied().unwrap_or(0) + 1;
+            debug_log!("Round winner player={:?} count={}", winner_id, new_count);
+            win_counts.set(winner_id, new_count);
+        }
+    }
+}
+
+// Everyone runs for a finish line, the round is over once nobody is left running.
+// The screen still scrolls with the leaders but gives stragglers more room.
+pub struct Race;
+
+// 80 rows up from the start line.
+pub const RACE_FINISH_Y : i32 = 16 - 80;
+// For first, second and third, match_winner wants required_win_count firsts.
+pub const RACE_POINTS : [u8; 3] = [3, 2, 1];
+// Extra rows below the usual screen kill.
+const RACE_SCREEN_SLACK : i32 = 8;
+// Where the screen sits once someone has finished, the same as following a player on the line.
+const RACE_FINISHED_SCREEN_BUFFER : i32 = 6;
+
+impl Race {
+    fn finished(round_state : &RoundState, id : PlayerId) -> bool {
+        round_state.finish_order.contains(&id)
+    }
+
+    fn racing(round_state : &RoundState, id : PlayerId) -> bool {
+        round_state.alive_states.get_copy(id) == Some(AliveState::Alive) && !Self::finished(round_state, id)
+    }
+}
+
+impl GameRules for Race {
+    fn map_profile(&self, profile : MapGenProfile) -> MapGenProfile {
+        MapGenProfile {
+            finish_y : Some(RACE_FINISH_Y),
+            ..profile
+        }
+    }
+
+    fn update_screen_y(&self, round_state : &RoundState, player_states : &PlayerIdMap<PlayerState>) -> i32 {
+        // People who have finished hold the screen at the line rather than
+        // dragging it on up over everyone else.
+        let mut racing = round_state.alive_states.clone();
+        for id in &round_state.finish_order {
+            racing.set(*id, AliveState::NotInGame);
+        }
+
+        let screen_y = crossy_ruleset::update_screen_y(round_state.screen_y, player_states, &racing);
+        if (round_state.finish_order.is_empty()) {
+            screen_y
+        }
+        else {
+            screen_y.min(RACE_FINISH_Y - RACE_FINISHED_SCREEN_BUFFER)
+        }
+    }
+
+    fn death_cause(&self, time_us : u32, round_state : &RoundState, map : &Map, player_state : &PlayerState, ruleset_fst : &CrossyRulesetFST) -> Option<DeathCause> {
+        if (Self::finished(round_state, player_state.id)) {
+            return None;
+        }
+
+        crossy_ruleset::death_cause(time_us, round_state.round_id, map, player_state, round_state.screen_y + RACE_SCREEN_SLACK, ruleset_fst)
+    }
+
+    fn tick_round(&self, round_state : &mut RoundState, player_states : &PlayerIdMap<PlayerState>) {
+        // Crossing on the same frame goes to the lowest id.
+        for (id, player) in player_states.iter() {
+            if (Self::racing(round_state, id) && player.pos.get_y_grid() <= RACE_FINISH_Y) {
+                debug_log!("Finished player={:?} place={}", id, round_state.finish_order.len() + 1);
+                round_state.finish_order.push(id);
+            }
+        }
+    }
+
+    fn round_over(&self, round_state : &RoundState, _config : &GameConfig) -> bool {
+        !round_state.alive_states.iter().any(|(id, _)| Self::racing(round_state, id))
+    }
+
+    fn score_round(&self, round_state : &RoundState, win_counts : &mut PlayerIdMap<u8>) {
+        for (id, points) in round_state.finish_order.iter().zip(RACE_POINTS) {
+            let new_count = win_counts.get(*id).copied().unwrap_or(0).saturating_add(points);
+            win_counts.set(*id, new_count);
+        }
+    }
+
+    fn match_winner(&self, win_counts : &PlayerIdMap<u8>, config : &GameConfig) -> Option<PlayerId> {
+        most_wins_reaching(win_counts, config.required_win_count.saturating_mul(RACE_POINTS[0]))
+    }
+}
+
+// Last frog standing two against two (or however many turn up).
+// A team takes the round if any of them are left and everyone on it goes up a win together.
+pub struct Teams;
+
+pub const TEAM_COUNT : usize = 2;
+// Raft seats, the left half goes on team 0.
+const RAFT_WIDTH : u8 = 4;
+
+impl Teams {
+    fn team_sizes(teams : &PlayerIdMap<u8>, player_states : &PlayerIdMap<PlayerState>) -> [usize; TEAM_COUNT] {
+        let mut sizes = [0; TEAM_COUNT];
+        for (id, _) in player_states.iter() {
+            if let Some(team) = teams.get_copy(id) {
+                sizes[team as usize] += 1;
+            }
+        }
+        sizes
+    }
+
+    fn alive_teams(round_state : &RoundState) -> Vec<u8> {
+        let mut alive = Vec::new();
+        for (id, x) in round_state.alive_states.iter() {
+            if let (AliveState::Alive, Some(team)) = (*x, round_state.teams.get_copy(id)) {
+                if (!alive.contains(&team)) {
+                    alive.push(team);
+                }
+            }
+        }
+        alive
+    }
+}
+
+impl GameRules for Teams {
+    fn update_teams(&self, teams : &mut PlayerIdMap<u8>, player_states : &PlayerIdMap<PlayerState>) {
+        let picking = teams.count_populated() == 0;
+
+        // Out of the lobby, you're on the team for the side of the raft you're standing on.
+        let mut unassigned = Vec::new();
+        for (id, player) in player_states.iter() {
+            if (teams.contains(id)) {
+                continue;
+            }
+
+            match &player.pos {
+                Pos::Lillipad(lillipad) if picking => teams.set(id, if (lillipad.id < RAFT_WIDTH / 2) { 0 } else { 1 }),
+                _ => unassigned.push(id),
+            }
+        }
+
+        // Everyone piled onto one side, split them up.
+        let sizes = Self::team_sizes(teams, player_states);
+        if (picking && sizes.iter().filter(|x| **x > 0).count() == 1 && player_states.count_populated() > 1) {
+            *teams = PlayerIdMap::new();
+            unassigned = player_states.valid_ids();
+        }
+
+        // Late joiners and anyone who skipped the lobby even things up, ties to the lower team.
+        for id in unassigned {
+            let sizes = Self::team_sizes(teams, player_states);
+            let team = (0..TEAM_COUNT).min_by_key(|x| sizes[*x]).unwrap();
+            teams.set(id, team as u8);
+        }
+    }
+
+    fn round_over(&self, round_state : &RoundState, _config : &GameConfig) -> bool {
+        Self::alive_teams(round_state).len() < 2
+    }
+
+    fn score_round(&self, round_state : &RoundState, win_counts : &mut PlayerIdMap<u8>) {
+        let alive = Self::alive_teams(round_state);
+        let [winning_team] = alive[..] else {
+            return;
+        };
+
+        // Wins are shared so the team is on its best member's count, people who joined late catch up.
+        let members : Vec<PlayerId> = round_state.teams.iter().filter(|(_, x)| **x == winning_team).map(|(id, _)| id).collect();
+        let new_count = members.iter().filter_map(|id| win_counts.get_copy(*id)).max().unwrap_or(0) + 1;
+        debug_log!("Round winner team={} count={}", winning_team, new_count);
+        for id in members {
+            win_counts.set(id, new_count);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_state(alive : &[(u8, AliveState)]) -> RoundState {
+        let mut alive_states = PlayerIdMap::new();
+        for (id, state) in alive {
+            alive_states.set(PlayerId(*id), *state);
+        }
+
+        RoundState {
+            screen_y : 0,
+            alive_states,
+            win_counts : PlayerIdMap::new(),
+            round_id : 1,
+            finish_order : Vec::new(),
+            teams : PlayerIdMap::new(),
+            round_time_us : 0,
+            taken_pickups : Vec::new(),
+        }
+    }
+
+    #[test]
+    fn last_frog_standing() {
+        let rules = GameMode::LastFrogStanding.rules();
+        let config = GameConfig::default();
+
+        let state = round_state(&[(0, AliveState::Alive), (1, AliveState::Alive), (2, AliveState::Dead)]);
+        assert!(!rules.round_over(&state, &config));
+
+        let state = round_state(&[(0, AliveState::Dead), (1, AliveState::Alive), (2, AliveState::Dead)]);
+        assert!(rules.round_over(&state, &config));
+
+        let mut win_counts = PlayerIdMap::new();
+        win_counts.set(PlayerId(1), 2);
+        rules.score_round(&state, &mut win_counts);
+        assert_eq!(win_counts.get_copy(PlayerId(1)), Some(3));
+        assert_eq!(rules.match_winner(&win_counts, &config), Some(PlayerId(1)));
+
+        // Everyone died, nobody scores
+        let state = round_state(&[(0, AliveState::Dead), (1, AliveState::Dead)]);
+        rules.score_round(&state, &mut win_counts);
+        assert_eq!(win_counts.get_copy(PlayerId(1)), Some(3));
+        assert_eq!(win_counts.get_copy(PlayerId(0)), None);
+    }
+
+    #[test]
+    fn match_winner_ties() {
+        let rules = GameMode::LastFrogStanding.rules();
+        let config = GameConfig::default();
+
+        let mut win_counts = PlayerIdMap::new();
+        win_counts.set(PlayerId(0), 2);
+        win_counts.set(PlayerId(3), 2);
+        assert_eq!(rules.match_winner(&win_counts, &config), None);
+
+        win_counts.set(PlayerId(0), 3);
+        win_counts.set(PlayerId(3), 3);
+        win_counts.set(PlayerId(5), 1);
+        assert_eq!(rules.match_winner(&win_counts, &config), Some(PlayerId(0)));
+    }
+
+    #[test]
+    fn race() {
+        let rules = GameMode::Race.rules();
+        let config = GameConfig::default();
+
+        let mut player_states = PlayerIdMap::new();
+        for (id, y) in [(0, RACE_FINISH_Y + 1), (1, RACE_FINISH_Y), (2, RACE_FINISH_Y - 1), (3, RACE_FINISH_Y)] {
+            player_states.set(PlayerId(id), PlayerState {
+                id : PlayerId(id),
+                move_state : crate::player::MoveState::Stationary,
+                move_cooldown : 0,
+                pos : crate::game::Pos::new_coord(10, y),
+                powerup : None,
+            });
+        }
+
+        let mut state = round_state(&[(0, AliveState::Alive), (1, AliveState::Alive), (2, AliveState::Alive), (3, AliveState::Dead)]);
+        rules.tick_round(&mut state, &player_states);
+        assert_eq!(state.finish_order, vec![PlayerId(1), PlayerId(2)]);
+
+        // 0 is still going
+        assert!(!rules.round_over(&state, &config));
+        state.alive_states.set(PlayerId(0), AliveState::Dead);
+        assert!(rules.round_over(&state, &config));
+
+        let mut win_counts = PlayerIdMap::new();
+        win_counts.set(PlayerId(2), 7);
+        rules.score_round(&state, &mut win_counts);
+        assert_eq!(win_counts.get_copy(PlayerId(1)), Some(3));
+        assert_eq!(win_counts.get_copy(PlayerId(2)), Some(9));
+        assert_eq!(win_counts.get_copy(PlayerId(0)), None);
+        assert_eq!(rules.match_winner(&win_counts, &config), Some(PlayerId(2)));
+    }
+
+    fn player_at(id : u8, pos : Pos) -> PlayerState {
+        PlayerState {
+            id : PlayerId(id),
+            move_state : crate::player::MoveState::Stationary,
+            move_cooldown : 0,
+            pos,
+            powerup : None,
+        }
+    }
+
+    fn on_raft(seat : u8) -> Pos {
+        Pos::Lillipad(crate::game::LillipadId { id : seat, y : 7, round_id : 0 })
+    }
+
+    #[test]
+    fn teams_from_raft() {
+        let rules = GameMode::Teams.rules();
+
+        let mut player_states = PlayerIdMap::new();
+        player_states.set(PlayerId(0), player_at(0, on_raft(3)));
+        player_states.set(PlayerId(1), player_at(1, on_raft(0)));
+        player_states.set(PlayerId(2), player_at(2, on_raft(2)));
+        player_states.set(PlayerId(3), player_at(3, on_raft(1)));
+
+        let mut teams = PlayerIdMap::new();
+        rules.update_teams(&mut teams, &player_states);
+        assert_eq!(teams.get_copy(PlayerId(0)), Some(1));
+        assert_eq!(teams.get_copy(PlayerId(1)), Some(0));
+        assert_eq!(teams.get_copy(PlayerId(2)), Some(1));
+        assert_eq!(teams.get_copy(PlayerId(3)), Some(0));
+
+        // Late joiner goes on the smaller team
+        player_states.remove(PlayerId(3));
+        player_states.set(PlayerId(4), player_at(4, Pos::new_coord(10, 16)));
+        rules.update_teams(&mut teams, &player_states);
+        assert_eq!(teams.get_copy(PlayerId(4)), Some(0));
+
+        // Everyone on the same side gets split
+        let mut player_states = PlayerIdMap::new();
+        player_states.set(PlayerId(0), player_at(0, on_raft(0)));
+        player_states.set(PlayerId(1), player_at(1, on_raft(1)));
+        let mut teams = PlayerIdMap::new();
+        rules.update_teams(&mut teams, &player_states);
+        assert_eq!(teams.get_copy(PlayerId(0)), Some(0));
+        assert_eq!(teams.get_copy(PlayerId(1)), Some(1));
+    }
+
+    #[test]
+    fn teams_share_wins() {
+        let rules = GameMode::Teams.rules();
+        let config = GameConfig::default();
+
+        let mut state = round_state(&[(0, AliveState::Dead), (1, AliveState::Alive), (2, AliveState::Dead), (3, AliveState::Alive)]);
+        for (id, team) in [(0, 0), (1, 1), (2, 0), (3, 1)] {
+            state.teams.set(PlayerId(id), team);
+        }
+        assert!(rules.round_over(&state, &config));
+
+        state.alive_states.set(PlayerId(0), AliveState::Alive);
+        assert!(!rules.round_over(&state, &config));
+
+        state.alive_states.set(PlayerId(0), AliveState::Dead);
+        state.alive_states.set(PlayerId(1), AliveState::Dead);
+
+        let mut win_counts = PlayerIdMap::new();
+        win_counts.set(PlayerId(1), 2);
+        win_counts.set(PlayerId(3), 1);
+        rules.score_round(&state, &mut win_counts);
+        assert_eq!(win_counts.get_copy(PlayerId(1)), Some(3));
+        assert_eq!(win_counts.get_copy(PlayerId(3)), Some(3));
+        assert_eq!(win_counts.get_copy(PlayerId(0)), None);
+        assert_eq!(rules.match_winner(&win_counts, &config), Some(PlayerId(1)));
+    }
+}
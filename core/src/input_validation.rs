@@ -0,0 +1,250 @@
+use std::collections::{BTreeMap, VecDeque};
+
+use serde::{Deserialize, Serialize};
+
+use crate::Input;
+
+// Server side limits on what a client may send.
+//
+// Without an age limit a client can backdate an input anywhere in the timeline buffer and rewrite
+// its own past, eg step out of the way of a car that already hit it.
+// max_strikes violations within STRIKE_WINDOW_FRAMES gets the client kicked, 0 never kicks.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct InputLimits {
+    pub max_input_age_frames : u32,
+    pub max_input_lead_frames : u32,
+    pub max_inputs_per_second : u32,
+    pub max_strikes : u32,
+}
+
+impl Default for InputLimits {
+    fn default() -> Self {
+        Self {
+            // Half a second, well past any latency we can play with.
+            max_input_age_frames : 30,
+            max_input_lead_frames : 30,
+            // Moves take 7 frames so nobody can do much more than 8 a second.
+            max_inputs_per_second : 20,
+            max_strikes : 10,
+        }
+    }
+}
+
+const FRAMES_PER_SECOND : u32 = 60;
+const STRIKE_WINDOW_FRAMES : u32 = 10 * FRAMES_PER_SECOND;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum InputViolation {
+    // Further in the past than max_input_age_frames.
+    TooOld,
+    // Further ahead of the server than max_input_lead_frames.
+    TooFarAhead,
+    // A second input for a frame the client already sent one for, empty or not.
+    Duplicate,
+    RateLimited,
+    // Older than a frame the client has already sent, clients send every frame in order.
+    OutOfOrder,
+    // The player was still partway through their own move, no real client would send it.
+    MidMove,
+}
+
+impl std::fmt::Display for InputViolation {
+    fn fmt(&self, f : &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            InputViolation::TooOld => "input too old",
+            InputViolation::TooFarAhead => "input too far ahead",
+            InputViolation::Duplicate => "duplicate input for frame",
+            InputViolation::RateLimited => "too many inputs",
+            InputViolation::OutOfOrder => "input out of order",
+            InputViolation::MidMove => "input while moving",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+// Per client, all frame ids are server frames.
+#[derive(Debug, Default)]
+pub struct InputValidator {
+    // Latest frame the client has reported, anything at or before it is a resend.
+    last_frame_id : u32,
+    // Inputs that were ahead of the server, the server requeues them and they are checked when due.
+    pending_inputs : BTreeMap<u32, Input>,
+    // Server frame each recent input arrived on.
+    received_frame_ids : VecDeque<u32>,
+    strike_frame_ids : VecDeque<u32>,
+}
+
+impl InputValidator {
+    // Every frame the client reports is recorded, empty inputs included, so a frame can't be filled
+    // in later. Inputs ahead of the server are let through and come back round once the server
+    // catches up, they are checked properly then.
+    // mid_move is whether the player is partway through their own move going into frame_id.
+    pub fn check(&mut self, limits : &InputLimits, frame_id : u32, input : Input, top_frame_id : u32, mid_move : bool) -> Result<(), InputViolation> {
+        let result = self.check_inner(limits, frame_id, input, top_frame_id, mid_move);
+
+        // Empty inputs are harmless, only count real ones against the client.
+        if (result.is_err() && input != Input::None) {
+            self.strike_frame_ids.push_back(top_frame_id);
+        }
+
+        while let Some(x) = self.strike_frame_ids.front() {
+            if (x + STRIKE_WINDOW_FRAMES < top_frame_id) {
+                self.strike_frame_ids.pop_front();
+            }
+            else {
+                break;
+            }
+        }
+
+        result
+    }
+
+    fn check_inner(&mut self, limits : &InputLimits, frame_id : u32, input : Input, top_frame_id : u32, mid_move : bool) -> Result<(), InputViolation> {
+        let oldest_frame_id = top_frame_id.saturating_sub(limits.max_input_age_frames);
+        self.pending_inputs = self.pending_inputs.split_off(&oldest_frame_id);
+
+        // Frame 0 has no previous state to resimulate from.
+        if (frame_id == 0 || frame_id + limits.max_input_age_frames < top_frame_id) {
+            return Err(InputViolation::TooOld);
+        }
+
+        if (frame_id > top_frame_id + limits.max_input_lead_frames) {
+            return Err(InputViolation::TooFarAhead);
+        }
+
+        let requeued = frame_id <= top_frame_id && self.pending_inputs.get(&frame_id) == Some(&input);
+        if (requeued) {
+            self.pending_inputs.remove(&frame_id);
+        }
+        else {
+            if (frame_id == self.last_frame_id) {
+                return Err(InputViolation::Duplicate);
+            }
+
+            if (frame_id < self.last_frame_id) {
+                return Err(InputViolation::OutOfOrder);
+            }
+
+            self.last_frame_id = frame_id;
+
+            if (input == Input::None) {
+                return Ok(());
+            }
+
+            if (frame_id > top_frame_id) {
+                self.pending_inputs.insert(frame_id, input);
+                return Ok(());
+            }
+        }
+
+        if (mid_move) {
+            return Err(InputViolation::MidMove);
+        }
+
+        while let Some(x) = self.received_frame_ids.front() {
+            if (x + FRAMES_PER_SECOND <= top_frame_id) {
+                self.received_frame_ids.pop_front();
+            }
+            else {
+                break;
+            }
+        }
+
+        if (self.received_frame_ids.len() as u32 >= limits.max_inputs_per_second) {
+            return Err(InputViolation::RateLimited);
+        }
+
+        self.received_frame_ids.push_back(top_frame_id);
+        Ok(())
+    }
+
+    pub fn strikes(&self) -> u32 {
+        self.strike_frame_ids.len() as u32
+    }
+
+    pub fn should_kick(&self, limits : &InputLimits) -> bool {
+        limits.max_strikes > 0 && self.strikes() >= limits.max_strikes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn age_and_lead() {
+        let limits = InputLimits::default();
+        let mut validator = InputValidator::default();
+
+        assert_eq!(validator.check(&limits, 70, Input::Up, 100, false), Ok(()));
+        assert_eq!(validator.check(&limits, 90, Input::Up, 100, false), Ok(()));
+        assert_eq!(validator.check(&limits, 69, Input::Up, 100, false), Err(InputViolation::TooOld));
+        assert_eq!(validator.check(&limits, 0, Input::Up, 10, false), Err(InputViolation::TooOld));
+        assert_eq!(validator.check(&limits, 131, Input::Up, 100, false), Err(InputViolation::TooFarAhead));
+        assert_eq!(validator.strikes(), 3);
+
+        // Future inputs get checked again when they come due
+        assert_eq!(validator.check(&limits, 110, Input::Up, 100, false), Ok(()));
+        assert_eq!(validator.check(&limits, 110, Input::Up, 110, false), Ok(()));
+        assert_eq!(validator.check(&limits, 110, Input::Down, 111, false), Err(InputViolation::Duplicate));
+
+        // Bad empty inputs are dropped without a strike
+        assert_eq!(validator.check(&limits, 10, Input::None, 111, false), Err(InputViolation::TooOld));
+        assert_eq!(validator.strikes(), 4);
+    }
+
+    #[test]
+    fn rate_limit_and_kick() {
+        let limits = InputLimits {
+            max_inputs_per_second : 5,
+            max_strikes : 3,
+            ..Default::default()
+        };
+        let mut validator = InputValidator::default();
+
+        for i in 0..5 {
+            assert_eq!(validator.check(&limits, 100 + i, Input::Left, 100 + i, false), Ok(()));
+        }
+        assert_eq!(validator.check(&limits, 105, Input::Left, 105, false), Err(InputViolation::RateLimited));
+
+        // A second on we are fine again
+        assert_eq!(validator.check(&limits, 160, Input::Left, 160, false), Ok(()));
+        assert!(!validator.should_kick(&limits));
+
+        assert_eq!(validator.check(&limits, 160, Input::Right, 161, false), Err(InputViolation::Duplicate));
+        assert_eq!(validator.check(&limits, 160, Input::Right, 162, false), Err(InputViolation::Duplicate));
+        assert!(validator.should_kick(&limits));
+
+        // Strikes wear off
+        assert_eq!(validator.check(&limits, 1000, Input::Left, 1000, false), Ok(()));
+        assert_eq!(validator.strikes(), 0);
+        assert!(!validator.should_kick(&InputLimits { max_strikes : 0, ..limits }));
+    }
+
+    #[test]
+    fn frames_in_order() {
+        let limits = InputLimits::default();
+        let mut validator = InputValidator::default();
+
+        // Reporting nothing for a frame then filling it in later
+        assert_eq!(validator.check(&limits, 100, Input::None, 100, false), Ok(()));
+        assert_eq!(validator.check(&limits, 100, Input::Up, 102, false), Err(InputViolation::Duplicate));
+
+        assert_eq!(validator.check(&limits, 105, Input::None, 105, false), Ok(()));
+        assert_eq!(validator.check(&limits, 104, Input::Up, 105, false), Err(InputViolation::OutOfOrder));
+        assert_eq!(validator.check(&limits, 103, Input::None, 105, false), Err(InputViolation::OutOfOrder));
+        assert_eq!(validator.strikes(), 2);
+
+        assert_eq!(validator.check(&limits, 106, Input::Left, 106, true), Err(InputViolation::MidMove));
+        assert_eq!(validator.check(&limits, 107, Input::None, 107, true), Ok(()));
+        assert_eq!(validator.check(&limits, 108, Input::Left, 108, false), Ok(()));
+
+        // Future inputs only come back round unchanged
+        assert_eq!(validator.check(&limits, 115, Input::Up, 110, false), Ok(()));
+        assert_eq!(validator.check(&limits, 115, Input::Down, 115, false), Err(InputViolation::Duplicate));
+        assert_eq!(validator.check(&limits, 115, Input::Up, 115, true), Err(InputViolation::MidMove));
+        assert_eq!(validator.check(&limits, 115, Input::Up, 116, false), Err(InputViolation::Duplicate));
+        assert_eq!(validator.strikes(), 6);
+    }
+}
@@ -5,9 +5,13 @@ use serde::{Deserialize, Serialize};
 use std::fmt::Debug;
 
 use crate::game::Input;
-use crate::timeline::RemoteTickState;
+use crate::timeline::{RemoteTickState, Timeline};
 use crate::player_id_map::PlayerIdMap;
 use crate::crossy_ruleset::RulesState;
+use crate::input_validation::InputViolation;
+
+pub mod wire;
+pub mod delta;
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub enum CrossyMessage {
@@ -17,6 +21,8 @@ pub enum CrossyMessage {
     ClientTick(Vec<ClientTick>),
     ClientDrop(),
     LindenServerTick(LindenServerTick),
+    LindenServerTickDelta(delta::LindenServerTickDelta),
+    ServerTickAck(u32),
 
     TimeRequestPacket(TimeRequestPacket),
     TimeRequestIntermediate(TimeRequestIntermediate),
@@ -41,6 +47,8 @@ pub struct InitServerResponse {
     pub player_count: u8,
     pub seed: u32,
     pub player_id: crate::game::PlayerId,
+    #[serde(default)]
+    pub protocol_version: u8,
 }
 
 #[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
@@ -50,7 +58,7 @@ pub struct ServerDescription {
 }
 
 pub const INIT_MESSAGE: &[u8; 4] = b"helo";
-pub const CURRENT_VERSION: u8 = 1;
+pub const CURRENT_VERSION: u8 = wire::PROTOCOL_VERSION;
 
 impl Default for ClientHello {
     fn default() -> Self {
@@ -62,8 +70,21 @@ impl Default for ClientHello {
 }
 
 impl ClientHello {
-    pub fn check(&self, required_version: u8) -> bool {
-        self.header == *INIT_MESSAGE && self.version >= required_version
+    pub fn new(version: u8) -> Self {
+        ClientHello {
+            header: *INIT_MESSAGE,
+            version,
+        }
+    }
+
+    pub fn version(&self) -> u8 {
+        self.version
+    }
+
+    // Newer clients aren't understood by older servers and the other way round,
+    // only versions we have an encoding for are accepted.
+    pub fn check(&self) -> bool {
+        self.header == *INIT_MESSAGE && wire::version_supported(self.version)
     }
 }
 
@@ -81,6 +102,25 @@ pub struct LindenServerTick {
     pub delta_inputs : Vec<crate::timeline::RemoteInput>,
     pub last_client_frame_id : PlayerIdMap<u32>,
     pub rules_state : RulesState,
+    // Numbered per client so they can ack it, see delta.rs
+    #[serde(default)]
+    pub tick_id : u32,
+}
+
+impl LindenServerTick {
+    pub fn from_timeline(timeline : &Timeline, lkg_frame_id : u32, last_client_frame_id : PlayerIdMap<u32>) -> Self {
+        let top_state = timeline.top_state();
+        let lkg_state = timeline.try_get_state(lkg_frame_id).unwrap();
+
+        LindenServerTick {
+            latest : RemoteTickState::from_gamestate(top_state),
+            lkg_state : lkg_state.clone(),
+            delta_inputs : timeline.inputs_since_frame(lkg_frame_id),
+            last_client_frame_id,
+            rules_state : top_state.get_rule_state().clone(),
+            tick_id : 0,
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
@@ -127,6 +167,8 @@ pub enum TelemetryMessage
     ClientReceiveEvent(Telemetry_ClientReceiveEvent),
     LatencyEstimate(Telemetry_LatencyEstimate),
     PingOutcome(Telemetry_PingOutcome),
+    // Server side, never sent by clients.
+    InputRejected(Telemetry_InputRejected),
 }
 
 
@@ -162,4 +204,14 @@ pub struct Telemetry_PingOutcome
 
     pub current_client_time_ms : u32,
     pub current_client_date_time_ms : u32,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct Telemetry_InputRejected
+{
+    pub frame_id : u32,
+    pub server_frame_id : u32,
+    pub input : Input,
+    pub violation : InputViolation,
+    pub strikes : u32,
 }
\ No newline at end of file
@@ -0,0 +1,424 @@
+use std::collections::VecDeque;
+
+use serde::{Deserialize, Serialize};
+
+use crate::crossy_ruleset::RulesState;
+use crate::game::{GameState, PlayerInputs};
+use crate::player::PlayerState;
+use crate::player_id_map::PlayerIdMap;
+use crate::timeline::{RemoteInput, RemoteTickState};
+
+use super::LindenServerTick;
+
+// Every server tick carries the lkg state, every input since then and the rules state.
+// Nearly all of that is the same as the tick before so instead of resending it we diff against
+// the last tick the client told us it has (ServerTickAck) and the client rebuilds the full tick.
+//
+// The server keeps the ticks it has sent a client until they are acked, the client keeps the ticks
+// it has rebuilt until the server moves on to a newer baseline. If the baseline has been dropped
+// on either side we fall back to sending the full tick.
+
+// About 4s at 60Hz, a client that hasn't acked in that long gets full ticks until it catches up.
+const MAX_UNACKED_TICKS : usize = 240;
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub enum Delta<T> {
+    Same,
+    Changed(T),
+}
+
+impl<T : PartialEq + Clone> Delta<T> {
+    pub fn diff(baseline : &T, x : &T) -> Self {
+        if (baseline == x) {
+            Delta::Same
+        }
+        else {
+            Delta::Changed(x.clone())
+        }
+    }
+
+    pub fn apply(&self, baseline : &T) -> T {
+        match self {
+            Delta::Same => baseline.clone(),
+            Delta::Changed(x) => x.clone(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct GameStateDelta {
+    pub time_us : u32,
+    pub frame_id : u32,
+    // Slot by slot, slots past the end of the baseline are always Changed.
+    pub player_states : Vec<Delta<Option<PlayerState>>>,
+    pub rules_state : Delta<RulesState>,
+    pub player_inputs : Delta<PlayerInputs>,
+}
+
+impl GameStateDelta {
+    pub fn diff(baseline : &GameState, state : &GameState) -> Self {
+        let baseline_slots = baseline.player_states.slots();
+        let player_states = state.player_states.slots().iter().enumerate().map(|(i, slot)| {
+            match baseline_slots.get(i) {
+                Some(baseline_slot) => Delta::diff(baseline_slot, slot),
+                None => Delta::Changed(slot.clone()),
+            }
+        }).collect();
+
+        Self {
+            time_us : state.time_us,
+            frame_id : state.frame_id,
+            player_states,
+            rules_state : Delta::diff(&baseline.rules_state, &state.rules_state),
+            player_inputs : Delta::diff(&baseline.player_inputs, &state.player_inputs),
+        }
+    }
+
+    pub fn apply(&self, baseline : &GameState) -> Option<GameState> {
+        let baseline_slots = baseline.player_states.slots();
+        let mut slots = Vec::with_capacity(self.player_states.len());
+        for (i, slot) in self.player_states.iter().enumerate() {
+            slots.push(match (slot, baseline_slots.get(i)) {
+                (Delta::Changed(x), _) => x.clone(),
+                (Delta::Same, Some(x)) => x.clone(),
+                // Server thinks we have a slot we don't
+                (Delta::Same, None) => return None,
+            });
+        }
+
+        Some(GameState {
+            time_us : self.time_us,
+            frame_id : self.frame_id,
+            player_states : PlayerIdMap::from_slots(slots),
+            rules_state : self.rules_state.apply(&baseline.rules_state),
+            player_inputs : self.player_inputs.apply(&baseline.player_inputs),
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct RemoteTickStateDelta {
+    pub frame_id : u32,
+    pub time_us : u32,
+    // Index by index, states past the end of the baseline are always Changed.
+    pub states : Vec<Delta<PlayerState>>,
+}
+
+impl RemoteTickStateDelta {
+    pub fn diff(baseline : &RemoteTickState, state : &RemoteTickState) -> Self {
+        let states = state.states.iter().enumerate().map(|(i, x)| {
+            match baseline.states.get(i) {
+                Some(baseline_state) => Delta::diff(baseline_state, x),
+                None => Delta::Changed(x.clone()),
+            }
+        }).collect();
+
+        Self {
+            frame_id : state.frame_id,
+            time_us : state.time_us,
+            states,
+        }
+    }
+
+    pub fn apply(&self, baseline : &RemoteTickState) -> Option<RemoteTickState> {
+        let mut states = Vec::with_capacity(self.states.len());
+        for (i, x) in self.states.iter().enumerate() {
+            states.push(match x {
+                Delta::Changed(x) => x.clone(),
+                Delta::Same => baseline.states.get(i)?.clone(),
+            });
+        }
+
+        Some(RemoteTickState {
+            frame_id : self.frame_id,
+            time_us : self.time_us,
+            states,
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct LindenServerTickDelta {
+    pub tick_id : u32,
+    pub baseline_tick_id : u32,
+    pub latest : RemoteTickStateDelta,
+    pub lkg_state : GameStateDelta,
+    // The baseline's inputs from the new lkg frame on, minus removed_inputs, plus added_inputs.
+    // Inputs only change under us when late ones arrive or players leave.
+    pub removed_inputs : Vec<RemoteInput>,
+    pub added_inputs : Vec<RemoteInput>,
+    pub last_client_frame_id : PlayerIdMap<u32>,
+    pub rules_state : Delta<RulesState>,
+}
+
+impl LindenServerTickDelta {
+    pub fn diff(baseline : &LindenServerTick, tick : &LindenServerTick) -> Self {
+        let kept = kept_inputs(baseline, tick.lkg_state.frame_id);
+
+        Self {
+            tick_id : tick.tick_id,
+            baseline_tick_id : baseline.tick_id,
+            latest : RemoteTickStateDelta::diff(&baseline.latest, &tick.latest),
+            lkg_state : GameStateDelta::diff(&baseline.lkg_state, &tick.lkg_state),
+            removed_inputs : kept.iter().filter(|x| !tick.delta_inputs.contains(x)).map(|x| (*x).clone()).collect(),
+            added_inputs : tick.delta_inputs.iter().filter(|x| !kept.contains(x)).cloned().collect(),
+            last_client_frame_id : tick.last_client_frame_id.clone(),
+            rules_state : Delta::diff(&baseline.rules_state, &tick.rules_state),
+        }
+    }
+
+    pub fn apply(&self, baseline : &LindenServerTick) -> Option<LindenServerTick> {
+        if (baseline.tick_id != self.baseline_tick_id) {
+            return None;
+        }
+
+        let mut delta_inputs : Vec<RemoteInput> = kept_inputs(baseline, self.lkg_state.frame_id).into_iter()
+            .filter(|x| !self.removed_inputs.contains(x))
+            .cloned()
+            .chain(self.added_inputs.iter().cloned())
+            .collect();
+        delta_inputs.sort_by_key(|x| (x.frame_id, x.player_id));
+
+        Some(LindenServerTick {
+            latest : self.latest.apply(&baseline.latest)?,
+            lkg_state : self.lkg_state.apply(&baseline.lkg_state)?,
+            delta_inputs,
+            last_client_frame_id : self.last_client_frame_id.clone(),
+            rules_state : self.rules_state.apply(&baseline.rules_state),
+            tick_id : self.tick_id,
+        })
+    }
+}
+
+fn kept_inputs(baseline : &LindenServerTick, lkg_frame_id : u32) -> Vec<&RemoteInput> {
+    baseline.delta_inputs.iter().filter(|x| x.frame_id >= lkg_frame_id).collect()
+}
+
+// Server side, one per client.
+#[derive(Debug, Default)]
+pub struct TickEncoder {
+    next_tick_id : u32,
+    acked_tick_id : Option<u32>,
+    // Oldest first
+    sent : VecDeque<LindenServerTick>,
+}
+
+impl TickEncoder {
+    // Returns the frame the acked tick was sent on, if it's new, for round trip times.
+    pub fn ack(&mut self, tick_id : u32) -> Option<u32> {
+        if (self.acked_tick_id.map(|x| x >= tick_id).unwrap_or(false)) {
+            return None;
+        }
+
+        self.acked_tick_id = Some(tick_id);
+        while self.sent.front().map(|x| x.tick_id < tick_id).unwrap_or(false) {
+            self.sent.pop_front();
+        }
+
+        self.sent.front().filter(|x| x.tick_id == tick_id).map(|x| x.latest.frame_id)
+    }
+
+    pub fn encode(&mut self, mut tick : LindenServerTick) -> super::CrossyMessage {
+        tick.tick_id = self.next_tick_id;
+        self.next_tick_id += 1;
+
+        let baseline = self.acked_tick_id.and_then(|acked| self.sent.iter().find(|x| x.tick_id == acked));
+        let message = match baseline {
+            Some(baseline) => super::CrossyMessage::LindenServerTickDelta(LindenServerTickDelta::diff(baseline, &tick)),
+            None => super::CrossyMessage::LindenServerTick(tick.clone()),
+        };
+
+        self.sent.push_back(tick);
+        while self.sent.len() > MAX_UNACKED_TICKS {
+            self.sent.pop_front();
+        }
+
+        message
+    }
+}
+
+// Client side
+#[derive(Debug, Default)]
+pub struct TickDecoder {
+    // Oldest first
+    received : VecDeque<LindenServerTick>,
+    last_acked : Option<u32>,
+}
+
+impl TickDecoder {
+    pub fn receive_full(&mut self, tick : LindenServerTick) -> LindenServerTick {
+        self.push(tick.clone());
+        tick
+    }
+
+    pub fn receive_delta(&mut self, delta : &LindenServerTickDelta) -> Option<LindenServerTick> {
+        // The server only moves baselines forward so anything older is no use
+        while self.received.front().map(|x| x.tick_id < delta.baseline_tick_id).unwrap_or(false) {
+            self.received.pop_front();
+        }
+
+        let baseline = self.received.iter().find(|x| x.tick_id == delta.baseline_tick_id)?;
+        let tick = delta.apply(baseline)?;
+        self.push(tick.clone());
+        Some(tick)
+    }
+
+    pub fn has_ack(&self) -> bool {
+        self.received.back().map(|x| self.last_acked != Some(x.tick_id)).unwrap_or(false)
+    }
+
+    // Latest tick we haven't acked yet, if any.
+    pub fn take_ack(&mut self) -> Option<u32> {
+        if (!self.has_ack()) {
+            return None;
+        }
+
+        self.last_acked = self.received.back().map(|x| x.tick_id);
+        self.last_acked
+    }
+
+    fn push(&mut self, tick : LindenServerTick) {
+        self.received.push_back(tick);
+        while self.received.len() > MAX_UNACKED_TICKS {
+            self.received.pop_front();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use froggy_rand::FroggyRand;
+
+    use super::*;
+    use crate::crossy_ruleset::GameConfig;
+    use crate::game::{Input, PlayerId, Pos};
+    use crate::interop::{wire, CrossyMessage};
+    use crate::timeline::{Timeline, TICK_INTERVAL_US};
+
+    const PLAYERS : u8 = 4;
+    // Round trip in ticks before an ack gets back to the server
+    const ACK_DELAY : usize = 6;
+    // Inputs reach the server this many frames late and get slotted into the past
+    const INPUT_DELAY : u32 = 4;
+
+    fn random_input(rng : FroggyRand, frame_id : u32, player : u8) -> Input {
+        let rng = rng.subrand((frame_id, player));
+        // A couple of moves a second
+        if (rng.gen_unit("press") < 0.03) {
+            *rng.choose("dir", &[Input::Up, Input::Down, Input::Left, Input::Right])
+        }
+        else {
+            Input::None
+        }
+    }
+
+    // Runs a match the way the server does, sending every tick both in full and as a delta.
+    // Returns (full bytes, delta bytes).
+    fn run(frames : u32) -> (usize, usize) {
+        let rng = FroggyRand::new(1234);
+        let mut timeline = Timeline::from_seed(GameConfig::default(), "delta_test");
+        for i in 0..PLAYERS {
+            timeline.add_player(PlayerId(i), Pos::new_coord(7 + i as i32, 12));
+        }
+
+        let mut encoder = TickEncoder::default();
+        let mut decoder = TickDecoder::default();
+        let mut acks_in_flight = VecDeque::new();
+
+        let mut full_bytes = 0;
+        let mut delta_bytes = 0;
+
+        for tick_id in 0..frames {
+            timeline.tick(None, TICK_INTERVAL_US);
+            let frame_id = timeline.top_state().frame_id;
+
+            if (frame_id == frames / 2) {
+                timeline.remove_player(PlayerId(1));
+            }
+
+            if let Some(input_frame_id) = frame_id.checked_sub(INPUT_DELAY).filter(|x| *x > 0) {
+                let inputs = timeline.top_state().player_states.valid_ids().into_iter()
+                    .map(|id| (id, random_input(rng, input_frame_id, id.0)))
+                    .filter(|(_, input)| *input != Input::None)
+                    .map(|(player_id, input)| RemoteInput {
+                        time_us : input_frame_id * TICK_INTERVAL_US,
+                        frame_id : input_frame_id,
+                        input,
+                        player_id,
+                    })
+                    .collect();
+                assert!(timeline.try_propagate_inputs(inputs, true));
+            }
+
+            let lkg_frame_id = frame_id.saturating_sub(100);
+            let tick = LindenServerTick::from_timeline(&timeline, lkg_frame_id, PlayerIdMap::new());
+            let mut expected = tick.clone();
+            expected.tick_id = tick_id;
+            let expected_bytes = wire::encode(&CrossyMessage::LindenServerTick(expected));
+            full_bytes += expected_bytes.len();
+
+            let sent = wire::encode(&encoder.encode(tick));
+            delta_bytes += sent.len();
+
+            let rebuilt = match wire::decode(&sent).unwrap() {
+                CrossyMessage::LindenServerTick(x) => decoder.receive_full(x),
+                CrossyMessage::LindenServerTickDelta(x) => decoder.receive_delta(&x).expect("Missing baseline"),
+                x => panic!("Unexpected message {:?}", x),
+            };
+            assert_eq!(wire::encode(&CrossyMessage::LindenServerTick(rebuilt)), expected_bytes, "tick {}", tick_id);
+
+            acks_in_flight.push_back(decoder.take_ack());
+            if (acks_in_flight.len() > ACK_DELAY) {
+                if let Some(ack) = acks_in_flight.pop_front().unwrap() {
+                    encoder.ack(ack);
+                }
+            }
+        }
+
+        (full_bytes, delta_bytes)
+    }
+
+    #[test]
+    fn bandwidth() {
+        let frames = 60 * 20;
+        let (full_bytes, delta_bytes) = run(frames);
+        println!("Full ticks {} bytes/s, delta ticks {} bytes/s",
+            full_bytes as u32 * 60 / frames,
+            delta_bytes as u32 * 60 / frames);
+
+        // Headers, tick ids and frame times are sent either way so a quiet tick is still ~35 bytes
+        assert!(delta_bytes * 2 < full_bytes, "full {} delta {}", full_bytes, delta_bytes);
+    }
+
+    #[test]
+    fn falls_back_to_full_without_acks() {
+        let timeline = Timeline::from_seed(GameConfig::default(), "delta_test");
+        let tick = LindenServerTick::from_timeline(&timeline, 0, PlayerIdMap::new());
+
+        let mut encoder = TickEncoder::default();
+        assert!(matches!(encoder.encode(tick.clone()), CrossyMessage::LindenServerTick(_)));
+        assert!(matches!(encoder.encode(tick.clone()), CrossyMessage::LindenServerTick(_)));
+
+        encoder.ack(1);
+        match encoder.encode(tick.clone()) {
+            CrossyMessage::LindenServerTickDelta(x) => assert_eq!(x.baseline_tick_id, 1),
+            x => panic!("Expected delta, got {:?}", x),
+        }
+
+        // Acked tick fell out of the history
+        for _ in 0..MAX_UNACKED_TICKS {
+            encoder.encode(tick.clone());
+        }
+        assert!(matches!(encoder.encode(tick.clone()), CrossyMessage::LindenServerTick(_)));
+
+        // Client can't decode against a tick it doesn't have
+        let mut decoder = TickDecoder::default();
+        let mut encoder = TickEncoder::default();
+        encoder.encode(tick.clone());
+        encoder.ack(0);
+        match encoder.encode(tick) {
+            CrossyMessage::LindenServerTickDelta(x) => assert!(decoder.receive_delta(&x).is_none()),
+            x => panic!("Expected delta, got {:?}", x),
+        }
+    }
+}
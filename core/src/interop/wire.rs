@@ -0,0 +1,1495 @@
+use crate::crossy_ruleset::*;
+use crate::game_rules::GameMode;
+use crate::game::{CoordPos, GameState, Input, LillipadId, PlayerId, PlayerInputs, Pos};
+use crate::input_validation::InputViolation;
+use crate::map::level::LevelPreset;
+use crate::map::profile::MapGenPreset;
+use crate::math::V2;
+use crate::player::{MoveState, MovingState, PlayerState, Powerup, PushInfo};
+use crate::player_id_map::PlayerIdMap;
+use crate::timeline::{RemoteInput, RemoteTickState};
+
+use super::*;
+use super::delta::*;
+
+// Binary encoding for CrossyMessage.
+//
+// Every message is framed as
+//   magic "cx", protocol version, message tag, body
+// and every type that goes over the wire is written out field by field below. Adding a field to
+// GameState or PlayerState doesn't change the wire until it is added here, and the golden tests
+// at the bottom fail if the bytes change without a version bump.
+//
+// Integers are LEB128 varints, zigzagged if signed, floats are little endian.
+//
+// Both Writer and Reader carry the version being spoken so impls can shim older clients:
+// bump PROTOCOL_VERSION, only write / read the new field when the version is new enough and
+// fall back to a default otherwise.
+//
+// Version 1 is the old flexbuffers encoding of the serde derives. Clients that don't say which
+// version they speak are assumed to be on it and keep getting flexbuffers, see encode_for_version.
+// Fields added to the serde derives since are #[serde(default)] so both sides can still read each
+// other, and the server sends these clients full ticks as they never ack.
+//
+// Version 2 is the binary encoding below.
+//
+// Clients on any other version get a 426 from /join, and the web client reloads once to pick up
+// the new build before asking the player to refresh, see reload_for_new_version in index.js.
+pub const WIRE_MAGIC : &[u8; 2] = b"cx";
+pub const PROTOCOL_VERSION : u8 = 2;
+pub const MIN_PROTOCOL_VERSION : u8 = 1;
+pub const LEGACY_FLEXBUFFERS_VERSION : u8 = 1;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum WireError {
+    UnsupportedVersion(u8),
+    UnexpectedEnd,
+    BadTag { what : &'static str, tag : u64 },
+    TrailingBytes(usize),
+    Legacy(String),
+}
+
+impl std::fmt::Display for WireError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            WireError::UnsupportedVersion(v) => write!(f, "unsupported protocol version {} (supported {}..={})", v, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION),
+            WireError::UnexpectedEnd => write!(f, "message ended early"),
+            WireError::BadTag { what, tag } => write!(f, "bad {} tag {}", what, tag),
+            WireError::TrailingBytes(n) => write!(f, "{} bytes left over after message", n),
+            WireError::Legacy(e) => write!(f, "bad legacy message: {}", e),
+        }
+    }
+}
+
+pub fn version_supported(version : u8) -> bool {
+    (MIN_PROTOCOL_VERSION..=PROTOCOL_VERSION).contains(&version)
+}
+
+pub fn encode(message : &CrossyMessage) -> Vec<u8> {
+    encode_for_version(message, PROTOCOL_VERSION)
+}
+
+pub fn encode_for_version(message : &CrossyMessage, version : u8) -> Vec<u8> {
+    assert!(version_supported(version), "Tried to encode for unsupported version {}", version);
+
+    if (version == LEGACY_FLEXBUFFERS_VERSION) {
+        return flexbuffers::to_vec(message).unwrap();
+    }
+
+    let mut w = Writer::new(version);
+    w.bytes.extend_from_slice(WIRE_MAGIC);
+    w.u8(version);
+    message.write(&mut w);
+    w.bytes
+}
+
+// Anything not starting with the magic is treated as a legacy flexbuffers message.
+pub fn decode(bytes : &[u8]) -> Result<CrossyMessage, WireError> {
+    if (!bytes.starts_with(WIRE_MAGIC)) {
+        let reader = flexbuffers::Reader::get_root(bytes).map_err(|e| WireError::Legacy(e.to_string()))?;
+        return serde::Deserialize::deserialize(reader).map_err(|e : flexbuffers::DeserializationError| WireError::Legacy(e.to_string()));
+    }
+
+    let version = *bytes.get(WIRE_MAGIC.len()).ok_or(WireError::UnexpectedEnd)?;
+    if (version == LEGACY_FLEXBUFFERS_VERSION || !version_supported(version)) {
+        return Err(WireError::UnsupportedVersion(version));
+    }
+
+    let mut r = Reader::new(&bytes[WIRE_MAGIC.len() + 1..], version);
+    let message = CrossyMessage::read(&mut r)?;
+    if (r.remaining() > 0) {
+        return Err(WireError::TrailingBytes(r.remaining()));
+    }
+
+    Ok(message)
+}
+
+pub struct Writer {
+    pub version : u8,
+    bytes : Vec<u8>,
+}
+
+impl Writer {
+    pub fn new(version : u8) -> Self {
+        Self {
+            version,
+            bytes : Vec::with_capacity(64),
+        }
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.bytes
+    }
+
+    pub fn u8(&mut self, x : u8) {
+        self.bytes.push(x);
+    }
+
+    pub fn var(&mut self, mut x : u64) {
+        while x >= 0x80 {
+            self.bytes.push((x as u8) | 0x80);
+            x >>= 7;
+        }
+        self.bytes.push(x as u8);
+    }
+
+    pub fn var_signed(&mut self, x : i64) {
+        self.var(((x << 1) ^ (x >> 63)) as u64);
+    }
+
+    pub fn f32(&mut self, x : f32) {
+        self.bytes.extend_from_slice(&x.to_le_bytes());
+    }
+}
+
+pub struct Reader<'a> {
+    pub version : u8,
+    bytes : &'a [u8],
+    pos : usize,
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(bytes : &'a [u8], version : u8) -> Self {
+        Self {
+            version,
+            bytes,
+            pos : 0,
+        }
+    }
+
+    pub fn remaining(&self) -> usize {
+        self.bytes.len() - self.pos
+    }
+
+    pub fn u8(&mut self) -> Result<u8, WireError> {
+        let x = *self.bytes.get(self.pos).ok_or(WireError::UnexpectedEnd)?;
+        self.pos += 1;
+        Ok(x)
+    }
+
+    pub fn var(&mut self) -> Result<u64, WireError> {
+        let mut x = 0u64;
+        let mut shift = 0;
+        loop {
+            let byte = self.u8()?;
+            if (shift >= 64) {
+                return Err(WireError::BadTag { what : "varint", tag : byte as u64 });
+            }
+
+            x |= ((byte & 0x7f) as u64) << shift;
+            if (byte & 0x80 == 0) {
+                return Ok(x);
+            }
+            shift += 7;
+        }
+    }
+
+    pub fn var_signed(&mut self) -> Result<i64, WireError> {
+        let x = self.var()?;
+        Ok(((x >> 1) as i64) ^ -((x & 1) as i64))
+    }
+
+    pub fn f32(&mut self) -> Result<f32, WireError> {
+        let end = self.pos + 4;
+        let bytes = self.bytes.get(self.pos..end).ok_or(WireError::UnexpectedEnd)?;
+        self.pos = end;
+        Ok(f32::from_le_bytes(bytes.try_into().unwrap()))
+    }
+
+    // Lengths are checked against what is left so a bad message can't make us allocate gigabytes.
+    fn len(&mut self) -> Result<usize, WireError> {
+        let len = self.var()? as usize;
+        if (len > self.remaining()) {
+            return Err(WireError::UnexpectedEnd);
+        }
+        Ok(len)
+    }
+}
+
+pub trait Wire : Sized {
+    fn write(&self, w : &mut Writer);
+    fn read(r : &mut Reader) -> Result<Self, WireError>;
+}
+
+fn bad_tag<T>(what : &'static str, tag : u64) -> Result<T, WireError> {
+    Err(WireError::BadTag { what, tag })
+}
+
+// Primitives
+
+impl Wire for u8 {
+    fn write(&self, w : &mut Writer) {
+        w.u8(*self);
+    }
+
+    fn read(r : &mut Reader) -> Result<Self, WireError> {
+        r.u8()
+    }
+}
+
+impl Wire for bool {
+    fn write(&self, w : &mut Writer) {
+        w.u8(*self as u8);
+    }
+
+    fn read(r : &mut Reader) -> Result<Self, WireError> {
+        match r.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            x => bad_tag("bool", x as u64),
+        }
+    }
+}
+
+impl Wire for u32 {
+    fn write(&self, w : &mut Writer) {
+        w.var(*self as u64);
+    }
+
+    fn read(r : &mut Reader) -> Result<Self, WireError> {
+        let x = r.var()?;
+        u32::try_from(x).or(bad_tag("u32", x))
+    }
+}
+
+impl Wire for i32 {
+    fn write(&self, w : &mut Writer) {
+        w.var_signed(*self as i64);
+    }
+
+    fn read(r : &mut Reader) -> Result<Self, WireError> {
+        let x = r.var_signed()?;
+        i32::try_from(x).or(bad_tag("i32", x as u64))
+    }
+}
+
+impl Wire for i64 {
+    fn write(&self, w : &mut Writer) {
+        w.var_signed(*self);
+    }
+
+    fn read(r : &mut Reader) -> Result<Self, WireError> {
+        r.var_signed()
+    }
+}
+
+impl Wire for f32 {
+    fn write(&self, w : &mut Writer) {
+        w.f32(*self);
+    }
+
+    fn read(r : &mut Reader) -> Result<Self, WireError> {
+        r.f32()
+    }
+}
+
+impl<T : Wire> Wire for Option<T> {
+    fn write(&self, w : &mut Writer) {
+        match self {
+            None => w.u8(0),
+            Some(x) => {
+                w.u8(1);
+                x.write(w);
+            },
+        }
+    }
+
+    fn read(r : &mut Reader) -> Result<Self, WireError> {
+        match r.u8()? {
+            0 => Ok(None),
+            1 => Ok(Some(T::read(r)?)),
+            x => bad_tag("option", x as u64),
+        }
+    }
+}
+
+impl<T : Wire> Wire for Vec<T> {
+    fn write(&self, w : &mut Writer) {
+        w.var(self.len() as u64);
+        for x in self {
+            x.write(w);
+        }
+    }
+
+    fn read(r : &mut Reader) -> Result<Self, WireError> {
+        let len = r.len()?;
+        let mut vec = Vec::with_capacity(len);
+        for _ in 0..len {
+            vec.push(T::read(r)?);
+        }
+        Ok(vec)
+    }
+}
+
+impl<T : Wire + Clone> Wire for PlayerIdMap<T> {
+    fn write(&self, w : &mut Writer) {
+        w.var(self.slots().len() as u64);
+        for slot in self.slots() {
+            slot.write(w);
+        }
+    }
+
+    fn read(r : &mut Reader) -> Result<Self, WireError> {
+        Ok(PlayerIdMap::from_slots(Vec::read(r)?))
+    }
+}
+
+// Game types
+
+impl Wire for PlayerId {
+    fn write(&self, w : &mut Writer) {
+        w.u8(self.0);
+    }
+
+    fn read(r : &mut Reader) -> Result<Self, WireError> {
+        Ok(PlayerId(r.u8()?))
+    }
+}
+
+impl Wire for Input {
+    fn write(&self, w : &mut Writer) {
+        w.u8(*self as u8);
+    }
+
+    fn read(r : &mut Reader) -> Result<Self, WireError> {
+        let x = r.u8()?;
+        match x {
+            0 => Ok(Input::None),
+            1 => Ok(Input::Up),
+            2 => Ok(Input::Left),
+            3 => Ok(Input::Right),
+            4 => Ok(Input::Down),
+            _ => bad_tag("input", x as u64),
+        }
+    }
+}
+
+impl Wire for InputViolation {
+    fn write(&self, w : &mut Writer) {
+        w.u8(*self as u8);
+    }
+
+    fn read(r : &mut Reader) -> Result<Self, WireError> {
+        let x = r.u8()?;
+        match x {
+            0 => Ok(InputViolation::TooOld),
+            1 => Ok(InputViolation::TooFarAhead),
+            2 => Ok(InputViolation::Duplicate),
+            3 => Ok(InputViolation::RateLimited),
+            4 => Ok(InputViolation::OutOfOrder),
+            5 => Ok(InputViolation::MidMove),
+            _ => bad_tag("input violation", x as u64),
+        }
+    }
+}
+
+impl Wire for PlayerInputs {
+    fn write(&self, w : &mut Writer) {
+        self.inputs.write(w);
+    }
+
+    fn read(r : &mut Reader) -> Result<Self, WireError> {
+        Ok(PlayerInputs { inputs : Vec::read(r)? })
+    }
+}
+
+impl Wire for CoordPos {
+    fn write(&self, w : &mut Writer) {
+        self.x.write(w);
+        self.y.write(w);
+    }
+
+    fn read(r : &mut Reader) -> Result<Self, WireError> {
+        Ok(CoordPos { x : i32::read(r)?, y : i32::read(r)? })
+    }
+}
+
+impl Wire for LillipadId {
+    fn write(&self, w : &mut Writer) {
+        self.id.write(w);
+        self.y.write(w);
+        self.round_id.write(w);
+    }
+
+    fn read(r : &mut Reader) -> Result<Self, WireError> {
+        Ok(LillipadId { id : u8::read(r)?, y : i32::read(r)?, round_id : u8::read(r)? })
+    }
+}
+
+impl Wire for V2 {
+    fn write(&self, w : &mut Writer) {
+        self.x.write(w);
+        self.y.write(w);
+    }
+
+    fn read(r : &mut Reader) -> Result<Self, WireError> {
+        Ok(V2 { x : f32::read(r)?, y : f32::read(r)? })
+    }
+}
+
+impl Wire for Pos {
+    fn write(&self, w : &mut Writer) {
+        match self {
+            Pos::Coord(x) => {
+                w.u8(0);
+                x.write(w);
+            },
+            Pos::Lillipad(x) => {
+                w.u8(1);
+                x.write(w);
+            },
+            Pos::Absolute(x) => {
+                w.u8(2);
+                x.write(w);
+            },
+        }
+    }
+
+    fn read(r : &mut Reader) -> Result<Self, WireError> {
+        match r.u8()? {
+            0 => Ok(Pos::Coord(CoordPos::read(r)?)),
+            1 => Ok(Pos::Lillipad(LillipadId::read(r)?)),
+            2 => Ok(Pos::Absolute(V2::read(r)?)),
+            x => bad_tag("pos", x as u64),
+        }
+    }
+}
+
+impl Wire for PushInfo {
+    fn write(&self, w : &mut Writer) {
+        self.push_start_frame_id.write(w);
+        self.pushed_by.write(w);
+        self.pushing.write(w);
+    }
+
+    fn read(r : &mut Reader) -> Result<Self, WireError> {
+        Ok(PushInfo {
+            push_start_frame_id : u32::read(r)?,
+            pushed_by : Option::read(r)?,
+            pushing : Option::read(r)?,
+        })
+    }
+}
+
+impl Wire for MoveState {
+    fn write(&self, w : &mut Writer) {
+        match self {
+            MoveState::Stationary => w.u8(0),
+            MoveState::Moving(moving) => {
+                w.u8(1);
+                moving.remaining_us.write(w);
+                moving.target.write(w);
+                moving.push_info.write(w);
+                moving.dir.write(w);
+                moving.duration_us.write(w);
+            },
+        }
+    }
+
+    fn read(r : &mut Reader) -> Result<Self, WireError> {
+        match r.u8()? {
+            0 => Ok(MoveState::Stationary),
+            1 => Ok(MoveState::Moving(MovingState {
+                remaining_us : u32::read(r)?,
+                target : Pos::read(r)?,
+                push_info : PushInfo::read(r)?,
+                dir : Option::read(r)?,
+                duration_us : u32::read(r)?,
+            })),
+            x => bad_tag("move state", x as u64),
+        }
+    }
+}
+
+impl Wire for Powerup {
+    fn write(&self, w : &mut Writer) {
+        match self {
+            Powerup::SpeedBoost { remaining_us } => {
+                w.u8(0);
+                remaining_us.write(w);
+            },
+            Powerup::Shield => w.u8(1),
+            Powerup::ShieldBroken { remaining_us } => {
+                w.u8(2);
+                remaining_us.write(w);
+            },
+            Powerup::SuperPush { remaining_us } => {
+                w.u8(3);
+                remaining_us.write(w);
+            },
+        }
+    }
+
+    fn read(r : &mut Reader) -> Result<Self, WireError> {
+        match r.u8()? {
+            0 => Ok(Powerup::SpeedBoost { remaining_us : u32::read(r)? }),
+            1 => Ok(Powerup::Shield),
+            2 => Ok(Powerup::ShieldBroken { remaining_us : u32::read(r)? }),
+            3 => Ok(Powerup::SuperPush { remaining_us : u32::read(r)? }),
+            x => bad_tag("powerup", x as u64),
+        }
+    }
+}
+
+impl Wire for PlayerState {
+    fn write(&self, w : &mut Writer) {
+        self.id.write(w);
+        self.move_state.write(w);
+        self.move_cooldown.write(w);
+        self.pos.write(w);
+        self.powerup.write(w);
+    }
+
+    fn read(r : &mut Reader) -> Result<Self, WireError> {
+        Ok(PlayerState {
+            id : PlayerId::read(r)?,
+            move_state : MoveState::read(r)?,
+            move_cooldown : u32::read(r)?,
+            pos : Pos::read(r)?,
+            powerup : Option::read(r)?,
+        })
+    }
+}
+
+impl Wire for MapGenPreset {
+    fn write(&self, w : &mut Writer) {
+        w.u8(match self {
+            MapGenPreset::Standard => 0,
+            MapGenPreset::RoadsOnly => 1,
+            MapGenPreset::IceGauntlet => 2,
+            MapGenPreset::Calm => 3,
+        });
+    }
+
+    fn read(r : &mut Reader) -> Result<Self, WireError> {
+        match r.u8()? {
+            0 => Ok(MapGenPreset::Standard),
+            1 => Ok(MapGenPreset::RoadsOnly),
+            2 => Ok(MapGenPreset::IceGauntlet),
+            3 => Ok(MapGenPreset::Calm),
+            x => bad_tag("map gen preset", x as u64),
+        }
+    }
+}
+
+impl Wire for LevelPreset {
+    fn write(&self, w : &mut Writer) {
+        w.u8(match self {
+            LevelPreset::Tutorial => 0,
+            LevelPreset::Gauntlet => 1,
+        });
+    }
+
+    fn read(r : &mut Reader) -> Result<Self, WireError> {
+        match r.u8()? {
+            0 => Ok(LevelPreset::Tutorial),
+            1 => Ok(LevelPreset::Gauntlet),
+            x => bad_tag("level preset", x as u64),
+        }
+    }
+}
+
+impl Wire for GameMode {
+    fn write(&self, w : &mut Writer) {
+        w.u8(match self {
+            GameMode::LastFrogStanding => 0,
+            GameMode::Race => 1,
+            GameMode::Teams => 2,
+        });
+    }
+
+    fn read(r : &mut Reader) -> Result<Self, WireError> {
+        match r.u8()? {
+            0 => Ok(GameMode::LastFrogStanding),
+            1 => Ok(GameMode::Race),
+            2 => Ok(GameMode::Teams),
+            x => bad_tag("game mode", x as u64),
+        }
+    }
+}
+
+impl Wire for GameConfig {
+    fn write(&self, w : &mut Writer) {
+        self.required_win_count.write(w);
+        self.minimum_players.write(w);
+        self.bypass_lobby.write(w);
+        self.map_gen.write(w);
+        self.game_mode.write(w);
+        self.round_time_limit_us.write(w);
+        self.level.write(w);
+    }
+
+    fn read(r : &mut Reader) -> Result<Self, WireError> {
+        Ok(GameConfig {
+            required_win_count : u8::read(r)?,
+            minimum_players : u8::read(r)?,
+            bypass_lobby : bool::read(r)?,
+            map_gen : MapGenPreset::read(r)?,
+            game_mode : GameMode::read(r)?,
+            round_time_limit_us : Option::read(r)?,
+            level : Option::read(r)?,
+        })
+    }
+}
+
+impl Wire for AliveState {
+    fn write(&self, w : &mut Writer) {
+        w.u8(match self {
+            AliveState::NotInGame => 0,
+            AliveState::Alive => 1,
+            AliveState::Dead => 2,
+        });
+    }
+
+    fn read(r : &mut Reader) -> Result<Self, WireError> {
+        match r.u8()? {
+            0 => Ok(AliveState::NotInGame),
+            1 => Ok(AliveState::Alive),
+            2 => Ok(AliveState::Dead),
+            x => bad_tag("alive state", x as u64),
+        }
+    }
+}
+
+impl Wire for RoundState {
+    fn write(&self, w : &mut Writer) {
+        self.screen_y.write(w);
+        self.alive_states.write(w);
+        self.win_counts.write(w);
+        self.round_id.write(w);
+        self.finish_order.write(w);
+        self.teams.write(w);
+        self.round_time_us.write(w);
+        self.taken_pickups.write(w);
+    }
+
+    fn read(r : &mut Reader) -> Result<Self, WireError> {
+        Ok(RoundState {
+            screen_y : i32::read(r)?,
+            alive_states : PlayerIdMap::read(r)?,
+            win_counts : PlayerIdMap::read(r)?,
+            round_id : u8::read(r)?,
+            finish_order : Vec::read(r)?,
+            teams : PlayerIdMap::read(r)?,
+            round_time_us : u32::read(r)?,
+            taken_pickups : Vec::read(r)?,
+        })
+    }
+}
+
+impl Wire for CrossyRulesetFST {
+    fn write(&self, w : &mut Writer) {
+        match self {
+            CrossyRulesetFST::Lobby { time_with_all_players_in_ready_zone, raft_pos } => {
+                w.u8(0);
+                time_with_all_players_in_ready_zone.write(w);
+                raft_pos.write(w);
+            },
+            CrossyRulesetFST::RoundWarmup(state) => {
+                w.u8(1);
+                state.remaining_us.write(w);
+                state.time_full_us.write(w);
+                state.alive_states.write(w);
+                state.win_counts.write(w);
+                state.round_id.write(w);
+                state.teams.write(w);
+            },
+            CrossyRulesetFST::Round(state) => {
+                w.u8(2);
+                state.write(w);
+            },
+            CrossyRulesetFST::RoundCooldown(state) => {
+                w.u8(3);
+                state.remaining_us.write(w);
+                state.round_state.write(w);
+            },
+            CrossyRulesetFST::EndWinner(state) => {
+                w.u8(4);
+                state.winner_id.write(w);
+                state.remaining_us.write(w);
+                state.winner_team.write(w);
+                state.teams.write(w);
+            },
+            CrossyRulesetFST::EndAllLeft(state) => {
+                w.u8(5);
+                state.remaining_us.write(w);
+            },
+        }
+    }
+
+    fn read(r : &mut Reader) -> Result<Self, WireError> {
+        match r.u8()? {
+            0 => Ok(CrossyRulesetFST::Lobby {
+                time_with_all_players_in_ready_zone : u32::read(r)?,
+                raft_pos : f32::read(r)?,
+            }),
+            1 => Ok(CrossyRulesetFST::RoundWarmup(WarmupState {
+                remaining_us : u32::read(r)?,
+                time_full_us : u32::read(r)?,
+                alive_states : PlayerIdMap::read(r)?,
+                win_counts : PlayerIdMap::read(r)?,
+                round_id : u8::read(r)?,
+                teams : PlayerIdMap::read(r)?,
+            })),
+            2 => Ok(CrossyRulesetFST::Round(RoundState::read(r)?)),
+            3 => Ok(CrossyRulesetFST::RoundCooldown(CooldownState {
+                remaining_us : u32::read(r)?,
+                round_state : RoundState::read(r)?,
+            })),
+            4 => Ok(CrossyRulesetFST::EndWinner(EndWinnerState {
+                winner_id : PlayerId::read(r)?,
+                remaining_us : u32::read(r)?,
+                winner_team : Option::read(r)?,
+                teams : PlayerIdMap::read(r)?,
+            })),
+            5 => Ok(CrossyRulesetFST::EndAllLeft(EndAllLeftState {
+                remaining_us : u32::read(r)?,
+            })),
+            x => bad_tag("ruleset state", x as u64),
+        }
+    }
+}
+
+impl Wire for RulesState {
+    fn write(&self, w : &mut Writer) {
+        self.game_id.write(w);
+        self.fst.write(w);
+        self.config.write(w);
+    }
+
+    fn read(r : &mut Reader) -> Result<Self, WireError> {
+        Ok(RulesState {
+            game_id : u32::read(r)?,
+            fst : CrossyRulesetFST::read(r)?,
+            config : GameConfig::read(r)?,
+        })
+    }
+}
+
+impl Wire for GameState {
+    fn write(&self, w : &mut Writer) {
+        self.time_us.write(w);
+        self.frame_id.write(w);
+        self.player_states.write(w);
+        self.rules_state.write(w);
+        self.player_inputs.write(w);
+    }
+
+    fn read(r : &mut Reader) -> Result<Self, WireError> {
+        Ok(GameState {
+            time_us : u32::read(r)?,
+            frame_id : u32::read(r)?,
+            player_states : PlayerIdMap::read(r)?,
+            rules_state : RulesState::read(r)?,
+            player_inputs : PlayerInputs::read(r)?,
+        })
+    }
+}
+
+impl Wire for RemoteInput {
+    fn write(&self, w : &mut Writer) {
+        self.time_us.write(w);
+        self.frame_id.write(w);
+        self.input.write(w);
+        self.player_id.write(w);
+    }
+
+    fn read(r : &mut Reader) -> Result<Self, WireError> {
+        Ok(RemoteInput {
+            time_us : u32::read(r)?,
+            frame_id : u32::read(r)?,
+            input : Input::read(r)?,
+            player_id : PlayerId::read(r)?,
+        })
+    }
+}
+
+impl Wire for RemoteTickState {
+    fn write(&self, w : &mut Writer) {
+        self.frame_id.write(w);
+        self.time_us.write(w);
+        self.states.write(w);
+    }
+
+    fn read(r : &mut Reader) -> Result<Self, WireError> {
+        Ok(RemoteTickState {
+            frame_id : u32::read(r)?,
+            time_us : u32::read(r)?,
+            states : Vec::read(r)?,
+        })
+    }
+}
+
+// Messages
+
+impl Wire for ClientHello {
+    fn write(&self, w : &mut Writer) {
+        w.bytes.extend_from_slice(&self.header);
+        self.version.write(w);
+    }
+
+    fn read(r : &mut Reader) -> Result<Self, WireError> {
+        let mut header = [0; 4];
+        for x in header.iter_mut() {
+            *x = r.u8()?;
+        }
+
+        Ok(ClientHello { header, version : u8::read(r)? })
+    }
+}
+
+impl Wire for InitServerResponse {
+    fn write(&self, w : &mut Writer) {
+        self.server_version.write(w);
+        self.player_count.write(w);
+        self.seed.write(w);
+        self.player_id.write(w);
+        self.protocol_version.write(w);
+    }
+
+    fn read(r : &mut Reader) -> Result<Self, WireError> {
+        Ok(InitServerResponse {
+            server_version : u8::read(r)?,
+            player_count : u8::read(r)?,
+            seed : u32::read(r)?,
+            player_id : PlayerId::read(r)?,
+            protocol_version : u8::read(r)?,
+        })
+    }
+}
+
+impl Wire for ServerDescription {
+    fn write(&self, w : &mut Writer) {
+        self.server_version.write(w);
+        self.seed.write(w);
+    }
+
+    fn read(r : &mut Reader) -> Result<Self, WireError> {
+        Ok(ServerDescription {
+            server_version : u8::read(r)?,
+            seed : u32::read(r)?,
+        })
+    }
+}
+
+impl Wire for ClientTick {
+    fn write(&self, w : &mut Writer) {
+        self.time_us.write(w);
+        self.frame_id.write(w);
+        self.input.write(w);
+    }
+
+    fn read(r : &mut Reader) -> Result<Self, WireError> {
+        Ok(ClientTick {
+            time_us : u32::read(r)?,
+            frame_id : u32::read(r)?,
+            input : Input::read(r)?,
+        })
+    }
+}
+
+impl Wire for LindenServerTick {
+    fn write(&self, w : &mut Writer) {
+        self.latest.write(w);
+        self.lkg_state.write(w);
+        self.delta_inputs.write(w);
+        self.last_client_frame_id.write(w);
+        self.rules_state.write(w);
+        self.tick_id.write(w);
+    }
+
+    fn read(r : &mut Reader) -> Result<Self, WireError> {
+        Ok(LindenServerTick {
+            latest : RemoteTickState::read(r)?,
+            lkg_state : GameState::read(r)?,
+            delta_inputs : Vec::read(r)?,
+            last_client_frame_id : PlayerIdMap::read(r)?,
+            rules_state : RulesState::read(r)?,
+            tick_id : u32::read(r)?,
+        })
+    }
+}
+
+impl<T : Wire> Wire for Delta<T> {
+    fn write(&self, w : &mut Writer) {
+        match self {
+            Delta::Same => w.u8(0),
+            Delta::Changed(x) => {
+                w.u8(1);
+                x.write(w);
+            },
+        }
+    }
+
+    fn read(r : &mut Reader) -> Result<Self, WireError> {
+        match r.u8()? {
+            0 => Ok(Delta::Same),
+            1 => Ok(Delta::Changed(T::read(r)?)),
+            x => bad_tag("delta", x as u64),
+        }
+    }
+}
+
+impl Wire for RemoteTickStateDelta {
+    fn write(&self, w : &mut Writer) {
+        self.frame_id.write(w);
+        self.time_us.write(w);
+        self.states.write(w);
+    }
+
+    fn read(r : &mut Reader) -> Result<Self, WireError> {
+        Ok(RemoteTickStateDelta {
+            frame_id : u32::read(r)?,
+            time_us : u32::read(r)?,
+            states : Vec::read(r)?,
+        })
+    }
+}
+
+impl Wire for GameStateDelta {
+    fn write(&self, w : &mut Writer) {
+        self.time_us.write(w);
+        self.frame_id.write(w);
+        self.player_states.write(w);
+        self.rules_state.write(w);
+        self.player_inputs.write(w);
+    }
+
+    fn read(r : &mut Reader) -> Result<Self, WireError> {
+        Ok(GameStateDelta {
+            time_us : u32::read(r)?,
+            frame_id : u32::read(r)?,
+            player_states : Vec::read(r)?,
+            rules_state : Delta::read(r)?,
+            player_inputs : Delta::read(r)?,
+        })
+    }
+}
+
+impl Wire for LindenServerTickDelta {
+    fn write(&self, w : &mut Writer) {
+        self.tick_id.write(w);
+        self.baseline_tick_id.write(w);
+        self.latest.write(w);
+        self.lkg_state.write(w);
+        self.removed_inputs.write(w);
+        self.added_inputs.write(w);
+        self.last_client_frame_id.write(w);
+        self.rules_state.write(w);
+    }
+
+    fn read(r : &mut Reader) -> Result<Self, WireError> {
+        Ok(LindenServerTickDelta {
+            tick_id : u32::read(r)?,
+            baseline_tick_id : u32::read(r)?,
+            latest : RemoteTickStateDelta::read(r)?,
+            lkg_state : GameStateDelta::read(r)?,
+            removed_inputs : Vec::read(r)?,
+            added_inputs : Vec::read(r)?,
+            last_client_frame_id : PlayerIdMap::read(r)?,
+            rules_state : Delta::read(r)?,
+        })
+    }
+}
+
+impl Wire for TimeRequestPacket {
+    fn write(&self, w : &mut Writer) {
+        self.client_send_time_us.write(w);
+    }
+
+    fn read(r : &mut Reader) -> Result<Self, WireError> {
+        Ok(TimeRequestPacket { client_send_time_us : u32::read(r)? })
+    }
+}
+
+impl Wire for TimeRequestIntermediate {
+    fn write(&self, w : &mut Writer) {
+        self.client_send_time_us.write(w);
+        self.server_receive_time_us.write(w);
+        self.socket_id.write(w);
+    }
+
+    fn read(r : &mut Reader) -> Result<Self, WireError> {
+        Ok(TimeRequestIntermediate {
+            client_send_time_us : u32::read(r)?,
+            server_receive_time_us : u32::read(r)?,
+            socket_id : u32::read(r)?,
+        })
+    }
+}
+
+impl Wire for TimeResponsePacket {
+    fn write(&self, w : &mut Writer) {
+        self.client_send_time_us.write(w);
+        self.server_receive_time_us.write(w);
+        self.server_send_time_us.write(w);
+    }
+
+    fn read(r : &mut Reader) -> Result<Self, WireError> {
+        Ok(TimeResponsePacket {
+            client_send_time_us : u32::read(r)?,
+            server_receive_time_us : u32::read(r)?,
+            server_send_time_us : u32::read(r)?,
+        })
+    }
+}
+
+impl Wire for TelemetryMessage {
+    fn write(&self, w : &mut Writer) {
+        match self {
+            TelemetryMessage::ClientReceiveEvent(x) => {
+                w.u8(0);
+                x.server_send_frame_id.write(w);
+                x.receive_frame_id.write(w);
+                x.delta_input_server_frame_times_min.write(w);
+                x.delta_input_server_frame_times_max.write(w);
+                x.delta_input_server_frame_times_count.write(w);
+            },
+            TelemetryMessage::LatencyEstimate(x) => {
+                w.u8(1);
+                x.estimated_latency_us.write(w);
+                x.estimated_frame_delta.write(w);
+                x.estimated_server_current_frame_id.write(w);
+            },
+            TelemetryMessage::PingOutcome(x) => {
+                w.u8(2);
+                x.unlerped_estimated_latency_us.write(w);
+                x.unlerped_estimated_frame_delta.write(w);
+                x.estimated_latency_us.write(w);
+                x.estimated_frame_delta.write(w);
+                x.estimated_server_time_us.write(w);
+                x.estimated_server_current_frame_id.write(w);
+                x.current_client_time_ms.write(w);
+                x.current_client_date_time_ms.write(w);
+            },
+            TelemetryMessage::InputRejected(x) => {
+                w.u8(3);
+                x.frame_id.write(w);
+                x.server_frame_id.write(w);
+                x.input.write(w);
+                x.violation.write(w);
+                x.strikes.write(w);
+            },
+        }
+    }
+
+    fn read(r : &mut Reader) -> Result<Self, WireError> {
+        match r.u8()? {
+            0 => Ok(TelemetryMessage::ClientReceiveEvent(Telemetry_ClientReceiveEvent {
+                server_send_frame_id : u32::read(r)?,
+                receive_frame_id : u32::read(r)?,
+                delta_input_server_frame_times_min : Option::read(r)?,
+                delta_input_server_frame_times_max : Option::read(r)?,
+                delta_input_server_frame_times_count : u32::read(r)?,
+            })),
+            1 => Ok(TelemetryMessage::LatencyEstimate(Telemetry_LatencyEstimate {
+                estimated_latency_us : i32::read(r)?,
+                estimated_frame_delta : i32::read(r)?,
+                estimated_server_current_frame_id : u32::read(r)?,
+            })),
+            2 => Ok(TelemetryMessage::PingOutcome(Telemetry_PingOutcome {
+                unlerped_estimated_latency_us : i64::read(r)?,
+                unlerped_estimated_frame_delta : i64::read(r)?,
+                estimated_latency_us : f32::read(r)?,
+                estimated_frame_delta : f32::read(r)?,
+                estimated_server_time_us : u32::read(r)?,
+                estimated_server_current_frame_id : u32::read(r)?,
+                current_client_time_ms : u32::read(r)?,
+                current_client_date_time_ms : u32::read(r)?,
+            })),
+            3 => Ok(TelemetryMessage::InputRejected(Telemetry_InputRejected {
+                frame_id : u32::read(r)?,
+                server_frame_id : u32::read(r)?,
+                input : Input::read(r)?,
+                violation : InputViolation::read(r)?,
+                strikes : u32::read(r)?,
+            })),
+            x => bad_tag("telemetry message", x as u64),
+        }
+    }
+}
+
+// Tags are part of the format, never reuse one.
+impl Wire for CrossyMessage {
+    fn write(&self, w : &mut Writer) {
+        match self {
+            CrossyMessage::Hello(x) => {
+                w.u8(0);
+                x.write(w);
+            },
+            CrossyMessage::HelloResponse(x) => {
+                w.u8(1);
+                x.write(w);
+            },
+            CrossyMessage::ServerDecription(x) => {
+                w.u8(2);
+                x.write(w);
+            },
+            CrossyMessage::ClientTick(x) => {
+                w.u8(3);
+                x.write(w);
+            },
+            CrossyMessage::ClientDrop() => w.u8(4),
+            CrossyMessage::LindenServerTick(x) => {
+                w.u8(5);
+                x.write(w);
+            },
+            CrossyMessage::TimeRequestPacket(x) => {
+                w.u8(6);
+                x.write(w);
+            },
+            CrossyMessage::TimeRequestIntermediate(x) => {
+                w.u8(7);
+                x.write(w);
+            },
+            CrossyMessage::TimeResponsePacket(x) => {
+                w.u8(8);
+                x.write(w);
+            },
+            CrossyMessage::TelemetryMessagePackage(x) => {
+                w.u8(9);
+                x.messages.write(w);
+            },
+            CrossyMessage::GoodBye() => w.u8(10),
+            CrossyMessage::EmptyMessage() => w.u8(11),
+            CrossyMessage::LindenServerTickDelta(x) => {
+                w.u8(12);
+                x.write(w);
+            },
+            CrossyMessage::ServerTickAck(x) => {
+                w.u8(13);
+                x.write(w);
+            },
+        }
+    }
+
+    fn read(r : &mut Reader) -> Result<Self, WireError> {
+        match r.u8()? {
+            0 => Ok(CrossyMessage::Hello(ClientHello::read(r)?)),
+            1 => Ok(CrossyMessage::HelloResponse(InitServerResponse::read(r)?)),
+            2 => Ok(CrossyMessage::ServerDecription(ServerDescription::read(r)?)),
+            3 => Ok(CrossyMessage::ClientTick(Vec::read(r)?)),
+            4 => Ok(CrossyMessage::ClientDrop()),
+            5 => Ok(CrossyMessage::LindenServerTick(LindenServerTick::read(r)?)),
+            6 => Ok(CrossyMessage::TimeRequestPacket(TimeRequestPacket::read(r)?)),
+            7 => Ok(CrossyMessage::TimeRequestIntermediate(TimeRequestIntermediate::read(r)?)),
+            8 => Ok(CrossyMessage::TimeResponsePacket(TimeResponsePacket::read(r)?)),
+            9 => Ok(CrossyMessage::TelemetryMessagePackage(TelemetryMessagePackage { messages : Vec::read(r)? })),
+            10 => Ok(CrossyMessage::GoodBye()),
+            11 => Ok(CrossyMessage::EmptyMessage()),
+            12 => Ok(CrossyMessage::LindenServerTickDelta(LindenServerTickDelta::read(r)?)),
+            13 => Ok(CrossyMessage::ServerTickAck(u32::read(r)?)),
+            x => bad_tag("message", x as u64),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn player(id : u8, move_state : MoveState, pos : Pos) -> PlayerState {
+        PlayerState {
+            id : PlayerId(id),
+            move_state,
+            move_cooldown : 0,
+            pos,
+            powerup : None,
+        }
+    }
+
+    fn example_game_state() -> GameState {
+        let mut state = GameState::new(GameConfig::default());
+        state.time_us = 1_000_000;
+        state.frame_id = 60;
+        state.player_states.set(PlayerId(0), player(0, MoveState::Stationary, Pos::new_coord(3, -2)));
+        state.player_states.set(PlayerId(2), player(2, MoveState::Moving(MovingState {
+            remaining_us : 1000,
+            duration_us : 5000,
+            target : Pos::Lillipad(LillipadId { id : 4, y : -30, round_id : 2 }),
+            push_info : PushInfo { push_start_frame_id : 58, pushed_by : Some(PlayerId(0)), pushing : None },
+            dir : Some(Input::Up),
+        }), Pos::Absolute(V2 { x : 1.5, y : -2.25 })));
+        let player_2 = state.player_states.get_mut(PlayerId(2)).unwrap();
+        player_2.powerup = Some(Powerup::SuperPush { remaining_us : 2000 });
+        state.player_inputs.set(PlayerId(2), Input::Left);
+        state
+    }
+
+    fn example_linden_tick() -> CrossyMessage {
+        let state = example_game_state();
+        let mut last_client_frame_id = PlayerIdMap::new();
+        last_client_frame_id.set(PlayerId(0), 55);
+
+        CrossyMessage::LindenServerTick(LindenServerTick {
+            latest : RemoteTickState::from_gamestate(&state),
+            lkg_state : state.clone(),
+            delta_inputs : vec![RemoteInput { time_us : 1_016_666, frame_id : 61, input : Input::Down, player_id : PlayerId(0) }],
+            last_client_frame_id,
+            rules_state : state.rules_state.clone(),
+            tick_id : 7,
+        })
+    }
+
+    fn all_fsts() -> Vec<CrossyRulesetFST> {
+        let mut alive_states = PlayerIdMap::new();
+        alive_states.set(PlayerId(1), AliveState::Alive);
+        alive_states.set(PlayerId(3), AliveState::Dead);
+        let mut win_counts = PlayerIdMap::new();
+        win_counts.set(PlayerId(1), 2);
+        let mut teams = PlayerIdMap::new();
+        teams.set(PlayerId(1), 0);
+        teams.set(PlayerId(3), 1);
+
+        let round_state = RoundState {
+            screen_y : -40,
+            alive_states : alive_states.clone(),
+            win_counts : win_counts.clone(),
+            round_id : 3,
+            finish_order : vec![PlayerId(3), PlayerId(1)],
+            teams : teams.clone(),
+            round_time_us : 12_345_678,
+            taken_pickups : vec![-12, -40],
+        };
+
+        vec![
+            CrossyRulesetFST::start(),
+            CrossyRulesetFST::RoundWarmup(WarmupState {
+                remaining_us : 10,
+                time_full_us : 20,
+                alive_states,
+                win_counts,
+                round_id : 3,
+                teams : teams.clone(),
+            }),
+            CrossyRulesetFST::Round(round_state.clone()),
+            CrossyRulesetFST::RoundCooldown(CooldownState { remaining_us : 5, round_state }),
+            CrossyRulesetFST::EndWinner(EndWinnerState { winner_id : PlayerId(1), remaining_us : 7, winner_team : Some(0), teams }),
+            CrossyRulesetFST::EndAllLeft(EndAllLeftState::default()),
+        ]
+    }
+
+    fn roundtrip(message : &CrossyMessage) -> CrossyMessage {
+        let bytes = encode(message);
+        let decoded = decode(&bytes).unwrap();
+        assert_eq!(encode(&decoded), bytes);
+        assert_eq!(format!("{:?}", decoded), format!("{:?}", message));
+        decoded
+    }
+
+    #[test]
+    fn roundtrip_messages() {
+        roundtrip(&example_linden_tick());
+        roundtrip(&CrossyMessage::Hello(ClientHello::default()));
+        roundtrip(&CrossyMessage::GoodBye());
+        roundtrip(&CrossyMessage::TelemetryMessagePackage(TelemetryMessagePackage {
+            messages : vec![
+                TelemetryMessage::LatencyEstimate(Telemetry_LatencyEstimate {
+                    estimated_latency_us : -5000,
+                    estimated_frame_delta : -3,
+                    estimated_server_current_frame_id : 1234,
+                }),
+                TelemetryMessage::PingOutcome(Telemetry_PingOutcome {
+                    unlerped_estimated_latency_us : i64::MIN,
+                    unlerped_estimated_frame_delta : i64::MAX,
+                    estimated_latency_us : 0.5,
+                    estimated_frame_delta : -1.0,
+                    estimated_server_time_us : u32::MAX,
+                    estimated_server_current_frame_id : 0,
+                    current_client_time_ms : 1,
+                    current_client_date_time_ms : 2,
+                }),
+                TelemetryMessage::InputRejected(Telemetry_InputRejected {
+                    frame_id : 100,
+                    server_frame_id : 200,
+                    input : Input::Left,
+                    violation : InputViolation::TooOld,
+                    strikes : 3,
+                }),
+                TelemetryMessage::InputRejected(Telemetry_InputRejected {
+                    frame_id : 101,
+                    server_frame_id : 200,
+                    input : Input::Up,
+                    violation : InputViolation::MidMove,
+                    strikes : 4,
+                }),
+            ],
+        }));
+    }
+
+    #[test]
+    fn roundtrip_rules_states() {
+        for fst in all_fsts() {
+            let mut rules_state = RulesState::new(GameConfig::default());
+            rules_state.fst = fst;
+
+            let mut w = Writer::new(PROTOCOL_VERSION);
+            rules_state.write(&mut w);
+            let bytes = w.into_bytes();
+
+            let mut r = Reader::new(&bytes, PROTOCOL_VERSION);
+            assert_eq!(RulesState::read(&mut r).unwrap(), rules_state);
+            assert_eq!(r.remaining(), 0);
+        }
+    }
+
+    #[test]
+    fn roundtrip_keeps_player_id_gaps() {
+        let mut map = PlayerIdMap::new();
+        map.set(PlayerId(1), 10u32);
+        map.set(PlayerId(4), 40u32);
+        map.remove(PlayerId(4));
+
+        let mut w = Writer::new(PROTOCOL_VERSION);
+        map.write(&mut w);
+        let bytes = w.into_bytes();
+        assert_eq!(PlayerIdMap::<u32>::read(&mut Reader::new(&bytes, PROTOCOL_VERSION)).unwrap(), map);
+    }
+
+    // If these change the wire has changed, bump PROTOCOL_VERSION.
+    #[test]
+    fn golden_bytes() {
+        let client_tick = CrossyMessage::ClientTick(vec![ClientTick { time_us : 300, frame_id : 5, input : Input::Up }]);
+        assert_eq!(encode(&client_tick), vec![b'c', b'x', PROTOCOL_VERSION, 3, 1, 0xac, 0x02, 5, 1]);
+
+        let time_request = CrossyMessage::TimeRequestPacket(TimeRequestPacket { client_send_time_us : 1 });
+        assert_eq!(encode(&time_request), vec![b'c', b'x', PROTOCOL_VERSION, 6, 1]);
+
+        let hello = CrossyMessage::Hello(ClientHello::new(PROTOCOL_VERSION));
+        assert_eq!(encode(&hello), vec![b'c', b'x', PROTOCOL_VERSION, 0, b'h', b'e', b'l', b'o', PROTOCOL_VERSION]);
+
+        let ack = CrossyMessage::ServerTickAck(130);
+        assert_eq!(encode(&ack), vec![b'c', b'x', PROTOCOL_VERSION, 13, 0x82, 0x01]);
+
+        let config = GameConfig {
+            game_mode : GameMode::Teams,
+            round_time_limit_us : Some(300),
+            level : Some(LevelPreset::Gauntlet),
+            ..Default::default()
+        };
+        let mut alive_states = PlayerIdMap::new();
+        alive_states.set(PlayerId(0), AliveState::Alive);
+        let mut teams = PlayerIdMap::new();
+        teams.set(PlayerId(0), 1);
+
+        let mut state = GameState::new(config);
+        state.frame_id = 2;
+        state.player_states.set(PlayerId(0), player(0, MoveState::Moving(MovingState {
+            remaining_us : 3,
+            duration_us : 70,
+            target : Pos::new_coord(3, -3),
+            push_info : PushInfo::empty_at_frame(0),
+            dir : Some(Input::Up),
+        }), Pos::new_coord(3, -2)));
+        let player_0 = state.player_states.get_mut(PlayerId(0)).unwrap();
+        player_0.powerup = Some(Powerup::Shield);
+        state.rules_state.fst = CrossyRulesetFST::Round(RoundState {
+            screen_y : -2,
+            alive_states,
+            win_counts : PlayerIdMap::new(),
+            round_id : 1,
+            finish_order : vec![PlayerId(0)],
+            teams,
+            round_time_us : 300,
+            taken_pickups : vec![-12],
+        });
+
+        let mut w = Writer::new(PROTOCOL_VERSION);
+        state.write(&mut w);
+        assert_eq!(w.into_bytes(), vec![
+            // time_us, frame_id
+            0, 2,
+            // player_states, one slot: id, moving, remaining, coord (3, -3), push info, up, duration
+            1, 1, 0, 1, 3, 0, 6, 5, 0, 0, 0, 1, 1, 70,
+            // cooldown, coord (3, -2), shield
+            0, 0, 6, 3, 1, 1,
+            // rules_state: game_id, round, screen y, alive states, win counts, round id, finish order, teams, round time,
+            // one pickup taken at -12
+            0, 2, 3, 1, 1, 1, 0, 1, 1, 0, 1, 1, 1, 172, 2, 1, 23,
+            // config: win count, min players, bypass lobby, standard map, teams, time limit, gauntlet
+            3, 2, 0, 0, 2, 1, 172, 2, 1, 1,
+            // no inputs
+            0,
+        ]);
+
+        let mut moved = state.clone();
+        moved.frame_id = 3;
+        moved.player_states.get_mut(PlayerId(0)).unwrap().move_state = MoveState::Stationary;
+
+        let mut w = Writer::new(PROTOCOL_VERSION);
+        GameStateDelta::diff(&state, &moved).write(&mut w);
+        assert_eq!(w.into_bytes(), vec![
+            // time_us, frame_id
+            0, 3,
+            // one slot, changed: id, stationary, cooldown, coord (3, -2), shield
+            1, 1, 1, 0, 0, 0, 0, 6, 3, 1, 1,
+            // rules state and inputs unchanged
+            0, 0,
+        ]);
+    }
+
+    #[test]
+    fn rejects_versions() {
+        let mut bytes = encode(&CrossyMessage::GoodBye());
+        bytes[2] = PROTOCOL_VERSION + 1;
+        assert_eq!(decode(&bytes).unwrap_err(), WireError::UnsupportedVersion(PROTOCOL_VERSION + 1));
+
+        // Legacy clients never send the magic
+        bytes[2] = LEGACY_FLEXBUFFERS_VERSION;
+        assert_eq!(decode(&bytes).unwrap_err(), WireError::UnsupportedVersion(LEGACY_FLEXBUFFERS_VERSION));
+
+        assert!(!ClientHello::new(0).check());
+        assert!(ClientHello::new(LEGACY_FLEXBUFFERS_VERSION).check());
+        assert!(ClientHello::new(PROTOCOL_VERSION).check());
+        assert!(!ClientHello::new(PROTOCOL_VERSION + 1).check());
+    }
+
+    #[test]
+    fn rejects_bad_messages() {
+        let bytes = encode(&example_linden_tick());
+        for len in [3, 4, bytes.len() / 2, bytes.len() - 1] {
+            assert!(decode(&bytes[..len]).is_err(), "len {}", len);
+        }
+
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+        assert_eq!(decode(&trailing).unwrap_err(), WireError::TrailingBytes(1));
+
+        assert_eq!(decode(&[b'c', b'x', PROTOCOL_VERSION, 200]).unwrap_err(), WireError::BadTag { what : "message", tag : 200 });
+    }
+
+    #[test]
+    fn legacy_flexbuffers() {
+        let message = example_linden_tick();
+        let legacy = encode_for_version(&message, LEGACY_FLEXBUFFERS_VERSION);
+        assert_eq!(legacy, flexbuffers::to_vec(&message).unwrap());
+
+        let decoded = decode(&legacy).unwrap();
+        assert_eq!(encode(&decoded), encode(&message));
+
+        // Which is why we moved off it
+        assert!(encode(&message).len() * 4 < legacy.len());
+    }
+
+    // What a client from before the binary protocol sends, pins down the shim.
+    #[test]
+    fn legacy_client_messages() {
+        let hello = [72, 101, 108, 108, 111, 0, 104, 101, 97, 100, 101, 114, 0, 104, 101, 108, 111, 118, 101, 114, 115, 105, 111, 110, 0, 2, 20, 10, 2, 1, 2, 18, 1, 92, 8, 1, 36, 1, 1, 1, 9, 36, 2, 36, 1];
+        match decode(&hello).unwrap() {
+            CrossyMessage::Hello(x) => assert_eq!(x, ClientHello::new(LEGACY_FLEXBUFFERS_VERSION)),
+            x => panic!("Expected hello, got {:?}", x),
+        }
+
+        let client_tick = [67, 108, 105, 101, 110, 116, 84, 105, 99, 107, 0, 116, 105, 109, 101, 95, 117, 115, 0, 102, 114, 97, 109, 101, 95, 105, 100, 0, 105, 110, 112, 117, 116, 0, 2, 85, 112, 0, 3, 20, 12, 30, 3, 0, 1, 0, 3, 0, 5, 0, 15, 0, 44, 1, 9, 20, 9, 1, 10, 37, 1, 61, 1, 1, 1, 7, 40, 2, 36, 1];
+        match decode(&client_tick).unwrap() {
+            CrossyMessage::ClientTick(x) => assert_eq!(x, vec![ClientTick { time_us : 300, frame_id : 5, input : Input::Up }]),
+            x => panic!("Expected client tick, got {:?}", x),
+        }
+
+        let time_request = [84, 105, 109, 101, 82, 101, 113, 117, 101, 115, 116, 80, 97, 99, 107, 101, 116, 0, 99, 108, 105, 101, 110, 116, 95, 115, 101, 110, 100, 95, 116, 105, 109, 101, 95, 117, 115, 0, 1, 21, 1, 1, 1, 1, 8, 1, 46, 1, 1, 1, 7, 36, 2, 36, 1];
+        match decode(&time_request).unwrap() {
+            CrossyMessage::TimeRequestPacket(x) => assert_eq!(x, TimeRequestPacket { client_send_time_us : 1 }),
+            x => panic!("Expected time request, got {:?}", x),
+        }
+    }
+}
@@ -57,10 +57,15 @@ pub mod player_id_map;
 pub mod interop;
 pub mod timeline;
 pub mod crossy_ruleset;
+pub mod game_rules;
 pub mod map;
 pub mod telemetry;
 pub mod ring_buffer;
 pub mod math;
 pub mod bitmap;
+pub mod replay;
+pub mod ai;
+pub mod prediction;
+pub mod input_validation;
 
 pub use game::*;
\ No newline at end of file
@@ -4,6 +4,18 @@ use crate::SCREEN_SIZE;
 
 use super::PathDescr;
 
+// Bushes can be walked through, trees are solid.
+// Rows of bushes are never generated next to each other so trees can't cut off the way up.
+const BUSH_CHANCE : f64 = 0.45;
+const TREE_CHANCE : f64 = 0.12;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BushColumn {
+    Open,
+    Bush,
+    Tree,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct BushDescr {
     pub path_descr : PathDescr,
@@ -12,27 +24,111 @@ pub struct BushDescr {
 }
 
 impl BushDescr {
+    pub fn column(&self, x : i32) -> BushColumn {
+        if (x <= self.path_descr.wall_width as i32 || x >= (SCREEN_SIZE - self.path_descr.wall_width as i32 - 1)) {
+            return BushColumn::Open;
+        }
+
+        let rng = FroggyRand::from_hash((self.y, self.seed));
+        let roll = rng.gen_unit(x);
+        if (roll < TREE_CHANCE) {
+            BushColumn::Tree
+        }
+        else if (roll < BUSH_CHANCE) {
+            BushColumn::Bush
+        }
+        else {
+            BushColumn::Open
+        }
+    }
+
+    pub fn is_tree(&self, x : i32) -> bool {
+        self.column(x) == BushColumn::Tree
+    }
+
+    pub fn is_bush(&self, x : i32) -> bool {
+        self.column(x) == BushColumn::Bush
+    }
+
     pub fn hydrate(&self) -> HydratedBushRow {
         let mut bushes = Vec::new();
-        let rng = FroggyRand::from_hash((self.y, self.seed));
+        let mut trees = Vec::new();
 
         for x in 0..SCREEN_SIZE {
-            if (x <= self.path_descr.wall_width as i32 || x >= (SCREEN_SIZE - self.path_descr.wall_width as i32 - 1)) {
-                continue;
+            match self.column(x) {
+                BushColumn::Bush => bushes.push(x),
+                BushColumn::Tree => trees.push(x),
+                BushColumn::Open => {},
             }
-
-            //if (rng.gen_unit(x) < 0.45) {
-                bushes.push(x);
-            //}
         }
 
         HydratedBushRow {
             bushes,
+            trees,
         }
     }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct HydratedBushRow {
-    bushes : Vec<i32>,
-}
\ No newline at end of file
+    pub bushes : Vec<i32>,
+    pub trees : Vec<i32>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crossy_ruleset::RulesState;
+    use crate::map::{Row, RowId, RowType};
+    use crate::CoordPos;
+
+    #[test]
+    fn sparse_and_seeded() {
+        let mut bush_count = 0;
+        let mut tree_count = 0;
+
+        for seed in 0..50 {
+            let descr = BushDescr {
+                path_descr : PathDescr { wall_width : 2 },
+                seed,
+                y : -10,
+            };
+
+            let hydrated = descr.hydrate();
+            assert!(hydrated.bushes.len() + hydrated.trees.len() < 16);
+            assert!(hydrated.bushes.iter().chain(hydrated.trees.iter()).all(|x| *x > 2 && *x < 17));
+
+            let again = descr.hydrate();
+            assert_eq!(hydrated.bushes, again.bushes);
+            assert_eq!(hydrated.trees, again.trees);
+
+            bush_count += hydrated.bushes.len();
+            tree_count += hydrated.trees.len();
+        }
+
+        assert!(bush_count > 0);
+        assert!(tree_count > 0);
+        assert!(tree_count < bush_count);
+    }
+
+    #[test]
+    fn trees_are_solid() {
+        let y = -10;
+        let descr = BushDescr {
+            path_descr : PathDescr { wall_width : 2 },
+            seed : 1234,
+            y,
+        };
+        let hydrated = descr.hydrate();
+        let row = Row {
+            row_id : RowId::from_y(y),
+            row_type : RowType::Bushes(descr),
+        };
+
+        let rules_state = RulesState::new(Default::default());
+        for x in 3..17 {
+            let solid = row.solid(0, &rules_state, CoordPos::new(x, y));
+            assert_eq!(solid, hydrated.trees.contains(&x), "x = {}", x);
+        }
+    }
+}
@@ -0,0 +1,423 @@
+use crate::bitmap::BitMap;
+use crate::crossy_ruleset::lillipad_offscreen;
+use crate::player;
+use crate::timeline::TICK_INTERVAL_US;
+use crate::{PreciseCoords, SCREEN_SIZE};
+
+use super::river::{self, River};
+use super::road::Road;
+
+// Roads and rivers are only generated with spacing heuristics, this checks a section
+// can actually be crossed with real timings.
+//
+// Obstacles wrap around so each lane repeats with its period. We search over (position, tick)
+// starting from the bank below at any tick in the first period, then allow up to another
+// period to reach the bank above. Obstacles are only simulated as far as the search has got,
+// most sections are crossed well before the end of the window.
+//
+// Timings mirror PlayerState and death_cause:
+// - A move takes MOVE_TICKS without a speed boost, we can move again the tick we arrive.
+// - While moving between tiles we are checked for cars at the tile we are moving to.
+// - While moving on or off a lillipad we are checked where we are, so on a road that is
+//   the tile we jumped from and on a lillipad it is the lillipad.
+//
+// Lanes are ordered from the bottom of the section to the top.
+
+const MOVE_TICKS : usize = player::MOVE_TICKS as usize;
+
+#[derive(Debug, Clone, Copy)]
+pub enum Lane<'a> {
+    Road(&'a Road),
+    River(&'a River),
+}
+
+impl<'a> Lane<'a> {
+    fn period_us(&self) -> u32 {
+        match self {
+            Lane::Road(road) => road.period_us(),
+            Lane::River(river) => river.period_us(),
+        }
+    }
+}
+
+// Per lane per tick, what a stationary frog can stand on.
+enum LaneTimeline {
+    // Columns with a car in them, and columns with a car in them at any point during a move starting then.
+    Road {
+        blocked : Vec<BitMap>,
+        blocked_moving : Vec<u64>,
+    },
+    // Lillipad x positions, indexed by id.
+    River(Vec<Vec<f64>>),
+}
+
+const SCREEN_MASK : u64 = (1 << SCREEN_SIZE) - 1;
+
+fn tick_time_us(tick : usize) -> u32 {
+    tick as u32 * TICK_INTERVAL_US
+}
+
+// Stationary positions reached per tick and lane, columns for roads and lillipad ids for rivers.
+// Moves land at most MOVE_TICKS ahead so we only keep that many ticks, reusing rows once passed.
+struct Reached {
+    lanes : usize,
+    rows : Vec<u64>,
+}
+
+impl Reached {
+    fn new(lanes : usize) -> Self {
+        Self {
+            lanes,
+            rows : vec![0; lanes * (MOVE_TICKS + 1)],
+        }
+    }
+
+    fn index(&self, tick : usize, lane : usize) -> usize {
+        (tick % (MOVE_TICKS + 1)) * self.lanes + lane
+    }
+
+    fn get(&self, tick : usize, lane : usize) -> u64 {
+        self.rows[self.index(tick, lane)]
+    }
+
+    fn add(&mut self, tick : usize, lane : usize, positions : u64) {
+        let i = self.index(tick, lane);
+        self.rows[i] |= positions;
+    }
+
+    fn clear(&mut self, tick : usize) {
+        let start = self.index(tick, 0);
+        self.rows[start..start + self.lanes].fill(0);
+    }
+}
+
+pub fn verify_section(lanes : &[Lane], bank_below : BitMap, bank_above : BitMap) -> bool {
+    if (lanes.is_empty()) {
+        return true;
+    }
+
+    let period_ticks = lanes.iter().map(|x| x.period_us() / TICK_INTERVAL_US).max().unwrap() as usize + 1;
+    let ticks = 2 * period_ticks;
+
+    let timelines : Vec<LaneTimeline> = lanes.iter().map(|lane| match lane {
+        Lane::Road(_) => LaneTimeline::Road {
+            blocked : Vec::with_capacity(ticks + MOVE_TICKS),
+            blocked_moving : Vec::with_capacity(ticks),
+        },
+        Lane::River(_) => LaneTimeline::River(Vec::with_capacity(ticks + MOVE_TICKS)),
+    }).collect();
+
+    let mut search = Search {
+        lanes,
+        timelines,
+        bank_above,
+    };
+
+    let mut reached = Reached::new(lanes.len());
+
+    for t in 0..ticks {
+        search.extend_timelines(t + MOVE_TICKS + 1);
+
+        // We can wait on the bank as long as we like.
+        reached.add(t + MOVE_TICKS, 0, search.arrive_columns(0, t, bank_below.inner & SCREEN_MASK, From::Bank));
+
+        for lane in 0..lanes.len() {
+            let current = reached.get(t, lane);
+            if (current == 0) {
+                continue;
+            }
+
+            match &search.timelines[lane] {
+                LaneTimeline::Road { blocked, .. } => {
+                    // Wait
+                    reached.add(t + 1, lane, current & !blocked[t + 1].inner);
+
+                    if (search.try_moves_from_road(&mut reached, lane, t, current)) {
+                        return true;
+                    }
+                },
+                LaneTimeline::River(xs) => {
+                    let mut remaining = current;
+                    while (remaining != 0) {
+                        let id = remaining.trailing_zeros() as usize;
+                        remaining &= remaining - 1;
+
+                        // Wait
+                        if (!lillipad_offscreen(xs[t + 1][id])) {
+                            reached.add(t + 1, lane, 1 << id);
+                        }
+
+                        // We stay on the lillipad until we arrive.
+                        if (lillipad_offscreen(xs[t + MOVE_TICKS - 1][id])) {
+                            continue;
+                        }
+
+                        if (search.try_moves_from_lillipad(&mut reached, lane, t, xs[t][id])) {
+                            return true;
+                        }
+                    }
+                },
+            }
+        }
+
+        reached.clear(t);
+    }
+
+    false
+}
+
+// Where a move starts from, decides which tile we are checked against on the way.
+#[derive(Debug, Clone, Copy)]
+enum From {
+    Bank,
+    Road(usize),
+    Lillipad,
+}
+
+struct Search<'a> {
+    lanes : &'a [Lane<'a>],
+    timelines : Vec<LaneTimeline>,
+    bank_above : BitMap,
+}
+
+impl<'a> Search<'a> {
+    fn extend_timelines(&mut self, len : usize) {
+        for (lane, timeline) in self.lanes.iter().zip(self.timelines.iter_mut()) {
+            match (lane, timeline) {
+                (Lane::Road(road), LaneTimeline::Road { blocked, blocked_moving }) => {
+                    while (blocked.len() < len) {
+                        blocked.push(road.blocked_columns(tick_time_us(blocked.len())));
+                    }
+
+                    while (blocked_moving.len() + MOVE_TICKS < blocked.len()) {
+                        let t = blocked_moving.len();
+                        blocked_moving.push(blocked[t + 1..=t + MOVE_TICKS].iter().fold(0, |acc, x| acc | x.inner));
+                    }
+                },
+                (Lane::River(river), LaneTimeline::River(xs)) => {
+                    while (xs.len() < len) {
+                        let lillipad_xs = river.get_lillipad_xs(tick_time_us(xs.len()));
+                        // Lillipads are tracked in a bitmask.
+                        debug_assert!(lillipad_xs.len() <= 64);
+                        xs.push(lillipad_xs);
+                    }
+                },
+                _ => unreachable!(),
+            }
+        }
+    }
+
+    // Moving from each of the columns in a road lane at tick t, returns true if we make it to the bank above.
+    fn try_moves_from_road(&self, reached : &mut Reached, lane : usize, t : usize, columns : u64) -> bool {
+        let from = From::Road(lane);
+
+        // Left and right
+        let sideways = ((columns << 1) | (columns >> 1)) & SCREEN_MASK;
+        reached.add(t + MOVE_TICKS, lane, self.arrive_columns(lane, t, sideways, from));
+
+        // Down, no point going back to the bank
+        if (lane > 0) {
+            reached.add(t + MOVE_TICKS, lane - 1, self.arrive_columns(lane - 1, t, columns, from));
+        }
+
+        // Up
+        if (lane + 1 == self.timelines.len()) {
+            columns & self.bank_above.inner != 0
+        }
+        else {
+            reached.add(t + MOVE_TICKS, lane + 1, self.arrive_columns(lane + 1, t, columns, from));
+            false
+        }
+    }
+
+    // Moving from x on a lillipad at tick t, returns true if we make it to the bank above.
+    fn try_moves_from_lillipad(&self, reached : &mut Reached, lane : usize, t : usize, x : f64) -> bool {
+        let from = From::Lillipad;
+
+        // Left and right
+        for dx in [-1.0, 1.0] {
+            reached.add(t + MOVE_TICKS, lane, self.arrive(lane, t, x + dx, from));
+        }
+
+        // Down, no point going back to the bank
+        if (lane > 0) {
+            reached.add(t + MOVE_TICKS, lane - 1, self.arrive(lane - 1, t, x, from));
+        }
+
+        // Up
+        if (lane + 1 == self.timelines.len()) {
+            let coord = PreciseCoords { x, y : 0 }.to_coords();
+            coord.x >= 0 && coord.x < SCREEN_SIZE && self.bank_above.get(coord.x)
+        }
+        else {
+            reached.add(t + MOVE_TICKS, lane + 1, self.arrive(lane + 1, t, x, from));
+            false
+        }
+    }
+
+    // Jumping at tick t to each of the columns in a lane, not from a lillipad.
+    fn arrive_columns(&self, lane : usize, t : usize, columns : u64, from : From) -> u64 {
+        match &self.timelines[lane] {
+            LaneTimeline::Road { blocked_moving, .. } => columns & !blocked_moving[t],
+            LaneTimeline::River(_) => {
+                let mut arrived = 0;
+                let mut remaining = columns;
+                while (remaining != 0) {
+                    let x = remaining.trailing_zeros();
+                    remaining &= remaining - 1;
+                    arrived |= self.arrive(lane, t, x as f64, from);
+                }
+                arrived
+            },
+        }
+    }
+
+    // Jumping at tick t to x in a lane, returns what we are standing on at t + MOVE_TICKS.
+    fn arrive(&self, lane : usize, t : usize, x : f64, from : From) -> u64 {
+        match &self.timelines[lane] {
+            LaneTimeline::Road { blocked, blocked_moving } => {
+                let column = PreciseCoords { x, y : 0 }.to_coords().x;
+                if (!(0..SCREEN_SIZE).contains(&column)) {
+                    return 0;
+                }
+
+                // Coming off a lillipad we are only checked against the road once we land.
+                let hit = if let From::Lillipad = from {
+                    blocked[t + MOVE_TICKS].get(column)
+                }
+                else {
+                    blocked_moving[t] & (1 << column) != 0
+                };
+
+                if (hit) {
+                    return 0;
+                }
+
+                1 << column
+            },
+            LaneTimeline::River(xs) => {
+                // Jumping onto a lillipad from a road we are checked against the tile we jumped from.
+                if let From::Road(source_lane) = from {
+                    if let LaneTimeline::Road { blocked : source_blocked, .. } = &self.timelines[source_lane] {
+                        let column = x.round() as i32;
+                        if ((1..MOVE_TICKS).any(|dt| source_blocked[t + dt].get(column))) {
+                            return 0;
+                        }
+                    }
+                }
+
+                match river::closest_lillipad(&xs[t], x) {
+                    Some(id) if id < 64 && !lillipad_offscreen(xs[t + MOVE_TICKS][id]) => 1 << id,
+                    _ => 0,
+                }
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::map::level::LevelLane;
+
+    const ROUND : u8 = 1;
+
+    fn open_bank() -> BitMap {
+        let mut bank = BitMap::default();
+        for x in 0..SCREEN_SIZE {
+            bank.set_bit(x);
+        }
+        bank
+    }
+
+    fn roads(seed : u32, y0 : i32, lanes : i32) -> Vec<Road> {
+        (0..lanes).map(|i| Road::new(seed, ROUND, y0 - i, i % 2 == 0, 0, 0.0)).collect()
+    }
+
+    fn rivers(seed : u32, y0 : i32, lanes : i32) -> Vec<River> {
+        (0..lanes).map(|i| River::new(seed, ROUND, y0 - i, false, 0, 0.0)).collect()
+    }
+
+    // Evenly spaced single obstacles on a two second loop.
+    pub(crate) fn even_lane(count : usize) -> LevelLane {
+        LevelLane {
+            inverted : false,
+            period_us : 2_000_000.0,
+            r_width : 0.25,
+            group_length : 1,
+            gaps : vec![1.0 / count as f64; count],
+        }
+    }
+
+    #[test]
+    fn crossable_roads() {
+        for seed in 0..10 {
+            let roads = roads(seed, 0, 4);
+            let lanes : Vec<Lane> = roads.iter().map(Lane::Road).collect();
+            assert!(verify_section(&lanes, open_bank(), open_bank()), "seed {}", seed);
+        }
+    }
+
+    #[test]
+    fn crossable_rivers() {
+        for seed in 0..10 {
+            let rivers = rivers(seed, 0, 3);
+            let lanes : Vec<Lane> = rivers.iter().map(Lane::River).collect();
+            assert!(verify_section(&lanes, open_bank(), open_bank()), "seed {}", seed);
+        }
+    }
+
+    #[test]
+    fn crossable_road_into_river() {
+        for seed in 0..10 {
+            let roads = roads(seed, 0, 2);
+            let rivers = rivers(seed, -2, 2);
+            let lanes : Vec<Lane> = roads.iter().map(Lane::Road)
+                .chain(rivers.iter().map(Lane::River))
+                .collect();
+            assert!(verify_section(&lanes, open_bank(), open_bank()), "seed {}", seed);
+        }
+    }
+
+    #[test]
+    fn no_bank() {
+        let roads = roads(0, 0, 2);
+        let rivers = rivers(0, -2, 2);
+        let lanes : Vec<Lane> = roads.iter().map(Lane::Road)
+            .chain(rivers.iter().map(Lane::River))
+            .collect();
+
+        assert!(!verify_section(&lanes, BitMap::default(), open_bank()));
+        assert!(!verify_section(&lanes, open_bank(), BitMap::default()));
+        assert!(!verify_section(&lanes[2..], open_bank(), BitMap::default()));
+    }
+
+    #[test]
+    fn no_section() {
+        assert!(verify_section(&[], BitMap::default(), BitMap::default()));
+    }
+
+    #[test]
+    fn blocked_roads() {
+        // Each lane has gaps you can wait in, but with the same period going the same way
+        // the cars on the lane above always arrive as you would jump into them.
+        let lower = Road::from_level(0, &even_lane(6));
+        let upper = Road::from_level(-1, &even_lane(6));
+
+        assert!(verify_section(&[Lane::Road(&lower)], open_bank(), open_bank()));
+        assert!(verify_section(&[Lane::Road(&upper)], open_bank(), open_bank()));
+        assert!(!verify_section(&[Lane::Road(&lower), Lane::Road(&upper)], open_bank(), open_bank()));
+    }
+
+    #[test]
+    fn river_without_lillipads() {
+        let rivers = rivers(0, 0, 2);
+        let empty = River::from_level(-2, &even_lane(0));
+        let lanes : Vec<Lane> = rivers.iter().map(Lane::River)
+            .chain(std::iter::once(Lane::River(&empty)))
+            .collect();
+
+        assert!(verify_section(&lanes[..2], open_bank(), open_bank()));
+        assert!(!verify_section(&lanes, open_bank(), open_bank()));
+    }
+}
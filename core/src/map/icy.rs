@@ -99,6 +99,27 @@ pub struct BlockMap {
 }
 
 impl BlockMap {
+    // Rows drawn as ASCII, 'X' is a block and anything else is ice.
+    // Each row covers the full width, walls included.
+    pub fn from_ascii(rows: &[&str], wall_width: i32) -> Self {
+        BlockMap {
+            full_width: rows.first().map(|x| x.len() as i32).unwrap_or(0),
+            wall_width,
+            inner: rows.iter().map(|x| Self::parse_ascii_row(x)).collect(),
+        }
+    }
+
+    pub fn parse_ascii_row(row: &str) -> BitMap {
+        let mut row_map = BitMap::default();
+        for (i, c) in row.chars().enumerate() {
+            if c == 'X' || c == 'x' {
+                row_map.set_bit(i as i32);
+            }
+        }
+
+        row_map
+    }
+
     #[inline]
     pub fn set(&mut self, pos: CoordPos, val: bool) {
         self.inner[pos.y as usize].set(pos.x, val)
@@ -787,27 +808,10 @@ mod tests {
     }
 
     fn generate_map(rows: &[&str]) -> BlockMap {
-        let full_width = (rows[0].len() + 2) as i32;
-        let wall_width = 1;
-
-        let mut inner = Vec::new();
-
-        for row in rows {
-            let mut row_map = BitMap::default();
-            for (i, c) in row.chars().enumerate() {
-                if c == 'X' || c == 'x' {
-                    row_map.set_bit(i as i32 + wall_width);
-                }
-            }
-
-            inner.push(row_map)
-        }
-
-        BlockMap {
-            full_width,
-            wall_width,
-            inner
-        }
+        // Pad with a wall either side
+        let padded: Vec<String> = rows.iter().map(|x| format!(" {} ", x)).collect();
+        let padded: Vec<&str> = padded.iter().map(|x| x.as_str()).collect();
+        BlockMap::from_ascii(&padded, 1)
     }
 }
 
@@ -0,0 +1,407 @@
+use serde::{Deserialize, Serialize};
+
+use crate::bitmap::BitMap;
+use crate::SCREEN_SIZE;
+
+use super::icy::BlockMap;
+use super::obstacle_row::Obstacle;
+
+// Hand authored levels, used in place of procedural generation for every round.
+// The rows start above the starting barrier, past the top we carry on with plain path.
+//
+// Text format, one row per line with the top of the level first so it reads like the screen.
+// Blank lines and lines starting with '#' are skipped.
+//
+//   name Road tutorial
+//   path 4
+//   road < period=6 width=0.2 gaps=0.3,0.3,0.4
+//   road > gaps=0.5,0.5
+//   river > length=3 gaps=0.25,0.25,0.25,0.25
+//   icy 4 .......X....X.......
+//   bushes 3
+//
+// Roads and rivers take a direction, '<' or '>', then optional settings
+//   period  seconds for the row to loop
+//   width   how much of the loop is on screen, see ObstacleRow
+//   length  lillipads in each group, rivers only
+//   gaps    distance from the start of each car or group of lillipads to the next, as a
+//           fraction of the loop. They can't add up to more than 1, anything left over goes
+//           on the last gap.
+//
+// Icy rows are drawn across the full width of the screen, 'X' is a block.
+
+const ROAD_PERIOD_SECS : f64 = 8.5;
+const ROAD_WIDTH : f64 = 0.2;
+const RIVER_PERIOD_SECS : f64 = 18.0;
+const RIVER_WIDTH : f64 = 0.3;
+const RIVER_GROUP_LENGTH : u32 = 3;
+
+// Levels that ship with the game, see levels/. GameConfig names them so it stays small.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum LevelPreset {
+    Tutorial,
+    Gauntlet,
+}
+
+pub const LEVEL_PRESET_NAMES : [&str; 2] = ["tutorial", "gauntlet"];
+
+impl LevelPreset {
+    pub fn from_name(name : &str) -> Option<Self> {
+        match name {
+            "tutorial" => Some(LevelPreset::Tutorial),
+            "gauntlet" => Some(LevelPreset::Gauntlet),
+            _ => None,
+        }
+    }
+
+    fn text(self) -> &'static str {
+        match self {
+            LevelPreset::Tutorial => include_str!("../../levels/tutorial.level"),
+            LevelPreset::Gauntlet => include_str!("../../levels/gauntlet.level"),
+        }
+    }
+
+    // Every preset is parsed in the tests.
+    pub fn level(self) -> Level {
+        Level::parse(self.text()).unwrap()
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Level {
+    pub name : String,
+    // Bottom to top
+    pub rows : Vec<LevelRow>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub enum LevelRow {
+    Path { wall_width : u32 },
+    Bushes { wall_width : u32 },
+    Icy { wall_width : u32, blocks : BitMap },
+    Road(LevelLane),
+    River(LevelLane),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct LevelLane {
+    pub inverted : bool,
+    pub period_us : f64,
+    pub r_width : f64,
+    pub group_length : u32,
+    pub gaps : Vec<f64>,
+}
+
+impl LevelLane {
+    // Positions around the loop, each group is packed with obstacles one tile apart.
+    pub fn obstacles(&self) -> Vec<Obstacle> {
+        let mut obstacles = Vec::with_capacity(self.gaps.len() * self.group_length as usize);
+        let mut group_start = 0.0;
+
+        for (group_id, gap) in self.gaps.iter().enumerate() {
+            for i in 0..self.group_length {
+                obstacles.push(Obstacle {
+                    id : obstacles.len() as u32,
+                    group_id : group_id as u32 + 1,
+                    x : group_start + i as f64 * self.tile_width(),
+                });
+            }
+
+            group_start += gap;
+        }
+
+        obstacles
+    }
+
+    // One tile on screen as a fraction of the loop.
+    fn tile_width(&self) -> f64 {
+        2.0 * self.r_width / SCREEN_SIZE as f64
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LevelError {
+    pub line : usize,
+    pub message : String,
+}
+
+impl std::fmt::Display for LevelError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl Level {
+    pub fn parse(text : &str) -> Result<Self, LevelError> {
+        let mut name = String::new();
+        let mut rows = Vec::new();
+
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if (line.is_empty() || line.starts_with('#')) {
+                continue;
+            }
+
+            let err = |message : String| LevelError { line : i + 1, message };
+            let (keyword, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+            let rest = rest.trim();
+
+            match keyword {
+                "name" => {
+                    name = rest.to_owned();
+                },
+                "path" => {
+                    rows.push(LevelRow::Path { wall_width : parse_wall_width(rest).map_err(err)? });
+                },
+                "bushes" => {
+                    rows.push(LevelRow::Bushes { wall_width : parse_wall_width(rest).map_err(err)? });
+                },
+                "icy" => {
+                    let (wall_width, blocks) = rest.split_once(char::is_whitespace)
+                        .ok_or(err("Expected 'icy <wall width> <row>'".to_owned()))?;
+                    let blocks = blocks.trim();
+                    if (blocks.chars().count() != SCREEN_SIZE as usize) {
+                        return Err(err(format!("Icy row should be {} wide, got {}", SCREEN_SIZE, blocks.chars().count())));
+                    }
+
+                    rows.push(LevelRow::Icy {
+                        wall_width : parse_wall_width(wall_width).map_err(err)?,
+                        blocks : BlockMap::parse_ascii_row(blocks),
+                    });
+                },
+                "road" => {
+                    rows.push(LevelRow::Road(parse_lane(rest, ROAD_PERIOD_SECS, ROAD_WIDTH, 1, false).map_err(err)?));
+                },
+                "river" => {
+                    rows.push(LevelRow::River(parse_lane(rest, RIVER_PERIOD_SECS, RIVER_WIDTH, RIVER_GROUP_LENGTH, true).map_err(err)?));
+                },
+                _ => {
+                    return Err(err(format!("Unknown row type '{}'", keyword)));
+                }
+            }
+        }
+
+        // Written top first
+        rows.reverse();
+
+        Ok(Level {
+            name,
+            rows,
+        })
+    }
+}
+
+fn parse_wall_width(s : &str) -> Result<u32, String> {
+    let wall_width : u32 = s.trim().parse().map_err(|e| format!("Bad wall width '{}': {}", s, e))?;
+    if (wall_width as i32 >= SCREEN_SIZE / 2 - 1) {
+        return Err(format!("Wall width {} leaves no room", wall_width));
+    }
+
+    Ok(wall_width)
+}
+
+fn parse_lane(s : &str, period_secs : f64, r_width : f64, group_length : u32, allow_group_length : bool) -> Result<LevelLane, String> {
+    let mut parts = s.split_whitespace();
+    let inverted = match parts.next() {
+        Some(">") => false,
+        Some("<") => true,
+        x => return Err(format!("Expected a direction '<' or '>', got {:?}", x)),
+    };
+
+    let mut lane = LevelLane {
+        inverted,
+        period_us : period_secs * 1_000_000.0,
+        r_width,
+        group_length,
+        gaps : Vec::new(),
+    };
+
+    for part in parts {
+        let (key, value) = part.split_once('=').ok_or(format!("Expected key=value, got '{}'", part))?;
+        let parse_f64 = |x : &str| x.parse::<f64>().map_err(|e| format!("Bad {} '{}': {}", key, x, e));
+        match key {
+            "period" => lane.period_us = parse_f64(value)? * 1_000_000.0,
+            "width" => lane.r_width = parse_f64(value)?,
+            "length" if allow_group_length => {
+                lane.group_length = value.parse().map_err(|e| format!("Bad length '{}': {}", value, e))?;
+            },
+            "gaps" => {
+                lane.gaps = value.split(',').map(parse_f64).collect::<Result<_, _>>()?;
+            },
+            _ => return Err(format!("Unknown setting '{}'", key)),
+        }
+    }
+
+    if (lane.period_us < 1_000_000.0) {
+        return Err(format!("Period {}s is too fast", lane.period_us / 1_000_000.0));
+    }
+
+    if (lane.r_width <= 0.0 || lane.r_width > 0.5) {
+        return Err(format!("Width {} should be in (0, 0.5]", lane.r_width));
+    }
+
+    if (lane.gaps.is_empty()) {
+        return Err("Expected gaps=..".to_owned());
+    }
+
+    let total : f64 = lane.gaps.iter().sum();
+    if (total > 1.0 + 1e-9) {
+        return Err(format!("Gaps add up to {:.3}, more than the loop", total));
+    }
+
+    let group_width = lane.group_length as f64 * lane.tile_width();
+    if (lane.gaps.iter().any(|gap| *gap < group_width)) {
+        return Err(format!("Gaps must be at least {:.3} to fit a group", group_width));
+    }
+
+    Ok(lane)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map::{Map, RowType};
+
+    const LEVEL : &str = "
+        name Test level
+        # Top
+        path 2
+        river < length=2 gaps=0.3,0.3
+        road > period=5 width=0.25 gaps=0.5,0.4
+        icy 3 ........X...........
+        bushes 3
+        path 4
+        ";
+
+    #[test]
+    fn parse() {
+        let level = Level::parse(LEVEL).unwrap();
+        assert_eq!(level.name, "Test level");
+        assert_eq!(level.rows.len(), 6);
+        assert_eq!(level.rows[0], LevelRow::Path { wall_width : 4 });
+        assert_eq!(level.rows[5], LevelRow::Path { wall_width : 2 });
+
+        match &level.rows[2] {
+            LevelRow::Icy { wall_width, blocks } => {
+                assert_eq!(*wall_width, 3);
+                assert!(blocks.get(8));
+                assert!(!blocks.get(7));
+            },
+            x => panic!("Expected icy row, got {:?}", x),
+        }
+
+        match &level.rows[3] {
+            LevelRow::Road(lane) => {
+                assert!(!lane.inverted);
+                assert_eq!(lane.period_us, 5_000_000.0);
+                assert_eq!(lane.obstacles().len(), 2);
+            },
+            x => panic!("Expected road, got {:?}", x),
+        }
+
+        match &level.rows[4] {
+            LevelRow::River(lane) => {
+                assert!(lane.inverted);
+                assert_eq!(lane.obstacles().len(), 4);
+            },
+            x => panic!("Expected river, got {:?}", x),
+        }
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert_eq!(Level::parse("path 4\nroad ^ gaps=0.5").unwrap_err().line, 2);
+        assert!(Level::parse("lava").is_err());
+        assert!(Level::parse("path 20").is_err());
+        assert!(Level::parse("icy 2 ..X..").is_err());
+        assert!(Level::parse("road > gaps=0.5,0.6").is_err());
+        assert!(Level::parse("road > length=2 gaps=0.5").is_err());
+        assert!(Level::parse("road >").is_err());
+    }
+
+    // Sections have to be crossable, the same as generated ones.
+    #[test]
+    fn presets() {
+        use crate::map::crossing::{verify_section, Lane};
+        use crate::map::river::River;
+        use crate::map::road::Road;
+
+        fn bank(row : &LevelRow) -> BitMap {
+            match row {
+                LevelRow::Path { wall_width } => crate::map::bank_columns(&RowType::Path { wall_width : *wall_width }),
+                LevelRow::Icy { wall_width, blocks } => BitMap {
+                    inner : crate::map::bank_columns(&RowType::Path { wall_width : *wall_width }).inner & !blocks.inner,
+                },
+                x => panic!("Put a path or icy row either side of a section, got {:?}", x),
+            }
+        }
+
+        for name in LEVEL_PRESET_NAMES {
+            let level = LevelPreset::from_name(name).unwrap().level();
+            assert!(!level.name.is_empty());
+            assert!(matches!(level.rows.last(), Some(LevelRow::Path { .. })), "{} should end on a path", name);
+
+            let mut start = 0;
+            while (start < level.rows.len()) {
+                let is_lane = |row : &LevelRow| matches!(row, LevelRow::Road(_) | LevelRow::River(_));
+                if (!is_lane(&level.rows[start])) {
+                    start += 1;
+                    continue;
+                }
+
+                let end = start + level.rows[start..].iter().take_while(|x| is_lane(x)).count();
+                let section = &level.rows[start..end];
+
+                let roads : Vec<Option<Road>> = section.iter().enumerate().map(|(i, row)| match row {
+                    LevelRow::Road(lane) => Some(Road::from_level(-(i as i32), lane)),
+                    _ => None,
+                }).collect();
+                let rivers : Vec<Option<River>> = section.iter().enumerate().map(|(i, row)| match row {
+                    LevelRow::River(lane) => Some(River::from_level(-(i as i32), lane)),
+                    _ => None,
+                }).collect();
+                let lanes : Vec<Lane> = roads.iter().zip(&rivers).map(|x| match x {
+                    (Some(road), _) => Lane::Road(road),
+                    (_, Some(river)) => Lane::River(river),
+                    _ => unreachable!(),
+                }).collect();
+
+                let bank_below = if (start == 0) {
+                    crate::map::bank_columns(&RowType::StartingBarrier)
+                }
+                else {
+                    bank(&level.rows[start - 1])
+                };
+
+                assert!(verify_section(&lanes, bank_below, bank(&level.rows[end])), "{} rows {}..{} can't be crossed", name, start, end);
+                start = end;
+            }
+        }
+
+        assert!(LevelPreset::from_name("nope").is_none());
+    }
+
+    #[test]
+    fn load_into_map() {
+        let level = Level::parse(LEVEL).unwrap();
+        let map = Map::from_level("level_test", level);
+
+        // Above the starting barrier
+        let y0 = 20 - 9;
+
+        for round in [1, 2] {
+            assert!(matches!(map.get_row(round, y0).row_type, RowType::Path { wall_width : 4 }));
+            assert!(matches!(map.get_row(round, y0 - 1).row_type, RowType::Bushes(_)));
+            assert!(matches!(map.get_row(round, y0 - 2).row_type, RowType::IcyRow(_)));
+            assert!(matches!(map.get_row(round, y0 - 3).row_type, RowType::Road(_)));
+            assert!(matches!(map.get_row(round, y0 - 4).row_type, RowType::River(_)));
+            assert!(matches!(map.get_row(round, y0 - 5).row_type, RowType::Path { wall_width : 2 }));
+
+            // Carry on with the last path
+            assert!(matches!(map.get_row(round, y0 - 50).row_type, RowType::Path { wall_width : 2 }));
+        }
+
+        assert_eq!(map.get_cars(1, 0).len(), 2);
+        assert_eq!(map.get_lillipads(1, 0).len(), 4);
+    }
+}
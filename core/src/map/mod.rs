@@ -10,12 +10,21 @@ pub mod river;
 pub mod obstacle_row;
 pub mod bushes;
 pub mod icy;
+pub mod crossing;
+pub mod profile;
+pub mod level;
+pub mod pickup;
 
 use road::Road;
+use crossing::Lane;
+use profile::MapGenProfile;
+use level::{Level, LevelRow};
 use river::{River};
 use obstacle_row::{ObstaclePublic, ObstacleRowDescr};
 use bushes::BushDescr;
+use pickup::{Pickup, PickupPublic};
 
+use crate::bitmap::BitMap;
 use crate::crossy_ruleset::{CrossyRulesetFST, LobbyState, RulesState};
 use crate::game::CoordPos;
 use crate::SCREEN_SIZE;
@@ -55,6 +64,7 @@ pub enum RowType {
   LobbyMain,
   LobbyRiverBankLower,
   IcyRow(IcyDescr),
+  FinishLine,
 }
 
 impl RowType {
@@ -77,13 +87,26 @@ pub struct PathDescr {
     pub wall_width : u32,
 }
 
+const STANDS_WIDTH : i32 = 6;
+
+// Times we regenerate a road or river section that can't be crossed before giving up
+// and putting a path there instead.
+const CROSSING_GEN_ATTEMPTS : u32 = 8;
+
+// Path rows below a finish line, the tallest section is a 13 row icy one.
+const FINISH_CLEARANCE_ROWS : i32 = 14;
+
 #[derive(Debug)]
 struct MapRound {
     seed : u32,
     round_id : u8,
+    profile : MapGenProfile,
+    // Rows came from a Level, past the end we only add paths.
+    authored : bool,
     gen_state_wall_width : i32,
     roads : Vec<(i32, Road)>,
     rivers : Vec<(i32, River)>,
+    pickups : Vec<(i32, Pickup)>,
     rows : VecDeque<Row>,
 }
 
@@ -91,25 +114,53 @@ struct MapRound {
 pub struct MapInner {
     // todo better structure
     seed : u32,
+    profile : MapGenProfile,
+    level : Option<Level>,
     rounds : Vec<MapRound>,
 }
 
 #[derive(Clone, Debug)]
 pub struct Map{
    seed : u32,
+   profile : MapGenProfile,
    inner : std::sync::Arc<std::sync::Mutex<MapInner>>,
 }
 
 impl Map {
+    pub fn seed_from_key<T : Hash>(seed_key : T) -> u32 {
+        FroggyRand::new(0).gen(seed_key) as u32
+    }
+
     pub fn new<T : Hash>(seed_key : T) -> Self {
-        let seed = FroggyRand::new(0).gen(seed_key) as u32;
-        Self::exact_seed(seed)
+        Self::exact_seed(Self::seed_from_key(seed_key))
     }
 
     pub fn exact_seed(seed : u32) -> Self {
+        Self::exact_seed_with_profile(seed, MapGenProfile::default())
+    }
+
+    pub fn new_with_profile<T : Hash>(seed_key : T, profile : MapGenProfile) -> Self {
+        Self::exact_seed_with_profile(Self::seed_from_key(seed_key), profile)
+    }
+
+    pub fn exact_seed_with_profile(seed : u32, profile : MapGenProfile) -> Self {
         Self {
             seed,
-            inner: std::sync::Arc::new(std::sync::Mutex::new(MapInner::new(seed))),
+            profile,
+            inner: std::sync::Arc::new(std::sync::Mutex::new(MapInner::new(seed, profile, None))),
+        }
+    }
+
+    // Every round uses the level instead of generating rows.
+    pub fn from_level<T : Hash>(seed_key : T, level : Level) -> Self {
+        Self::exact_seed_from_level(Self::seed_from_key(seed_key), MapGenProfile::default(), level)
+    }
+
+    pub fn exact_seed_from_level(seed : u32, profile : MapGenProfile, level : Level) -> Self {
+        Self {
+            seed,
+            profile,
+            inner: std::sync::Arc::new(std::sync::Mutex::new(MapInner::new(seed, profile, Some(level)))),
         }
     }
 
@@ -117,6 +168,10 @@ impl Map {
         self.seed
     }
 
+    pub fn get_profile(&self) -> &MapGenProfile {
+        &self.profile
+    }
+
     /*
     Premature optimisation, add back in if we need
     pub fn update_min_y(&mut self, min_y : i32) {
@@ -144,6 +199,22 @@ impl Map {
         guard.get(round).get_lillipads(time_us)
     }
 
+    pub fn get_pickup(&self, round : u8, y : i32) -> Option<Pickup> {
+        let mut guard = self.inner.lock().unwrap();
+        guard.get_mut(round).generate_to_y(RowId::from_y(y));
+        guard.get(round).pickups.iter().find(|(pickup_y, _)| *pickup_y == y).map(|(_, pickup)| *pickup)
+    }
+
+    // Every pickup from screen_y down, taken or not.
+    pub fn get_pickups(&self, round : u8, screen_y : i32) -> Vec<PickupPublic> {
+        let mut guard = self.inner.lock().unwrap();
+        guard.get_mut(round).generate_to_y(RowId::from_y(screen_y));
+        guard.get(round).pickups.iter()
+            .filter(|(y, _)| *y >= screen_y)
+            .map(|(y, pickup)| PickupPublic { x : pickup.x, y : *y, kind : pickup.kind })
+            .collect()
+    }
+
     pub fn collides_car(&self, time_us : u32, round : u8, pos : CoordPos) -> bool {
         let mut guard = self.inner.lock().unwrap();
         guard.get_mut(round).generate_to_y(RowId::from_y(pos.y));
@@ -272,14 +343,16 @@ impl Map {
 }
 
 impl MapInner {
-    fn new(seed : u32) -> Self {
+    fn new(seed : u32, profile : MapGenProfile, level : Option<Level>) -> Self {
         let mut rounds = Vec::with_capacity(8);
 
         // Always set first map seed to zero
-        rounds.push(MapRound::new(0, 0));
+        rounds.push(MapRound::new(0, 0, profile, None));
 
         Self {
             seed,
+            profile,
+            level,
             rounds,
         }
     }
@@ -287,7 +360,7 @@ impl MapInner {
     fn gen_to(&mut self, i : usize) {
         while i >= self.rounds.len() {
             let rid = self.rounds.len() as u8;
-            self.rounds.push(MapRound::new(self.seed, rid));
+            self.rounds.push(MapRound::new(self.seed, rid, self.profile, self.level.as_ref()));
         }
     }
 
@@ -305,13 +378,16 @@ impl MapInner {
 }
 
 impl MapRound {
-    fn new(seed : u32, round_id : u8) -> Self {
+    fn new(seed : u32, round_id : u8, profile : MapGenProfile, level : Option<&Level>) -> Self {
         let mut round = Self {
             seed,
             round_id,
+            profile,
+            authored : false,
             gen_state_wall_width : 0,
             roads : Vec::with_capacity(24),
             rivers : Vec::with_capacity(24),
+            pickups : Vec::with_capacity(8),
             rows : VecDeque::with_capacity(64),
         };
 
@@ -320,6 +396,10 @@ impl MapRound {
         }
         else {
             round.initial_generate();
+
+            if let Some(level) = level {
+                round.load_level(level);
+            }
         }
 
         round
@@ -369,6 +449,58 @@ impl MapRound {
         })
     }
 
+    fn load_level(&mut self, level : &Level) {
+        self.authored = true;
+
+        for level_row in &level.rows {
+            let row_id = RowId(self.rows.front().unwrap().row_id.0 + 1);
+            let y = row_id.to_y();
+
+            let row_type = match level_row {
+                LevelRow::Path { wall_width } => {
+                    self.gen_state_wall_width = *wall_width as i32;
+                    RowType::Path { wall_width : *wall_width }
+                },
+                LevelRow::Bushes { wall_width } => {
+                    self.gen_state_wall_width = *wall_width as i32;
+                    RowType::Bushes(BushDescr {
+                        path_descr : PathDescr { wall_width : *wall_width },
+                        seed : self.seed,
+                        y,
+                    })
+                },
+                LevelRow::Icy { wall_width, blocks } => {
+                    self.gen_state_wall_width = *wall_width as i32;
+                    RowType::IcyRow(IcyDescr {
+                        path_descr : PathDescr { wall_width : *wall_width },
+                        seed : self.seed,
+                        y,
+                        blocks : *blocks,
+                    })
+                },
+                LevelRow::Road(lane) => {
+                    self.roads.push((y, Road::from_level(y, lane)));
+                    RowType::Road(ObstacleRowDescr {
+                        seed : self.seed,
+                        inverted : lane.inverted,
+                    })
+                },
+                LevelRow::River(lane) => {
+                    self.rivers.push((y, River::from_level(y, lane)));
+                    RowType::River(ObstacleRowDescr {
+                        seed : self.seed,
+                        inverted : lane.inverted,
+                    })
+                },
+            };
+
+            self.rows.push_front(Row {
+                row_id,
+                row_type,
+            });
+        }
+    }
+
     fn generate_lobby(&mut self) {
         // One extra in lobby as we dont have the starting barrier
         for i in 0..3 {
@@ -418,8 +550,9 @@ impl MapRound {
         while self.rows.front().map(|row| row_id_target.0 > row.row_id.0).unwrap_or(true) {
             let row_id = RowId(self.rows.front().map(|row| row.row_id.0 + 1).unwrap_or(0));
             let rng = FroggyRand::from_hash((self.seed, self.round_id, row_id));
+            let difficulty = self.profile.difficulty(self.round_id, row_id);
 
-            verbose_log!("Generating at {:?}, y={} | {:?}", row_id, row_id.to_y(), &rng);
+            verbose_log!("Generating at {:?}, y={} difficulty {} | {:?}", row_id, row_id.to_y(), difficulty, &rng);
 
             // Lobby
             if (self.seed == 0) {
@@ -431,13 +564,49 @@ impl MapRound {
                 continue;
             }
 
+            if (self.authored) {
+                self.rows.push_front(Row {
+                    row_id,
+                    row_type: RowType::Path{
+                        wall_width : self.gen_state_wall_width as u32,
+                    },
+                });
+
+                continue;
+            }
+
+            if let Some(finish_y) = self.profile.finish_y {
+                let y = row_id.to_y();
+                if (y == finish_y) {
+                    self.rows.push_front(Row {
+                        row_id,
+                        row_type: RowType::FinishLine,
+                    });
+
+                    continue;
+                }
+
+                // Sections are generated in one go from their bottom row, keep the run up clear
+                // so none of them go through the finish.
+                if (y > finish_y && y <= finish_y + FINISH_CLEARANCE_ROWS) {
+                    self.rows.push_front(Row {
+                        row_id,
+                        row_type: RowType::Path{
+                            wall_width : self.gen_state_wall_width as u32,
+                        },
+                    });
+
+                    continue;
+                }
+            }
+
             // Seed 0 is reserved for lobbies
             // We shouldnt generate any roads / rivers
-            if (self.seed != 0 && rng.gen_unit("gen_feature") < 0.25) {
+            if (self.seed != 0 && rng.gen_unit("gen_feature") < self.profile.feature_chance(difficulty)) {
                 verbose_log!("Generating obtacle row at y={}", row_id.to_y());
 
                 let prev_was_icy = self.rows.front().map(|x| if let RowType::River(_) = x.row_type {true} else {false}).unwrap_or(false);
-                if !prev_was_icy && rng.gen_unit("feature_type") < 0.15
+                if !prev_was_icy && rng.gen_unit("feature_type") < self.profile.icy_chance
                 {
                     //if let Some(new_gen_to) = icy::try_gen_icy_section(rng, row_id, &mut self.rows)
                     if icy::try_gen_icy_section(rng, row_id, &mut self.rows)
@@ -455,57 +624,76 @@ impl MapRound {
                         continue;
                     }
                 }
-                else if (rng.gen_unit("rouda") < 0.5) {
+                else if (rng.gen_unit("rouda") < self.profile.road_chance) {
                     verbose_log!("Generating road");
 
-                    let lanes = *rng.choose("road_lanes", &[1, 2, 3, 4, 5]);
+                    let lanes = self.profile.choose_road_lanes(rng, difficulty);
                     let initial_direction = *rng.choose("road_initial_direction", &[true, false]);
 
                     verbose_log!("lanes {}, initial_direction {}", lanes, initial_direction);
 
-                    for i in 0..lanes {
-                        let rid = RowId(row_id.0 + i);
-                        let y = rid.to_y();
-                        verbose_log!("Adding road at {}", y);
-                        let road = Road::new(self.seed, self.round_id, y, initial_direction);
-                        verbose_log!("Road {:?}", &road);
-                        self.roads.push((y, road));
+                    let verified = self.gen_crossable_section("Road", row_id, |attempt| {
+                        (0..2 * lanes).map(|i| {
+                            let y = RowId(row_id.0 + i).to_y();
+                            let inverted = if (i < lanes) { initial_direction } else { !initial_direction };
+                            (y, Road::new(self.seed, self.round_id, y, inverted, attempt, difficulty))
+                        }).collect()
+                    }, |road| Lane::Road(road));
+
+                    let Some(roads) = verified else {
+                        debug_log!("Giving up on road section at y={}", row_id.to_y());
                         self.rows.push_front(Row {
-                            row_id: rid,
-                            row_type: RowType::Road(ObstacleRowDescr {
-                                seed: self.seed,
-                                inverted: initial_direction,
-                        })});
-                    }
-                    for i in 0..lanes {
-                        let rid = RowId(row_id.0 + lanes + i);
-                        let y = rid.to_y();
-                        verbose_log!("Adding road inverted at {}", y);
-                        let road = Road::new(self.seed, self.round_id, y, !initial_direction);
+                            row_id,
+                            row_type: RowType::Path{
+                                wall_width : self.gen_state_wall_width as u32,
+                            },
+                        });
+                        continue;
+                    };
+
+                    for (i, (y, road)) in roads.into_iter().enumerate() {
+                        let rid = RowId(row_id.0 + i as u32);
+                        let inverted = if ((i as u32) < lanes) { initial_direction } else { !initial_direction };
+                        verbose_log!("Adding road at {} inverted {}", y, inverted);
                         verbose_log!("Road {:?}", &road);
                         self.roads.push((y, road));
                         self.rows.push_front(Row {
                             row_id: rid,
                             row_type: RowType::Road(ObstacleRowDescr {
                                 seed: self.seed,
-                                inverted: !initial_direction,
+                                inverted,
                         })});
                     }
                 }
                 else {
                     verbose_log!("Generating river");
 
-                    let lanes = *rng.choose("river_lanes", &[2, 2, 3, 4]);
+                    let lanes = self.profile.choose_river_lanes(rng, difficulty);
                     let river_direction = *rng.choose("river_direction", &[true, false]);
 
                     verbose_log!("lanes {}, river_direction {}", lanes, river_direction);
 
-                    for i in 0..lanes {
-                        let rid = RowId(row_id.0 + i);
-                        let y = rid.to_y();
+                    let verified = self.gen_crossable_section("River", row_id, |attempt| {
+                        (0..lanes).map(|i| {
+                            let y = RowId(row_id.0 + i).to_y();
+                            (y, River::new(self.seed, self.round_id, y, river_direction, attempt, difficulty))
+                        }).collect()
+                    }, |river| Lane::River(river));
 
+                    let Some(rivers) = verified else {
+                        debug_log!("Giving up on river section at y={}", row_id.to_y());
+                        self.rows.push_front(Row {
+                            row_id,
+                            row_type: RowType::Path{
+                                wall_width : self.gen_state_wall_width as u32,
+                            },
+                        });
+                        continue;
+                    };
+
+                    for (i, (y, river)) in rivers.into_iter().enumerate() {
+                        let rid = RowId(row_id.0 + i as u32);
                         verbose_log!("Adding river at {}", y);
-                        let river = River::new(self.seed, self.round_id, y, river_direction);
                         verbose_log!("River {:?}", &river);
                         self.rivers.push((y, river));
                         self.rows.push_front(Row {
@@ -518,16 +706,16 @@ impl MapRound {
                 }
             }
             else {
-                const WALL_WIDTH_MAX : i32 = 6;
-                const WALL_WIDTH_MIN : i32 = 1;
-                let new_wall_width = self.gen_state_wall_width + rng.choose("wall_width", &[-1, -1, 0, 0, 0, 0, 1, 1, 1]);
-                self.gen_state_wall_width = new_wall_width.min(WALL_WIDTH_MAX).max(WALL_WIDTH_MIN);
+                let new_wall_width = self.gen_state_wall_width + self.profile.choose_wall_step(rng);
+                self.gen_state_wall_width = new_wall_width.min(self.profile.wall_width_max).max(self.profile.wall_width_min);
 
                 let path_descr = PathDescr {
                     wall_width : self.gen_state_wall_width as u32,
                 };
 
-                if (self.seed != 0 && rng.gen_unit("gen_bushes") < 0.25)
+                // Trees in two rows of bushes could wall players in.
+                let prev_was_bushes = matches!(self.rows.front().map(|x| &x.row_type), Some(RowType::Bushes(_)));
+                if (self.seed != 0 && !prev_was_bushes && rng.gen_unit("gen_bushes") < self.profile.bush_chance)
                 {
                     let seed = rng.gen("bush_seed") as u32;
                     self.rows.push_front(Row {
@@ -560,8 +748,66 @@ impl MapRound {
                         },
                     });
                 }
+
+                if let Some(pickup) = pickup::try_gen_pickup(rng, &self.rows.front().unwrap().row_type, self.profile.pickup_chance) {
+                    self.pickups.push((row_id.to_y(), pickup));
+                }
+            }
+        }
+    }
+
+    // Generate a section starting at row_id, each attempt after the first gets a new sub seed.
+    // None if none of them could be crossed.
+    fn gen_crossable_section<T>(&self,
+        name : &str,
+        row_id : RowId,
+        gen : impl Fn(u32) -> Vec<(i32, T)>,
+        lane : impl Fn(&T) -> Lane<'_>) -> Option<Vec<(i32, T)>> {
+        for attempt in 0..CROSSING_GEN_ATTEMPTS {
+            let section = gen(attempt);
+            if self.can_cross(section.iter().map(|(_, x)| lane(x))) {
+                return Some(section);
+            }
+
+            debug_log!("{} section at y={} attempt {} can't be crossed", name, row_id.to_y(), attempt);
+        }
+
+        None
+    }
+
+    // Check a new road or river section going on the front of the map can be crossed.
+    // If it goes straight on top of another section we have to check them together,
+    // it doesn't help that the lower one was crossable if there was a path above it.
+    fn can_cross<'a>(&'a self, new_lanes : impl Iterator<Item = Lane<'a>>) -> bool {
+        let mut lanes = Vec::with_capacity(8);
+        let mut bank_below = None;
+        for row in &self.rows {
+            let y = row.row_id.to_y();
+            match &row.row_type {
+                RowType::Road(_) => {
+                    let (_, road) = self.roads.iter().find(|(road_y, _)| *road_y == y).unwrap();
+                    lanes.push(Lane::Road(road));
+                },
+                RowType::River(_) => {
+                    let (_, river) = self.rivers.iter().find(|(river_y, _)| *river_y == y).unwrap();
+                    lanes.push(Lane::River(river));
+                },
+                row_type => {
+                    bank_below = Some(bank_columns(row_type));
+                    break;
+                }
             }
         }
+
+        // Rows go from the top down.
+        lanes.reverse();
+        lanes.extend(new_lanes);
+
+        // We don't know what comes next, assume a path with the walls as far in as they can get.
+        let wall_width = (self.gen_state_wall_width + 1).min(self.profile.wall_width_max);
+        let bank_above = bank_columns(&RowType::Path { wall_width : wall_width as u32 });
+
+        crossing::verify_section(&lanes, bank_below.unwrap_or(BitMap::default()), bank_above)
     }
 
     fn get_cars(&self, time_us : u32) -> Vec<ObstaclePublic> {
@@ -599,13 +845,30 @@ fn outside_walls(x : i32, wall_width : i32) -> bool {
     x <= wall_width as i32 || x >= (SCREEN_SIZE - 1 - wall_width as i32)
 }
 
+// Where you can stand on a row either side of a road or river section.
+fn bank_columns(row_type : &RowType) -> BitMap {
+    let mut columns = BitMap::default();
+    for x in 0..SCREEN_SIZE {
+        let open = match row_type {
+            RowType::Path { wall_width } => !outside_walls(x, *wall_width as i32),
+            RowType::Bushes(descr) => !outside_walls(x, descr.path_descr.wall_width as i32) && !descr.is_tree(x),
+            RowType::IcyRow(descr) => !outside_walls(x, descr.path_descr.wall_width as i32) && !descr.blocks.get(x),
+            RowType::StartingBarrier | RowType::Stands => !outside_walls(x, STANDS_WIDTH),
+            _ => true,
+        };
+
+        columns.set(x, open);
+    }
+
+    columns
+}
+
 impl Row {
     pub fn solid(&self, _time_us : u32, rule_state : &RulesState, pos : CoordPos) -> bool {
         //debug_log!("Checking {:?} solid, assert value self.row_id.to_y() = {}", pos, self.row_id.to_y());
         assert!(self.row_id.to_y() == pos.y);
         let x = pos.x;
 
-        const STANDS_WIDTH : i32 = 6;
         match &self.row_type {
             RowType::Path{wall_width} => {
                 outside_walls(x, *wall_width as i32)
@@ -616,7 +879,7 @@ impl Row {
                 outside_walls(x, descr.path_descr.wall_width as i32) || descr.blocks.get(x)
             }
             RowType::Bushes(s) => {
-                outside_walls(x, s.path_descr.wall_width as i32)
+                outside_walls(x, s.path_descr.wall_width as i32) || s.is_tree(x)
             }
             RowType::StartingBarrier => {
                 if let CrossyRulesetFST::RoundWarmup(_) = rule_state.fst {
@@ -651,4 +914,42 @@ impl Row {
             _ => None,
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    #[test]
+    fn retries_uncrossable_section() {
+        let round = MapRound::new(1, 1, MapGenProfile::default(), None);
+        let row_id = RowId(round.rows.front().unwrap().row_id.0 + 1);
+
+        // The first attempt can't be crossed, the second can.
+        let attempts = Cell::new(0);
+        let section = round.gen_crossable_section("Road", row_id, |attempt| {
+            attempts.set(attempt + 1);
+            let count = if (attempt == 0) { 6 } else { 4 };
+            (0..2).map(|i| {
+                let y = RowId(row_id.0 + i).to_y();
+                (y, Road::from_level(y, &crossing::tests::even_lane(count)))
+            }).collect()
+        }, |road| Lane::Road(road));
+
+        assert!(section.is_some());
+        assert_eq!(attempts.get(), 2);
+
+        // Give up if none of them can be crossed.
+        let section = round.gen_crossable_section("Road", row_id, |attempt| {
+            attempts.set(attempt + 1);
+            (0..2).map(|i| {
+                let y = RowId(row_id.0 + i).to_y();
+                (y, Road::from_level(y, &crossing::tests::even_lane(6)))
+            }).collect()
+        }, |road| Lane::Road(road));
+
+        assert!(section.is_none());
+        assert_eq!(attempts.get(), CROSSING_GEN_ATTEMPTS);
+    }
+}
@@ -128,6 +128,11 @@ impl ObstacleRow {
         cars
     }
 
+    // Obstacles wrap around so the whole row repeats with this period.
+    pub fn period_us(&self) -> u32 {
+        (1.0 / self.time_scale).round() as u32
+    }
+
     pub fn get_obstacle(&self, time_us : u32, i : usize) -> Obstacle {
         self.obstacles0[i].at_time(self.time_scale * time_us as f64)
     }
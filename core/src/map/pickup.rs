@@ -0,0 +1,56 @@
+use froggy_rand::FroggyRand;
+use serde::{Deserialize, Serialize};
+
+use crate::SCREEN_SIZE;
+
+use super::RowType;
+use super::bushes::BushColumn;
+
+// Something to walk over on a path row, taking it gives you a Powerup.
+// At most one per row so a row's y is enough to say which one has been taken.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub enum PickupKind {
+    SpeedBoost,
+    Shield,
+    SuperPush,
+}
+
+pub const ALL_PICKUP_KINDS : [PickupKind; 3] = [PickupKind::SpeedBoost, PickupKind::Shield, PickupKind::SuperPush];
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Pickup {
+    pub x : i32,
+    pub kind : PickupKind,
+}
+
+// Simple representation to convert to json
+#[derive(Debug, Clone, Serialize)]
+pub struct PickupPublic {
+    pub x : i32,
+    pub y : i32,
+    pub kind : PickupKind,
+}
+
+pub fn try_gen_pickup(rng : FroggyRand, row_type : &RowType, chance : f64) -> Option<Pickup> {
+    if (rng.gen_unit("gen_pickup") >= chance) {
+        return None;
+    }
+
+    // Not in bushes, you wouldn't be able to see it.
+    let open : Vec<i32> = (0..SCREEN_SIZE).filter(|x| {
+        match row_type {
+            RowType::Path { wall_width } => !super::outside_walls(*x, *wall_width as i32),
+            RowType::Bushes(descr) => !super::outside_walls(*x, descr.path_descr.wall_width as i32) && descr.column(*x) == BushColumn::Open,
+            _ => false,
+        }
+    }).collect();
+
+    if (open.is_empty()) {
+        return None;
+    }
+
+    Some(Pickup {
+        x : *rng.choose("pickup_x", &open),
+        kind : *rng.choose("pickup_kind", &ALL_PICKUP_KINDS),
+    })
+}
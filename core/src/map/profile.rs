@@ -0,0 +1,318 @@
+use froggy_rand::FroggyRand;
+use serde::{Deserialize, Serialize};
+use smallvec::SmallVec;
+
+use super::RowId;
+
+// Knobs for MapRound::generate_to_y.
+// All the randomness still comes from the per row rng so the same seed and profile
+// always give the same map on the server and every client.
+//
+// Weight tables are expanded into a table for rng.choose, so
+// road_lane_weights [0, 1, 1, 1, 1, 1] is the same as choosing from [1, 2, 3, 4, 5].
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub struct MapGenProfile {
+    // Chance a row starts an icy, road or river section.
+    pub feature_chance : f64,
+    // Chance a feature is icy.
+    pub icy_chance : f64,
+    // Chance a feature that isn't icy is a road rather than a river.
+    pub road_chance : f64,
+    // Chance a path row has bushes.
+    pub bush_chance : f64,
+
+    // Indexed by lane count, roads get this many lanes in each direction.
+    pub road_lane_weights : [u8; 6],
+    pub river_lane_weights : [u8; 6],
+
+    // Random walk on the path walls, weights for stepping by -1, 0, 1.
+    pub wall_step_weights : [u8; 3],
+    pub wall_width_min : i32,
+    pub wall_width_max : i32,
+
+    #[serde(default)]
+    pub ramp : DifficultyRamp,
+
+    // Race mode, y of the row everyone is running for.
+    #[serde(default)]
+    pub finish_y : Option<i32>,
+
+    // Chance a path or bushes row has a pickup on it.
+    #[serde(default)]
+    pub pickup_chance : f64,
+}
+
+// Rows get harder the further up they are and the later the round, so a round can't go on forever.
+// Difficulty goes from 0 to 1, at 0 rows are generated the same as without a ramp.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub struct DifficultyRamp {
+    // Rows up from the start before distance alone takes us to full difficulty.
+    pub rows_to_max : u32,
+    // Added for each round after the first.
+    pub per_round : f64,
+    pub max : f64,
+
+    // At full difficulty
+    pub extra_feature_chance : f64,
+    pub extra_lane_chance : f64,
+}
+
+impl Default for DifficultyRamp {
+    fn default() -> Self {
+        Self {
+            rows_to_max : 300,
+            per_round : 0.1,
+            max : 1.0,
+            extra_feature_chance : 0.1,
+            extra_lane_chance : 0.5,
+        }
+    }
+}
+
+impl DifficultyRamp {
+    pub const NONE : Self = Self {
+        rows_to_max : 1,
+        per_round : 0.0,
+        max : 0.0,
+        extra_feature_chance : 0.0,
+        extra_lane_chance : 0.0,
+    };
+}
+
+impl Default for MapGenProfile {
+    fn default() -> Self {
+        Self {
+            feature_chance : 0.25,
+            icy_chance : 0.15,
+            road_chance : 0.5,
+            bush_chance : 0.25,
+            road_lane_weights : [0, 1, 1, 1, 1, 1],
+            river_lane_weights : [0, 0, 2, 1, 1, 0],
+            wall_step_weights : [2, 4, 3],
+            wall_width_min : 1,
+            wall_width_max : 6,
+            ramp : DifficultyRamp::default(),
+            finish_y : None,
+            pickup_chance : 0.08,
+        }
+    }
+}
+
+impl MapGenProfile {
+    pub fn difficulty(&self, round_id : u8, row_id : RowId) -> f64 {
+        let distance = row_id.0 as f64 / self.ramp.rows_to_max.max(1) as f64;
+        let round = round_id.saturating_sub(1) as f64 * self.ramp.per_round;
+        (distance + round).min(self.ramp.max).max(0.0)
+    }
+
+    pub fn feature_chance(&self, difficulty : f64) -> f64 {
+        self.feature_chance + difficulty * self.ramp.extra_feature_chance
+    }
+
+    pub fn choose_road_lanes(&self, rng : FroggyRand, difficulty : f64) -> u32 {
+        let lanes = choose_weighted(rng, "road_lanes", &self.road_lane_weights) as u32;
+        lanes + self.extra_lane(rng, difficulty)
+    }
+
+    pub fn choose_river_lanes(&self, rng : FroggyRand, difficulty : f64) -> u32 {
+        let lanes = choose_weighted(rng, "river_lanes", &self.river_lane_weights) as u32;
+        lanes + self.extra_lane(rng, difficulty)
+    }
+
+    fn extra_lane(&self, rng : FroggyRand, difficulty : f64) -> u32 {
+        if (rng.gen_unit("extra_lane") < difficulty * self.ramp.extra_lane_chance) {
+            1
+        }
+        else {
+            0
+        }
+    }
+
+    pub fn choose_wall_step(&self, rng : FroggyRand) -> i32 {
+        choose_weighted(rng, "wall_width", &self.wall_step_weights) as i32 - 1
+    }
+}
+
+fn choose_weighted(rng : FroggyRand, key : &str, weights : &[u8]) -> usize {
+    let mut table : SmallVec<[usize; 16]> = SmallVec::new();
+    for (i, weight) in weights.iter().enumerate() {
+        for _ in 0..*weight {
+            table.push(i);
+        }
+    }
+
+    // Misconfigured profile with every weight zero (or none at all), take the first option
+    // rather than panic mid game.
+    if (table.is_empty()) {
+        return 0;
+    }
+
+    *rng.choose(key, &table)
+}
+
+// Named profiles so GameConfig stays small, it gets sent with every server tick.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
+pub enum MapGenPreset {
+    #[default]
+    Standard,
+    RoadsOnly,
+    IceGauntlet,
+    Calm,
+}
+
+pub const MAP_GEN_PRESET_NAMES : [&str; 4] = ["standard", "roads_only", "ice_gauntlet", "calm"];
+
+impl MapGenPreset {
+    pub fn from_name(name : &str) -> Option<Self> {
+        match name {
+            "standard" => Some(MapGenPreset::Standard),
+            "roads_only" => Some(MapGenPreset::RoadsOnly),
+            "ice_gauntlet" => Some(MapGenPreset::IceGauntlet),
+            "calm" => Some(MapGenPreset::Calm),
+            _ => None,
+        }
+    }
+
+    pub fn profile(self) -> MapGenProfile {
+        match self {
+            MapGenPreset::Standard => MapGenProfile::default(),
+            MapGenPreset::RoadsOnly => MapGenProfile {
+                feature_chance : 0.3,
+                icy_chance : 0.0,
+                road_chance : 1.0,
+                ..Default::default()
+            },
+            MapGenPreset::IceGauntlet => MapGenProfile {
+                feature_chance : 0.35,
+                icy_chance : 1.0,
+                bush_chance : 0.0,
+                ..Default::default()
+            },
+            MapGenPreset::Calm => MapGenProfile {
+                feature_chance : 0.12,
+                icy_chance : 0.0,
+                bush_chance : 0.1,
+                road_lane_weights : [0, 2, 1, 0, 0, 0],
+                river_lane_weights : [0, 0, 1, 0, 0, 0],
+                wall_step_weights : [1, 4, 1],
+                wall_width_max : 4,
+                ramp : DifficultyRamp::NONE,
+                ..Default::default()
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map::{Map, RowType};
+
+    fn expand(weights : &[u8], offset : i32) -> Vec<i32> {
+        weights.iter().enumerate()
+            .flat_map(|(i, w)| std::iter::repeat_n(i as i32 + offset, *w as usize))
+            .collect()
+    }
+
+    #[test]
+    fn standard_matches_original_tables() {
+        // Changing these changes every existing seed.
+        let profile = MapGenPreset::Standard.profile();
+        assert_eq!(expand(&profile.road_lane_weights, 0), vec![1, 2, 3, 4, 5]);
+        assert_eq!(expand(&profile.river_lane_weights, 0), vec![2, 2, 3, 4]);
+        assert_eq!(expand(&profile.wall_step_weights, -1), vec![-1, -1, 0, 0, 0, 0, 1, 1, 1]);
+    }
+
+    #[test]
+    fn choose_weighted_without_weights() {
+        let rng = FroggyRand::new(0);
+        assert_eq!(choose_weighted(rng, "empty", &[]), 0);
+        assert_eq!(choose_weighted(rng, "zeros", &[0, 0, 0]), 0);
+        assert_eq!(choose_weighted(rng, "one", &[0, 0, 1]), 2);
+    }
+
+    #[test]
+    fn preset_names() {
+        for name in MAP_GEN_PRESET_NAMES {
+            assert!(MapGenPreset::from_name(name).is_some(), "{}", name);
+        }
+        assert!(MapGenPreset::from_name("nope").is_none());
+    }
+
+    #[test]
+    fn roads_only() {
+        let map = Map::new_with_profile("roads_only", MapGenPreset::RoadsOnly.profile());
+        let mut roads = 0;
+        for y in (-150..10).rev() {
+            match map.get_row(1, y).row_type {
+                RowType::River(_) | RowType::IcyRow(_) => panic!("Unexpected row at y={}", y),
+                RowType::Road(_) => roads += 1,
+                _ => {},
+            }
+        }
+        assert!(roads > 0);
+    }
+
+    #[test]
+    fn finish_line() {
+        // Busy enough that something would cross the finish without the clearance.
+        let profile = MapGenProfile {
+            feature_chance : 0.6,
+            finish_y : Some(-40),
+            ..Default::default()
+        };
+
+        for seed in ["finish_0", "finish_1", "finish_2", "finish_3"] {
+            let map = Map::new_with_profile(seed, profile);
+            for y in (-60..10).rev() {
+                if let RowType::FinishLine = map.get_row(1, y).row_type {
+                    assert_eq!(y, -40);
+                }
+            }
+            assert!(matches!(map.get_row(1, -40).row_type, RowType::FinishLine));
+            // Nothing runs into it from below
+            assert!(matches!(map.get_row(1, -39).row_type, RowType::Path { .. }));
+        }
+    }
+
+    #[test]
+    fn pickups() {
+        let rules_state = crate::crossy_ruleset::RulesState::new(Default::default());
+        let mut count = 0;
+        for seed in ["pickups_0", "pickups_1", "pickups_2"] {
+            let map = Map::new_with_profile(seed, MapGenPreset::Standard.profile());
+            let again = Map::new_with_profile(seed, MapGenPreset::Standard.profile());
+            for y in (-150..10).rev() {
+                let pickup = map.get_pickup(1, y);
+                assert_eq!(pickup, again.get_pickup(1, y));
+
+                if let Some(pickup) = pickup {
+                    count += 1;
+                    let row = map.get_row(1, y);
+                    assert!(matches!(row.row_type, RowType::Path { .. } | RowType::Bushes(_)), "Pickup on {:?} at y={}", row.row_type, y);
+                    assert!(!row.solid(0, &rules_state, crate::game::CoordPos { x : pickup.x, y }));
+                }
+            }
+        }
+        assert!(count > 0);
+
+        let none = MapGenProfile {
+            pickup_chance : 0.0,
+            ..Default::default()
+        };
+        let map = Map::new_with_profile("pickups_0", none);
+        assert!((-150..10).all(|y| map.get_pickup(1, y).is_none()));
+    }
+
+    #[test]
+    fn difficulty_ramp() {
+        let profile = MapGenPreset::Standard.profile();
+        assert_eq!(profile.difficulty(1, RowId(0)), 0.0);
+        assert!(profile.difficulty(1, RowId(150)) > profile.difficulty(1, RowId(50)));
+        assert!(profile.difficulty(3, RowId(50)) > profile.difficulty(1, RowId(50)));
+        assert_eq!(profile.difficulty(20, RowId(10_000)), profile.ramp.max);
+
+        let calm = MapGenPreset::Calm.profile();
+        assert_eq!(calm.difficulty(20, RowId(10_000)), 0.0);
+    }
+}
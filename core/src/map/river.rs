@@ -2,6 +2,7 @@ use froggy_rand::FroggyRand;
 
 use crate::crossy_ruleset::CrossyRulesetFST;
 use crate::map::obstacle_row::*;
+use crate::map::level::LevelLane;
 use crate::{LillipadId};
 
 #[derive(Debug)]
@@ -14,14 +15,23 @@ const R_WIDTH_MIN : f64 = 0.22;
 const R_WIDTH_MAX : f64 = 0.42;
 const TIME_SCALE : f64 = 18_000_000.0;
 
+// At full difficulty lillipads are this much faster and groups can be this much shorter.
+const DIFFICULTY_SPEED_UP : f64 = 0.35;
+const DIFFICULTY_LENGTH_REDUCTION : f64 = 3.0;
+
 impl River {
-    pub fn new(seed : u32, round : u8, y : i32, inverted : bool) -> Self {
-        let rng = FroggyRand::from_hash((seed, round, y));
+    pub fn new(seed : u32, round : u8, y : i32, inverted : bool, attempt : u32, difficulty : f64) -> Self {
+        let mut rng = FroggyRand::from_hash((seed, round, y));
+        if (attempt > 0) {
+            // Previous attempt couldn't be crossed
+            rng = rng.subrand(attempt);
+        }
 
         let mut obstacles = Vec::with_capacity(16);
         let mut cur = 0.0;
 
-        let length = 3 + (rng.gen_froggy("lillipad_length", 0., 5., 3)) as u32;
+        let length_range = 5. - DIFFICULTY_LENGTH_REDUCTION * difficulty;
+        let length = 3 + (rng.gen_froggy("lillipad_length", 0., length_range, 3)) as u32;
         let r_width = rng.gen_froggy("r_width", R_WIDTH_MIN, R_WIDTH_MAX, 4);
 
         let r = 2.0 * r_width;
@@ -54,7 +64,13 @@ impl River {
         }
 
         River {
-            row : ObstacleRow::new(y, inverted, TIME_SCALE, obstacles, r_width),
+            row : ObstacleRow::new(y, inverted, TIME_SCALE / (1.0 + DIFFICULTY_SPEED_UP * difficulty), obstacles, r_width),
+        }
+    }
+
+    pub fn from_level(y : i32, lane : &LevelLane) -> Self {
+        River {
+            row : ObstacleRow::new(y, lane.inverted, lane.period_us, lane.obstacles(), lane.r_width),
         }
     }
 
@@ -67,37 +83,24 @@ impl River {
             return None;
         }
 
-        let frog_centre = pos.x;
-
-        let mut closest = None;
-        let mut closest_dist = f64::MAX;
-
-        for lillipad in self.row.get_obstacles_onscreen(time_us)
-        {
-            let realised = self.row.realise_obstacle(&lillipad);
-            let dist = (frog_centre - realised).abs();
-
-            if (dist < closest_dist) {
-                closest_dist = dist;
-                closest = Some(lillipad.id);
-            }
-        }
+        let realised = self.get_lillipad_xs(time_us);
+        closest_lillipad(&realised, pos.x).map(|id| LillipadId {
+            y : pos.y,
+            id : id as u8,
+            round_id,
+        })
+    }
 
-        //const MARGIN : f64 = LILLIPAD_WIDTH_TILES / 1.9;
-        const MARGIN : f64 = 0.9;
-        if (closest_dist < MARGIN) {
-            if let Some(id) = closest {
-                let lillipad_id = LillipadId {
-                    y : pos.y,
-                    id : id  as u8,
-                    round_id,
-                };
-
-                return Some(lillipad_id);
-            }
-        }
+    // Screen x of every lillipad, indexed by id.
+    pub fn get_lillipad_xs(&self, time_us : u32) -> Vec<f64> {
+        self.row.get_obstacles_onscreen(time_us)
+            .iter()
+            .map(|x| self.row.realise_obstacle(x))
+            .collect()
+    }
 
-        None
+    pub fn period_us(&self) -> u32 {
+        self.row.period_us()
     }
 
     pub fn get_lillipad_screen_x(&self, time_us : u32, lillipad_id : &LillipadId) -> f64 {
@@ -106,15 +109,33 @@ impl River {
     }
 }
 
-pub fn lobby_raft_at_pos(round_id : u8, pos : crate::PreciseCoords, ruleset_fst: &CrossyRulesetFST) -> Option<LillipadId>
-{
-    // @Hack @Dedup
-    // Copypasted and adapted from lillipad_at_pos
-    let frog_centre = pos.x;
-
+// Index of the lillipad a frog at x would land on.
+pub fn closest_lillipad(realised : &[f64], x : f64) -> Option<usize> {
     let mut closest = None;
     let mut closest_dist = f64::MAX;
 
+    for (id, lillipad_x) in realised.iter().enumerate()
+    {
+        let dist = (x - lillipad_x).abs();
+
+        if (dist < closest_dist) {
+            closest_dist = dist;
+            closest = Some(id);
+        }
+    }
+
+    //const MARGIN : f64 = LILLIPAD_WIDTH_TILES / 1.9;
+    const MARGIN : f64 = 0.9;
+    if (closest_dist < MARGIN) {
+        closest
+    }
+    else {
+        None
+    }
+}
+
+pub fn lobby_raft_at_pos(round_id : u8, pos : crate::PreciseCoords, ruleset_fst: &CrossyRulesetFST) -> Option<LillipadId>
+{
     let raft_pos = if let CrossyRulesetFST::Lobby { raft_pos, .. } = ruleset_fst {
         *raft_pos as f64
     }
@@ -132,29 +153,9 @@ pub fn lobby_raft_at_pos(round_id : u8, pos : crate::PreciseCoords, ruleset_fst:
         raft_pos + 3.0,
     ];
 
-    for (id, realised) in raft_positions.iter().enumerate()
-    {
-        let dist = (frog_centre - realised).abs();
-
-        if (dist < closest_dist) {
-            closest_dist = dist;
-            closest = Some(id);
-        }
-    }
-
-    //const MARGIN : f64 = LILLIPAD_WIDTH_TILES / 1.9;
-    const MARGIN : f64 = 0.9;
-    if (closest_dist < MARGIN) {
-        if let Some(id) = closest {
-            let lillipad_id = LillipadId {
-                y : pos.y,
-                id : id  as u8,
-                round_id,
-            };
-
-            return Some(lillipad_id);
-        }
-    }
-
-    None
-}
\ No newline at end of file
+    closest_lillipad(&raft_positions, pos.x).map(|id| LillipadId {
+        y : pos.y,
+        id : id as u8,
+        round_id,
+    })
+}
@@ -1,5 +1,7 @@
+use crate::bitmap::BitMap;
 use crate::game::CoordPos;
 use crate::map::obstacle_row::*;
+use crate::map::level::LevelLane;
 
 use froggy_rand::FroggyRand;
 
@@ -18,13 +20,26 @@ const MIN_SPAWN_DIST_TILES : f64 = CAR_WIDTH * 0.8;
 const MAX_SPAWN_DIST_TILES : f64 = CAR_WIDTH * 10.5;
 const SQUEEZE_SPAWN_DIST_TILES : f64 = CAR_WIDTH * 3.45;
 
+// At full difficulty cars are this much faster and the view onto the row narrows
+// which packs more cars on screen.
+const DIFFICULTY_SPEED_UP : f64 = 0.5;
+const R_WIDTH_MAX_HARDEST : f64 = 0.2;
+
+// Be a little kind
+const CAR_HIT_MARGIN : f64 = CAR_WIDTH / 2.25;
+
 
 impl Road {
-    pub fn new(seed : u32, round : u8, y : i32, inverted : bool) -> Self {
-        let rng = FroggyRand::from_hash((seed, round, y));
+    pub fn new(seed : u32, round : u8, y : i32, inverted : bool, attempt : u32, difficulty : f64) -> Self {
+        let mut rng = FroggyRand::from_hash((seed, round, y));
+        if (attempt > 0) {
+            // Previous attempt couldn't be crossed
+            rng = rng.subrand(attempt);
+        }
 
 
-        let r_width = rng.gen_froggy("r_width", R_WIDTH_MIN, R_WIDTH_MAX, 4);
+        let r_width_max = R_WIDTH_MAX + (R_WIDTH_MAX_HARDEST - R_WIDTH_MAX) * difficulty;
+        let r_width = rng.gen_froggy("r_width", R_WIDTH_MIN, r_width_max, 4);
 
         let r = r_width * 2.;
         let min_spacing = r * MIN_SPAWN_DIST_TILES / crate::SCREEN_SIZE as f64;
@@ -52,7 +67,13 @@ impl Road {
         }
 
         Road {
-            row : ObstacleRow::new(y, inverted, TIME_SCALE, obstacles, r_width),
+            row : ObstacleRow::new(y, inverted, TIME_SCALE / (1.0 + DIFFICULTY_SPEED_UP * difficulty), obstacles, r_width),
+        }
+    }
+
+    pub fn from_level(y : i32, lane : &LevelLane) -> Self {
+        Road {
+            row : ObstacleRow::new(y, lane.inverted, lane.period_us, lane.obstacles(), lane.r_width),
         }
     }
 
@@ -68,10 +89,8 @@ impl Road {
         let frog_centre = frog_pos.x as f64 + 0.5;
 
         for car in &self.row.get_obstacles_onscreen(time_us) {
-            // Be a little kind
-            const MARGIN : f64 = CAR_WIDTH / 2.25;
             let realised_car = self.row.realise_obstacle(car);
-            if (frog_centre - realised_car).abs() < MARGIN {
+            if (frog_centre - realised_car).abs() < CAR_HIT_MARGIN {
                 debug_log!("Killing, Collided with car {} {:?}", realised_car, frog_pos);
                 return true;
             }
@@ -79,4 +98,23 @@ impl Road {
 
         false
     }
+
+    // Every column collides_car would kill in at this time.
+    pub fn blocked_columns(&self, time_us : u32) -> BitMap {
+        let mut blocked = BitMap::default();
+        for car in &self.row.get_obstacles_onscreen(time_us) {
+            let realised_car = self.row.realise_obstacle(car);
+            for x in 0..crate::SCREEN_SIZE {
+                if (x as f64 + 0.5 - realised_car).abs() < CAR_HIT_MARGIN {
+                    blocked.set_bit(x);
+                }
+            }
+        }
+
+        blocked
+    }
+
+    pub fn period_us(&self) -> u32 {
+        self.row.period_us()
+    }
 }
\ No newline at end of file
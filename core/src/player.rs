@@ -1,7 +1,9 @@
 use serde::{Deserialize, Serialize};
 use crate::crossy_ruleset::{CrossyRulesetFST, RulesState, AliveState};
 use crate::map::Map;
+use crate::map::pickup::PickupKind;
 use crate::game::*;
+use crate::timeline::TICK_INTERVAL_US;
 
 
 #[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
@@ -12,6 +14,40 @@ pub struct PlayerState {
     pub move_cooldown: u32,
 
     pub pos: Pos,
+
+    // From the last pickup we walked over, kept here so rollback replays it with everything else.
+    #[serde(default)]
+    pub powerup : Option<Powerup>,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Copy, Debug)]
+pub enum Powerup {
+    SpeedBoost { remaining_us : u32 },
+    // Lasts until a car hits us.
+    Shield,
+    // Just been hit, long enough for the car to go past.
+    ShieldBroken { remaining_us : u32 },
+    SuperPush { remaining_us : u32 },
+}
+
+impl Powerup {
+    pub fn from_pickup(kind : PickupKind) -> Self {
+        match kind {
+            PickupKind::SpeedBoost => Powerup::SpeedBoost { remaining_us : SPEED_BOOST_DUR },
+            PickupKind::Shield => Powerup::Shield,
+            PickupKind::SuperPush => Powerup::SuperPush { remaining_us : SUPER_PUSH_DUR },
+        }
+    }
+
+    // None once it has run out.
+    fn tick(self, dt_us : u32) -> Option<Self> {
+        match self {
+            Powerup::SpeedBoost { remaining_us } => remaining_us.checked_sub(dt_us).map(|remaining_us| Powerup::SpeedBoost { remaining_us }),
+            Powerup::Shield => Some(Powerup::Shield),
+            Powerup::ShieldBroken { remaining_us } => remaining_us.checked_sub(dt_us).map(|remaining_us| Powerup::ShieldBroken { remaining_us }),
+            Powerup::SuperPush { remaining_us } => remaining_us.checked_sub(dt_us).map(|remaining_us| Powerup::SuperPush { remaining_us }),
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug, PartialOrd, Ord)]
@@ -35,24 +71,39 @@ impl PushInfo {
 pub struct MovingState
 {
     pub remaining_us : u32,
+    // How long the whole move takes, shorter with a speed boost.
+    #[serde(default = "default_move_dur")]
+    pub duration_us : u32,
     pub target : Pos,
     pub push_info : PushInfo,
+
+    // Direction of the move, carried on if we finish on ice.
+    #[serde(default)]
+    pub dir : Option<Input>,
+}
+
+fn default_move_dur() -> u32 {
+    MOVE_DUR
 }
 
 impl MovingState {
     pub fn new(frame_id : u32, target : Pos) -> MovingState {
         MovingState {
             remaining_us : MOVE_DUR,
+            duration_us : MOVE_DUR,
             push_info : PushInfo::empty_at_frame(frame_id),
             target,
+            dir : None,
         }
     }
 
-    pub fn with_push(target : Pos, push_info : PushInfo) -> MovingState {
+    pub fn with_push(target : Pos, dir : Input, push_info : PushInfo) -> MovingState {
         MovingState {
             remaining_us : MOVE_DUR,
+            duration_us : MOVE_DUR,
             target,
             push_info,
+            dir : Some(dir),
         }
     }
 }
@@ -67,6 +118,8 @@ pub struct Push {
     pub id : PlayerId,
     pub pushed_by : PlayerId,
     pub dir : Input,
+    // Tiles, two with a super push.
+    pub distance : u8,
 }
 
 enum TryMovePlayerState {
@@ -82,6 +135,20 @@ enum TryMovePlayerState {
 pub const MOVE_COOLDOWN_MAX: u32 = 1;
 pub const MOVE_DUR: u32 = 7 * (1_000_000 / 60);
 
+pub const BOOSTED_MOVE_DUR : u32 = MOVE_DUR * 3 / 5;
+pub const SPEED_BOOST_DUR : u32 = 5_000_000;
+pub const SUPER_PUSH_DUR : u32 = 8_000_000;
+pub const SHIELD_BROKEN_DUR : u32 = 1_000_000;
+
+// Ticks from starting a move until we can move again.
+// We arrive on the tick remaining_us would go negative, and MOVE_COOLDOWN_MAX is short enough
+// to have passed by then.
+pub const fn move_ticks(move_dur : u32) -> u32 {
+    move_dur / TICK_INTERVAL_US + 1
+}
+
+pub const MOVE_TICKS : u32 = move_ticks(MOVE_DUR);
+
 impl PlayerState {
     pub fn can_move(&self) -> bool {
         if let MoveState::Stationary = self.move_state {
@@ -93,6 +160,8 @@ impl PlayerState {
 
     pub fn tick_iterate(&self, state: &GameState, input: Input, dt_us: u32, pushes : &mut Vec<Push>, map : &Map) -> Self {
         let mut new = self.clone();
+        new.powerup = self.powerup.and_then(|x| x.tick(dt_us));
+
         match new.move_state {
             MoveState::Stationary => {
                 new.move_cooldown = new.move_cooldown.saturating_sub(dt_us);
@@ -118,21 +187,8 @@ impl PlayerState {
                         // rem_ms <= 0 so we add it to the max cooldown
                         new.move_cooldown = MOVE_COOLDOWN_MAX.saturating_sub(leftover_us);
 
-                        if let crate::map::RowType::IcyRow { .. } = map.get_row(state.get_round_id(), new.pos.get_y_grid()).row_type
-                        {
-                            // Moving onto icy row
-                            // Try and continue sliding
-
-                            // For now only tile to tile
-                            if let (Pos::Coord(coord_pos), Pos::Coord(target_coord)) = (self.pos, moving_state.target) {
-                                if let Some(sliding_input) = target_coord.delta_to_input(coord_pos) {
-                                    if sliding_input != Input::None {
-                                        if let Some(moving_state) = new.try_move(sliding_input, state, pushes, map) {
-                                            new.move_state = MoveState::Moving(moving_state);
-                                        }
-                                    }
-                                }
-                            }
+                        if let Some(moving_state) = new.try_slide(&moving_state, state, map) {
+                            new.move_state = MoveState::Moving(moving_state);
                         }
                     },
                 }
@@ -140,7 +196,9 @@ impl PlayerState {
         }
 
         if new.can_move() && input != Input::None {
-            if let Some(moving_state) = new.try_move(input, state, pushes, map) {
+            if let Some(mut moving_state) = new.try_move(input, state, pushes, map) {
+                moving_state.remaining_us = new.move_dur();
+                moving_state.duration_us = moving_state.remaining_us;
                 new.move_state = MoveState::Moving(moving_state);
             }
         }
@@ -148,6 +206,66 @@ impl PlayerState {
         new
     }
 
+    pub fn move_dur(&self) -> u32 {
+        if let Some(Powerup::SpeedBoost { .. }) = self.powerup {
+            BOOSTED_MOVE_DUR
+        }
+        else {
+            MOVE_DUR
+        }
+    }
+
+    pub fn shielded(&self) -> bool {
+        matches!(self.powerup, Some(Powerup::Shield | Powerup::ShieldBroken { .. }))
+    }
+
+    // Run once everyone has moved and been pushed this frame.
+    // Players go in id order so if two land on a pickup on the same frame the lower id gets it.
+    pub fn update_pickups(&self, state : &GameState, map : &Map, taken_pickup : &mut Option<i32>) -> Self {
+        let mut new = self.clone();
+        let Pos::Coord(coord_pos) = self.pos else {
+            return new;
+        };
+
+        let round_id = state.get_round_id();
+        if let Some(pickup) = map.get_pickup(round_id, coord_pos.y) {
+            if (pickup.x == coord_pos.x && state.can_take_pickup(coord_pos.y)) {
+                debug_log!("Player {:?} picked up {:?} at y={}", self.id, pickup.kind, coord_pos.y);
+                new.powerup = Some(Powerup::from_pickup(pickup.kind));
+                *taken_pickup = Some(coord_pos.y);
+            }
+        }
+
+        // Use the shield up here rather than when checking for deaths so it breaks wherever we ended up.
+        if let Some(Powerup::Shield) = new.powerup {
+            if let Some(car_check_pos) = new.car_check_pos() {
+                if map.collides_car(state.time_us, round_id, car_check_pos) {
+                    debug_log!("Player {:?} shield broken", self.id);
+                    new.powerup = Some(Powerup::ShieldBroken { remaining_us : SHIELD_BROKEN_DUR });
+                }
+            }
+        }
+
+        new
+    }
+
+    // When the player is moving between spots be more generous to player
+    // Check for car collisions at the point they are moving to.
+    pub fn car_check_pos(&self) -> Option<CoordPos> {
+        if let MoveState::Moving(moving_state) = &self.move_state {
+            if let Pos::Coord(moving_to_coord_pos) = moving_state.target {
+                return Some(moving_to_coord_pos);
+            }
+        }
+
+        if let Pos::Coord(coord_pos) = self.pos {
+            Some(coord_pos)
+        }
+        else {
+            None
+        }
+    }
+
     pub fn push(&self, push : &Push, state : &GameState, map : &Map) -> Self {
         let mut current_pos = self.pos.clone();
         if let MoveState::Moving(ms) = &self.move_state {
@@ -156,7 +274,19 @@ impl PlayerState {
             }
         }
 
-        let m_new_pos = map.try_apply_input(state.time_us, &state.rules_state, &current_pos, push.dir);
+        let mut m_new_pos = map.try_apply_input(state.time_us, &state.rules_state, &current_pos, push.dir);
+
+        // Keep going as far as we can, stopping short rather than failing the whole push.
+        for _ in 1..push.distance {
+            let Some(pos) = &m_new_pos else {
+                break;
+            };
+
+            match map.try_apply_input(state.time_us, &state.rules_state, pos, push.dir) {
+                Some(further) if !state.space_occupied_with_player(further, Some(self.id)) => m_new_pos = Some(further),
+                _ => break,
+            }
+        }
 
         if let Some(new_pos) = m_new_pos {
             let mut new = self.clone();
@@ -164,7 +294,7 @@ impl PlayerState {
             push_info.pushed_by = Some(push.pushed_by);
 
             // @nocheckin testing
-            let mut moving_state = MovingState::with_push(new_pos, push_info);
+            let mut moving_state = MovingState::with_push(new_pos, push.dir, push_info);
             moving_state.remaining_us = (0.8 * (moving_state.remaining_us as f32)).round() as u32;
 
             new.move_state = MoveState::Moving(moving_state);
@@ -199,10 +329,53 @@ impl PlayerState {
             }
         }
 
-        let mut moving_state = MovingState::with_push(new_pos, push_info);
+        let moving_state = MovingState::with_push(new_pos, input, push_info);
         Some(moving_state)
     }
 
+    // Finished a move onto ice, keep going the same way until something stops us.
+    // Sliding players never push, another player in the way stops the slide the same as a block
+    // and leaving the ice onto any other row ends it.
+    // Pushing someone hands them our momentum so we stop and they slide.
+    fn try_slide(&self, finished : &MovingState, state : &GameState, map : &Map) -> Option<MovingState> {
+        let dir = finished.dir?;
+        if (dir == Input::None || finished.push_info.pushing.is_some()) {
+            return None;
+        }
+
+        let Pos::Coord(coord_pos) = self.pos else {
+            return None;
+        };
+
+        if !matches!(map.get_row(state.get_round_id(), coord_pos.y).row_type, crate::map::RowType::IcyRow(_)) {
+            return None;
+        }
+
+        let new_pos = map.try_apply_input(state.time_us, &state.rules_state, &self.pos, dir)?;
+
+        for (id, other_player) in state.player_states.iter() {
+            if (id == self.id || state.rules_state.fst.get_player_alive(id) != AliveState::Alive) {
+                continue;
+            }
+
+            if (other_player.pos == new_pos) {
+                return None;
+            }
+
+            if let MoveState::Moving(other_moving) = &other_player.move_state {
+                if (other_moving.target == new_pos) {
+                    return None;
+                }
+            }
+        }
+
+        // Keep who pushed us so the push still gets credit at the end of the slide.
+        let mut push_info = PushInfo::empty_at_frame(state.frame_id);
+        push_info.pushed_by = finished.push_info.pushed_by;
+
+        Some(MovingState::with_push(new_pos, dir, push_info))
+    }
+
     fn try_move_player_initial(
         &self,
         dir : Input,
@@ -287,10 +460,12 @@ impl PlayerState {
             TryMovePlayerState::Blocked => None,
             TryMovePlayerState::MoveUnimpeded => Some(PushInfo::empty_at_frame(state.frame_id)),
             TryMovePlayerState::MoveWithPush => {
+                let distance = if let Some(Powerup::SuperPush { .. }) = self.powerup { 2 } else { 1 };
                 pushes.push(Push {
                     id : other.id,
                     pushed_by : self.id,
                     dir,
+                    distance,
                 });
 
                 // Managed to push
@@ -323,10 +498,19 @@ impl PlayerState {
         }
     }
 
+    // Partway through a move of our own that wont finish within the next dt_us, any input now is ignored.
+    pub fn is_mid_move(&self, dt_us : u32) -> bool {
+        match (&self.move_state) {
+            MoveState::Moving(s) => s.push_info.pushed_by.is_none() && s.remaining_us > dt_us,
+            _ => false,
+        }
+    }
+
     pub fn reset_to_pos(&mut self, pos : Pos) {
         self.pos = pos;
         self.move_state = MoveState::Stationary;
         self.move_cooldown = MOVE_COOLDOWN_MAX;
+        self.powerup = None;
     }
 }
 
@@ -342,10 +526,13 @@ pub struct PlayerStatePublic
     pub t_x : f64,
     pub t_y : i32,
     pub remaining_move_dur : u32, 
+    pub move_dur : u32,
     pub move_cooldown: u32,
 
     pub pushing : i32,
     pub pushed_by : i32,
+
+    pub powerup : Option<Powerup>,
 }
 
 impl PlayerState {
@@ -353,6 +540,7 @@ impl PlayerState {
         let mut player_state_public = PlayerStatePublic::default();
 
         player_state_public.id = self.id.0;
+        player_state_public.powerup = self.powerup;
 
         let PreciseCoords{x, y} = map.realise_pos(time_us, &self.pos, ruleset_fst);
         player_state_public.x = x;
@@ -364,6 +552,7 @@ impl PlayerState {
             player_state_public.t_x = t_x;
             player_state_public.t_y = t_y;
             player_state_public.remaining_move_dur = ms.remaining_us;
+            player_state_public.move_dur = ms.duration_us;
             player_state_public.pushing = ms.push_info.pushing.map(|x| x.0 as i32).unwrap_or(-1);
             player_state_public.pushed_by = ms.push_info.pushed_by.map(|x| x.0 as i32).unwrap_or(-1);
         }
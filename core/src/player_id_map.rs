@@ -32,6 +32,17 @@ impl<T> PlayerIdMap<T> {
         vec
     }
 
+    // Raw slots, used by the wire encoding so gaps from players leaving survive a round trip.
+    pub(crate) fn from_slots(inner : Vec<Option<T>>) -> Self {
+        Self {
+            inner,
+        }
+    }
+
+    pub(crate) fn slots(&self) -> &[Option<T>] {
+        &self.inner
+    }
+
     pub fn set(&mut self, id: PlayerId, x : T) {
         //assert!(id.0 != 0);
         let index = id.0 as usize;
@@ -0,0 +1,207 @@
+use serde::{Deserialize, Serialize};
+
+use crate::game::GameState;
+use crate::map::Map;
+use crate::math::V2;
+use crate::player::MoveState;
+use crate::player_id_map::PlayerIdMap;
+use crate::timeline::{RemoteInput, Timeline};
+use crate::Input;
+
+// Client side prediction settings.
+//
+// Input delay holds local inputs back a few frames so they reach the server closer to the frame
+// they are for, a little less responsive for us but fewer corrections on everyone else's screen.
+//
+// Server ticks that would roll us back further than max_rollback_frames aren't resimulated, we
+// take the server's latest state as is.
+//
+// When a rollback moves a player we keep drawing them where they were and blend towards the
+// corrected position, error_correction_rate is the fraction of the error removed each frame.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PredictionSettings {
+    pub input_delay_frames : u32,
+    pub max_rollback_frames : u32,
+    pub error_correction_rate : f32,
+}
+
+impl Default for PredictionSettings {
+    fn default() -> Self {
+        Self {
+            input_delay_frames : 2,
+            max_rollback_frames : 60,
+            error_correction_rate : 0.15,
+        }
+    }
+}
+
+// Earliest frame a server tick changes our history from, None if it agrees with what we predicted.
+pub fn rollback_frame_id(timeline : &Timeline, lkg_state : &GameState, inputs : &[RemoteInput]) -> Option<u32> {
+    let mut rollback_frame_id = None;
+
+    if let Some(local) = timeline.try_get_state(lkg_state.frame_id) {
+        if (local.player_states != lkg_state.player_states || local.rules_state != lkg_state.rules_state) {
+            rollback_frame_id = Some(lkg_state.frame_id);
+        }
+    }
+
+    for input in inputs {
+        if (input.frame_id > timeline.top_state().frame_id) {
+            continue;
+        }
+
+        // Same rule as Timeline::try_propagate_inputs, we only ever fill in inputs we didn't have.
+        let is_new = timeline.try_get_state(input.frame_id)
+            .map(|x| x.player_inputs.get(input.player_id) == Input::None && input.input != Input::None)
+            .unwrap_or(false);
+
+        if (is_new) {
+            let frame_id = input.frame_id.saturating_sub(1);
+            rollback_frame_id = Some(rollback_frame_id.map(|x : u32| x.min(frame_id)).unwrap_or(frame_id));
+        }
+    }
+
+    rollback_frame_id
+}
+
+// Past this many tiles we snap, respawns shouldn't glide across the screen.
+const MAX_ERROR : f32 = 3.0;
+const MIN_ERROR : f32 = 0.01;
+
+#[derive(Debug, Default)]
+pub struct ErrorCorrection {
+    offsets : PlayerIdMap<V2>,
+}
+
+impl ErrorCorrection {
+    // Where players are drawn, part way through any move.
+    pub fn visual_positions(state : &GameState, map : &Map) -> PlayerIdMap<V2> {
+        let mut positions = PlayerIdMap::new();
+        let fst = &state.rules_state.fst;
+
+        for (id, player) in state.player_states.iter() {
+            let pos = map.realise_pos(state.time_us, &player.pos, fst);
+            let mut visual = V2::new(pos.x as f32, pos.y as f32);
+
+            if let MoveState::Moving(moving_state) = &player.move_state {
+                let target = map.realise_pos(state.time_us, &moving_state.target, fst);
+                let t = 1.0 - (moving_state.remaining_us as f32 / moving_state.duration_us as f32).clamp(0.0, 1.0);
+                visual += t * (V2::new(target.x as f32, target.y as f32) - visual);
+            }
+
+            positions.set(id, visual);
+        }
+
+        positions
+    }
+
+    // Positions from before and after applying a server tick.
+    pub fn correct(&mut self, before : &PlayerIdMap<V2>, after : &PlayerIdMap<V2>) {
+        let mut offsets = PlayerIdMap::new();
+
+        for (id, after_pos) in after.iter() {
+            if let Some(before_pos) = before.get(id) {
+                let offset = self.offset(id) + (*before_pos - *after_pos);
+                if (offset.mag() < MAX_ERROR) {
+                    offsets.set(id, offset);
+                }
+            }
+        }
+
+        self.offsets = offsets;
+    }
+
+    pub fn tick(&mut self, rate : f32) {
+        let mut offsets = PlayerIdMap::new();
+        for (id, offset) in self.offsets.iter() {
+            let offset = (1.0 - rate.clamp(0.0, 1.0)) * *offset;
+            if (offset.mag() > MIN_ERROR) {
+                offsets.set(id, offset);
+            }
+        }
+
+        self.offsets = offsets;
+    }
+
+    // Add to the authoritative position to get where to draw.
+    pub fn offset(&self, id : crate::PlayerId) -> V2 {
+        self.offsets.get_copy(id).unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crossy_ruleset::GameConfig;
+    use crate::game::{PlayerId, Pos};
+    use crate::timeline::TICK_INTERVAL_US;
+
+    fn make_timeline() -> Timeline {
+        let mut timeline = Timeline::from_seed(GameConfig::default(), "prediction_test");
+        timeline.add_player(PlayerId(0), Pos::new_coord(7, 12));
+        timeline.add_player(PlayerId(1), Pos::new_coord(10, 12));
+        for _ in 0..20 {
+            timeline.tick(None, TICK_INTERVAL_US);
+        }
+        timeline
+    }
+
+    fn input(frame_id : u32, player_id : u8, input : Input) -> RemoteInput {
+        RemoteInput {
+            time_us : frame_id * TICK_INTERVAL_US,
+            frame_id,
+            input,
+            player_id : PlayerId(player_id),
+        }
+    }
+
+    #[test]
+    fn rollback_depth() {
+        let timeline = make_timeline();
+        let lkg = timeline.try_get_state(5).unwrap().clone();
+
+        assert_eq!(rollback_frame_id(&timeline, &lkg, &[]), None);
+        assert_eq!(rollback_frame_id(&timeline, &lkg, &[input(12, 1, Input::Up), input(9, 0, Input::Left)]), Some(8));
+
+        // Inputs from the future wait, they don't roll anything back
+        assert_eq!(rollback_frame_id(&timeline, &lkg, &[input(25, 1, Input::Up)]), None);
+
+        // Server had an input we never saw, so its lkg state disagrees with ours
+        let mut server = make_timeline();
+        assert!(server.try_propagate_inputs(vec![input(3, 1, Input::Up)], true));
+        let lkg = server.try_get_state(5).unwrap();
+        assert_eq!(rollback_frame_id(&timeline, lkg, &[input(12, 1, Input::Up)]), Some(5));
+    }
+
+    #[test]
+    fn blend_corrections() {
+        let map = Map::new("prediction_test");
+        let mut before = make_timeline().top_state().clone();
+        let after = before.clone();
+
+        // We predicted player 1 a tile to the right of where the server says
+        let mut player = before.get_player(PlayerId(1)).unwrap().clone();
+        player.pos = Pos::new_coord(11, 12);
+        before.player_states.set(PlayerId(1), player);
+
+        let mut correction = ErrorCorrection::default();
+        correction.correct(&ErrorCorrection::visual_positions(&before, &map), &ErrorCorrection::visual_positions(&after, &map));
+        assert_eq!(correction.offset(PlayerId(0)), V2::default());
+        assert_eq!(correction.offset(PlayerId(1)), V2::new(1.0, 0.0));
+
+        correction.tick(0.5);
+        assert_eq!(correction.offset(PlayerId(1)), V2::new(0.5, 0.0));
+        for _ in 0..20 {
+            correction.tick(0.5);
+        }
+        assert_eq!(correction.offset(PlayerId(1)), V2::default());
+
+        // Teleports snap
+        let mut player = before.get_player(PlayerId(1)).unwrap().clone();
+        player.pos = Pos::new_coord(2, 4);
+        before.player_states.set(PlayerId(1), player);
+        correction.correct(&ErrorCorrection::visual_positions(&before, &map), &ErrorCorrection::visual_positions(&after, &map));
+        assert_eq!(correction.offset(PlayerId(1)), V2::default());
+    }
+}
@@ -0,0 +1,380 @@
+use serde::{Deserialize, Serialize};
+
+use crate::crossy_ruleset::GameConfig;
+use crate::game::{Input, PlayerId, PlayerInputs, Pos};
+use crate::timeline::{RemoteInput, Timeline, TICK_INTERVAL_US};
+
+// A replay is everything needed to rebuild a match frame for frame:
+// the map seed, the config and the stream of inputs, joins and leaves.
+// Playback starts from frame 0 and re-runs Timeline::tick so it produces the same
+// GameStates as the original run.
+//
+// On disk it is a small header (magic + version) followed by a flexbuffers body.
+pub const REPLAY_MAGIC : &[u8; 4] = b"crpl";
+pub const REPLAY_VERSION : u8 = 1;
+
+// Games can sit in the lobby forever, stop recording after an hour rather than grow without bound.
+pub const MAX_REPLAY_FRAMES : u32 = 60 * 60 * 60;
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub enum ReplayEvent {
+    // Applied to the state at frame_id, after inputs for that frame.
+    Join { frame_id : u32, player_id : PlayerId, pos : Pos },
+    // Applied after joins, mirrors Timeline::remove_player.
+    Leave { frame_id : u32, player_id : PlayerId },
+    Input(RemoteInput),
+}
+
+impl ReplayEvent {
+    pub fn frame_id(&self) -> u32 {
+        match self {
+            ReplayEvent::Join { frame_id, .. } => *frame_id,
+            ReplayEvent::Leave { frame_id, .. } => *frame_id,
+            ReplayEvent::Input(input) => input.frame_id,
+        }
+    }
+
+    fn order_in_frame(&self) -> u8 {
+        match self {
+            ReplayEvent::Input(_) => 0,
+            ReplayEvent::Join { .. } => 1,
+            ReplayEvent::Leave { .. } => 2,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Replay {
+    pub seed : u32,
+    pub config : GameConfig,
+    pub last_frame_id : u32,
+    pub events : Vec<ReplayEvent>,
+}
+
+#[derive(Debug)]
+pub enum ReplayError {
+    BadHeader,
+    UnsupportedVersion(u8),
+    Corrupt(String),
+}
+
+impl std::fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ReplayError::BadHeader => write!(f, "not a replay file"),
+            ReplayError::UnsupportedVersion(v) => write!(f, "unsupported replay version {} (expected {})", v, REPLAY_VERSION),
+            ReplayError::Corrupt(e) => write!(f, "corrupt replay: {}", e),
+        }
+    }
+}
+
+impl Replay {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(1024);
+        bytes.extend_from_slice(REPLAY_MAGIC);
+        bytes.push(REPLAY_VERSION);
+        bytes.extend(flexbuffers::to_vec(self).unwrap());
+        bytes
+    }
+
+    pub fn from_bytes(bytes : &[u8]) -> Result<Self, ReplayError> {
+        if (bytes.len() < REPLAY_MAGIC.len() + 1 || &bytes[0..4] != REPLAY_MAGIC) {
+            return Err(ReplayError::BadHeader);
+        }
+
+        let version = bytes[4];
+        if (version != REPLAY_VERSION) {
+            return Err(ReplayError::UnsupportedVersion(version));
+        }
+
+        let reader = flexbuffers::Reader::get_root(&bytes[5..]).map_err(|e| ReplayError::Corrupt(e.to_string()))?;
+        Replay::deserialize(reader).map_err(|e| ReplayError::Corrupt(e.to_string()))
+    }
+}
+
+pub struct ReplayRecorder {
+    replay : Replay,
+}
+
+impl ReplayRecorder {
+    pub fn new(seed : u32, config : GameConfig) -> Self {
+        Self {
+            replay : Replay {
+                seed,
+                config,
+                last_frame_id : 0,
+                events : Vec::with_capacity(1024),
+            }
+        }
+    }
+
+    // Must be called before the timeline has been ticked.
+    pub fn from_timeline(timeline : &Timeline) -> Self {
+        debug_assert!(timeline.top_state().frame_id == 0);
+        Self::new(timeline.map.get_seed(), timeline.top_state().rules_state.config)
+    }
+
+    // Once full we keep events up to the last frame so the replay still plays back to there.
+    pub fn is_full(&self) -> bool {
+        self.replay.last_frame_id >= MAX_REPLAY_FRAMES
+    }
+
+    fn recording(&self, frame_id : u32) -> bool {
+        !self.is_full() || frame_id <= self.replay.last_frame_id
+    }
+
+    pub fn record_join(&mut self, frame_id : u32, player_id : PlayerId, pos : Pos) {
+        if (!self.recording(frame_id)) {
+            return;
+        }
+
+        self.replay.events.push(ReplayEvent::Join { frame_id, player_id, pos });
+    }
+
+    pub fn record_leave(&mut self, frame_id : u32, player_id : PlayerId) {
+        if (!self.recording(frame_id)) {
+            return;
+        }
+
+        self.replay.events.push(ReplayEvent::Leave { frame_id, player_id });
+    }
+
+    // Call before Timeline::try_propagate_inputs with the same inputs.
+    // We only keep the inputs the timeline is going to take, inputs that have fallen off the back of
+    // the state buffer or that would overwrite an existing input are ignored there too.
+    pub fn record_inputs(&mut self, timeline : &Timeline, inputs : &[RemoteInput]) {
+        for input in inputs {
+            if (input.input == Input::None || !self.recording(input.frame_id)) {
+                continue;
+            }
+
+            let accepted = timeline.try_get_state(input.frame_id)
+                .map(|state| state.player_inputs.get(input.player_id) == Input::None)
+                .unwrap_or(false);
+
+            if (accepted) {
+                self.replay.events.push(ReplayEvent::Input(input.clone()));
+            }
+        }
+    }
+
+    pub fn record_frame(&mut self, frame_id : u32) {
+        self.replay.last_frame_id = self.replay.last_frame_id.max(frame_id).min(MAX_REPLAY_FRAMES);
+    }
+
+    // Carry on recording a match that was restored from a server snapshot.
+    pub fn resume(replay : Replay) -> Self {
+        Self { replay }
+    }
+
+    pub fn replay(&self) -> &Replay {
+        &self.replay
+    }
+
+    pub fn finish(self) -> Replay {
+        self.replay
+    }
+}
+
+// Drives a timeline from a replay.
+// Kept separate from the timeline so clients can step their own.
+pub struct ReplayPlayer {
+    replay : Replay,
+    next_event : usize,
+}
+
+impl ReplayPlayer {
+    pub fn new(mut replay : Replay) -> Self {
+        // Stable so that inputs for the same frame keep their arrival order
+        // first input wins, the same as try_propagate_inputs.
+        replay.events.sort_by_key(|x| (x.frame_id(), x.order_in_frame()));
+
+        Self {
+            replay,
+            next_event : 0,
+        }
+    }
+
+    pub fn create_timeline(&mut self) -> Timeline {
+        let mut timeline = Timeline::from_exact_seed(self.replay.config, self.replay.seed);
+        self.next_event = 0;
+        self.apply_events(&mut timeline, 0);
+        timeline
+    }
+
+    pub fn last_frame_id(&self) -> u32 {
+        self.replay.last_frame_id
+    }
+
+    pub fn is_finished(&self, timeline : &Timeline) -> bool {
+        timeline.top_state().frame_id >= self.replay.last_frame_id
+    }
+
+    // Advance the timeline one frame, returns false once the replay is done.
+    pub fn tick(&mut self, timeline : &mut Timeline) -> bool {
+        if (self.is_finished(timeline)) {
+            return false;
+        }
+
+        let frame_id = timeline.top_state().frame_id + 1;
+
+        let mut inputs = PlayerInputs::new();
+        for event in &self.replay.events[self.next_event..] {
+            if (event.frame_id() != frame_id) {
+                break;
+            }
+
+            if let ReplayEvent::Input(input) = event {
+                if (inputs.get(input.player_id) == Input::None) {
+                    inputs.set(input.player_id, input.input);
+                }
+            }
+        }
+
+        timeline.tick(Some(inputs), TICK_INTERVAL_US);
+        self.apply_events(timeline, frame_id);
+
+        true
+    }
+
+    fn apply_events(&mut self, timeline : &mut Timeline, frame_id : u32) {
+        while let Some(event) = self.replay.events.get(self.next_event) {
+            if (event.frame_id() > frame_id) {
+                break;
+            }
+
+            if (event.frame_id() < frame_id) {
+                debug_log!("Replay skipping stale event {:?} at frame {}", event, frame_id);
+            }
+            else {
+                match event {
+                    ReplayEvent::Join { player_id, pos, .. } => {
+                        timeline.add_player(*player_id, *pos);
+                    },
+                    ReplayEvent::Leave { player_id, .. } => {
+                        timeline.remove_player(*player_id);
+                    },
+                    ReplayEvent::Input(_) => {},
+                }
+            }
+
+            self.next_event += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::PlayerId;
+
+    fn remote_input(frame_id : u32, player_id : u8, input : Input) -> RemoteInput {
+        RemoteInput {
+            time_us : frame_id * TICK_INTERVAL_US,
+            frame_id,
+            input,
+            player_id : PlayerId(player_id),
+        }
+    }
+
+    // Run something shaped like the server loop, with inputs arriving late.
+    fn run_recorded_match() -> (Timeline, Replay) {
+        let config = GameConfig {
+            bypass_lobby : true,
+            ..Default::default()
+        };
+
+        let mut timeline = Timeline::from_seed(config, "replay_test");
+        let mut recorder = ReplayRecorder::from_timeline(&timeline);
+
+        let inputs = [Input::Up, Input::Left, Input::Up, Input::Right, Input::Up, Input::Down];
+
+        for i in 0..600 {
+            timeline.tick(None, TICK_INTERVAL_US);
+            let frame_id = timeline.top_state().frame_id;
+
+            if (i % 20 == 10) {
+                let late = vec![
+                    remote_input(frame_id - 4, 1, inputs[(i / 20) % inputs.len()]),
+                    remote_input(frame_id - 2, 2, inputs[(i / 7) % inputs.len()]),
+                ];
+                recorder.record_inputs(&timeline, &late);
+                assert!(timeline.try_propagate_inputs(late, true));
+            }
+
+            if (i == 3) {
+                timeline.add_player(PlayerId(1), Pos::new_coord(9, 16));
+                recorder.record_join(frame_id, PlayerId(1), Pos::new_coord(9, 16));
+                timeline.add_player(PlayerId(2), Pos::new_coord(10, 16));
+                recorder.record_join(frame_id, PlayerId(2), Pos::new_coord(10, 16));
+            }
+
+            if (i == 500) {
+                timeline.remove_player(PlayerId(2));
+                recorder.record_leave(frame_id, PlayerId(2));
+            }
+
+            recorder.record_frame(frame_id);
+        }
+
+        (timeline, recorder.finish())
+    }
+
+    #[test]
+    fn replay_roundtrip_bytes() {
+        let (_, replay) = run_recorded_match();
+        let bytes = replay.to_bytes();
+        let loaded = Replay::from_bytes(&bytes).unwrap();
+        assert_eq!(loaded.seed, replay.seed);
+        assert_eq!(loaded.config, replay.config);
+        assert_eq!(loaded.last_frame_id, replay.last_frame_id);
+        assert_eq!(loaded.events, replay.events);
+    }
+
+    #[test]
+    fn recorder_stops_when_full() {
+        let timeline = Timeline::from_seed(GameConfig::default(), "replay_test");
+        let mut recorder = ReplayRecorder::from_timeline(&timeline);
+
+        recorder.record_frame(MAX_REPLAY_FRAMES - 1);
+        assert!(!recorder.is_full());
+
+        recorder.record_frame(MAX_REPLAY_FRAMES + 10);
+        assert!(recorder.is_full());
+        assert_eq!(recorder.replay().last_frame_id, MAX_REPLAY_FRAMES);
+
+        recorder.record_join(MAX_REPLAY_FRAMES, PlayerId(1), Pos::new_coord(9, 16));
+        recorder.record_join(MAX_REPLAY_FRAMES + 1, PlayerId(2), Pos::new_coord(10, 16));
+        recorder.record_leave(MAX_REPLAY_FRAMES + 1, PlayerId(1));
+        assert_eq!(recorder.replay().events.len(), 1);
+    }
+
+    #[test]
+    fn replay_rejects_bad_header() {
+        assert!(matches!(Replay::from_bytes(b"nope"), Err(ReplayError::BadHeader)));
+
+        let (_, replay) = run_recorded_match();
+        let mut bytes = replay.to_bytes();
+        bytes[4] = REPLAY_VERSION + 1;
+        assert!(matches!(Replay::from_bytes(&bytes), Err(ReplayError::UnsupportedVersion(_))));
+    }
+
+    #[test]
+    fn replay_matches_original() {
+        let (original, replay) = run_recorded_match();
+
+        let mut player = ReplayPlayer::new(replay);
+        let mut replayed = player.create_timeline();
+        while player.tick(&mut replayed) {}
+
+        assert_eq!(original.top_state().frame_id, replayed.top_state().frame_id);
+
+        // Compare every state we still have buffered.
+        for state in &original.states {
+            let replayed_state = replayed.try_get_state(state.frame_id).unwrap();
+            assert_eq!(state.time_us, replayed_state.time_us);
+            assert_eq!(state.player_states, replayed_state.player_states, "Mismatch at frame {}", state.frame_id);
+            assert_eq!(state.rules_state, replayed_state.rules_state, "Mismatch at frame {}", state.frame_id);
+        }
+    }
+}
@@ -5,8 +5,18 @@ use crate::map::Map;
 use crate::game::*;
 use crate::player::PlayerState;
 
-//const STATE_BUFFER_SIZE: usize = 128;
-const STATE_BUFFER_SIZE: usize = 512;
+// Hard cap on history, ~8s at 60Hz.
+// Servers and clients trim further with trim_before to what they can still need.
+const MAX_STATE_BUFFER_SIZE: usize = 512;
+
+// Never trim below this, late inputs can land from clients we don't have an estimate for yet.
+pub const MIN_STATE_BUFFER_SIZE: usize = 30;
+
+// Lookback for a client we know nothing about yet.
+const DEFAULT_LKG_LOOKBACK: u32 = 100;
+
+// Slack on top of the rtt for jitter.
+const LKG_RTT_MARGIN: u32 = 6;
 
 pub const TICK_INTERVAL_US : u32 = 16_666;
 
@@ -56,7 +66,16 @@ impl Timeline {
         states.push_front(GameState::new(config));
         Timeline {
             states,
-            map : Map::new(seed),
+            map : config.create_map(Map::seed_from_key(seed)),
+        }
+    }
+
+    pub fn from_exact_seed(config : GameConfig, seed: u32) -> Self {
+        let mut states = VecDeque::new();
+        states.push_front(GameState::new(config));
+        Timeline {
+            states,
+            map : config.create_map(seed),
         }
     }
 
@@ -72,11 +91,12 @@ impl Timeline {
         player_states: Vec<PlayerState>,
         rules_state : RulesState
     ) -> Self {
+        let map = rules_state.config.create_map(Map::seed_from_key(seed));
         let mut states = VecDeque::new();
         states.push_front(GameState::from_server_parts(frame_id, time_us, player_states, rules_state));
         Timeline {
             states,
-            map: Map::new(seed),
+            map,
         }
     }
 
@@ -87,11 +107,12 @@ impl Timeline {
         player_states: Vec<PlayerState>,
         rules_state: RulesState
     ) -> Self {
+        let map = rules_state.config.create_map(seed);
         let mut states = VecDeque::new();
         states.push_front(GameState::from_server_parts(frame_id, time_us, player_states, rules_state));
         Timeline {
             states,
-            map: Map::exact_seed(seed),
+            map,
         }
     }
 
@@ -139,6 +160,34 @@ impl Timeline {
         self.states.get(offset)
     }
 
+    pub fn oldest_frame_id(&self) -> u32 {
+        self.states.back().unwrap().frame_id
+    }
+
+    // Pick the last known good frame to send a client.
+    // acked_frame_id is the last frame we have the client's inputs up to. Anything newer would rebase
+    // the client from a state missing its own inputs that are still in flight. Clients who haven't
+    // sent inputs yet get a lookback from their round trip time.
+    pub fn select_lkg_frame_id(&self, acked_frame_id : Option<u32>, rtt_frames : Option<u32>) -> u32 {
+        let top_frame_id = self.top_state().frame_id;
+        let lkg_frame_id = match acked_frame_id {
+            Some(x) => x.min(top_frame_id),
+            None => {
+                let lookback = rtt_frames.map(|x| x + LKG_RTT_MARGIN).unwrap_or(DEFAULT_LKG_LOOKBACK);
+                top_frame_id.saturating_sub(lookback)
+            }
+        };
+
+        lkg_frame_id.max(self.oldest_frame_id())
+    }
+
+    // Drop history before frame_id, keeping at least MIN_STATE_BUFFER_SIZE states.
+    pub fn trim_before(&mut self, frame_id : u32) {
+        while self.states.len() > MIN_STATE_BUFFER_SIZE && self.oldest_frame_id() < frame_id {
+            self.states.pop_back();
+        }
+    }
+
     pub fn inputs_since_frame(&self, frame_id : u32) -> Vec<RemoteInput> {
         if let Some(mut offset) = self.frame_id_to_frame_offset(frame_id)
         {
@@ -389,7 +438,7 @@ impl Timeline {
 
     fn push_state(&mut self, state: GameState) {
         self.states.push_front(state);
-        while self.states.len() > STATE_BUFFER_SIZE {
+        while self.states.len() > MAX_STATE_BUFFER_SIZE {
             self.states.pop_back();
         }
     }
@@ -406,6 +455,7 @@ impl Timeline {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::crossy_ruleset::{AliveState, CrossyRulesetFST};
     use crate::player::*;
 
     // We dont want to actually expose this
@@ -415,4 +465,86 @@ mod tests {
             states : timeline.states.clone(),
         }
     }
+
+    fn timeline_at(frame_id : u32) -> Timeline {
+        let mut timeline = Timeline::from_seed(GameConfig::default(), "timeline_test");
+        for _ in 0..frame_id {
+            timeline.tick(None, TICK_INTERVAL_US);
+        }
+        timeline
+    }
+
+    #[test]
+    fn select_lkg_frame() {
+        let mut timeline = timeline_at(600);
+        assert_eq!(timeline.len(), MAX_STATE_BUFFER_SIZE);
+
+        // Never past the inputs we have from the client
+        assert_eq!(timeline.select_lkg_frame_id(Some(590), Some(2)), 590);
+        assert_eq!(timeline.select_lkg_frame_id(Some(550), Some(2)), 550);
+
+        // Clients can run ahead of the server
+        assert_eq!(timeline.select_lkg_frame_id(Some(610), None), 600);
+
+        // Nothing acked yet, go from the rtt
+        assert_eq!(timeline.select_lkg_frame_id(None, Some(20)), 600 - 20 - LKG_RTT_MARGIN);
+        assert_eq!(timeline.select_lkg_frame_id(None, None), 600 - DEFAULT_LKG_LOOKBACK);
+
+        // Can't go back further than we have
+        assert_eq!(timeline.select_lkg_frame_id(Some(10), None), timeline.oldest_frame_id());
+
+        timeline.trim_before(560);
+        assert_eq!(timeline.oldest_frame_id(), 560);
+        assert_eq!(timeline.select_lkg_frame_id(Some(550), None), 560);
+        assert_eq!(timeline.select_lkg_frame_id(None, None), 560);
+    }
+
+    #[test]
+    fn trim_keeps_minimum() {
+        let mut timeline = timeline_at(100);
+        timeline.trim_before(100);
+        assert_eq!(timeline.len(), MIN_STATE_BUFFER_SIZE);
+        assert_eq!(timeline.top_state().frame_id, 100);
+
+        // Trimmed history still takes late inputs
+        let frame_id = timeline.oldest_frame_id() + 1;
+        assert!(timeline.try_propagate_inputs(vec![RemoteInput {
+            time_us : frame_id * TICK_INTERVAL_US,
+            frame_id,
+            input : Input::Up,
+            player_id : PlayerId(0),
+        }], true));
+    }
+
+    // Two players who never move, returns the frame their round ended.
+    fn idle_round_end_frame(round_time_limit_us : Option<u32>) -> Option<u32> {
+        let config = GameConfig {
+            bypass_lobby : true,
+            round_time_limit_us,
+            ..Default::default()
+        };
+
+        let mut timeline = Timeline::from_seed(config, "time_limit_test");
+        timeline.add_player(PlayerId(1), Pos::new_coord(9, 11));
+        timeline.add_player(PlayerId(2), Pos::new_coord(10, 11));
+
+        for _ in 0..60 * 60 {
+            timeline.tick(None, TICK_INTERVAL_US);
+            if let CrossyRulesetFST::RoundCooldown(state) = &timeline.top_state().rules_state.fst {
+                assert!(state.round_state.alive_states.iter().all(|(_, x)| *x != AliveState::Alive));
+                return Some(timeline.top_state().frame_id);
+            }
+        }
+
+        None
+    }
+
+    #[test]
+    fn round_time_limit() {
+        assert_eq!(idle_round_end_frame(None), None);
+
+        let short = idle_round_end_frame(Some(5_000_000)).unwrap();
+        let long = idle_round_end_frame(Some(20_000_000)).unwrap();
+        assert!(long > short + 14 * 60, "{} {}", short, long);
+    }
 }
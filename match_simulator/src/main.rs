@@ -0,0 +1,386 @@
+#![allow(unused_parens)]
+
+use std::collections::BTreeMap;
+
+use crossy_multi_core::ai::{self, AIAgent};
+use crossy_multi_core::map::Map;
+use crossy_multi_core::map::level::Level;
+use crossy_multi_core::map::profile::{MapGenPreset, MAP_GEN_PRESET_NAMES};
+use crossy_multi_core::crossy_ruleset::{AliveState, CrossyRulesetFST, DeathCause, GameConfig};
+use crossy_multi_core::game_rules::{GameMode, GAME_MODE_NAMES};
+use crossy_multi_core::timeline::{Timeline, TICK_INTERVAL_US};
+use crossy_multi_core::{GameState, PlayerId, PlayerInputs, Pos};
+
+const USAGE : &str = "\
+Usage: match_simulator [options]
+  --seed N           Simulate a single seed
+  --seeds A..B       Simulate every seed in [A, B) (default 0..100)
+  --players N        Number of bots per match (default 4)
+  --bots a,b,..      Bot types, cycled over players (default go_up)
+  --wins N           Required win count (default 3)
+  --max-minutes N    Give up on a match after N minutes of game time (default 20)
+  --map-gen NAME     Map generation preset (default standard)
+  --mode NAME        Game mode, last_frog_standing, race or teams (default last_frog_standing)
+  --round-time-limit N  Seconds before the screen starts scrolling on its own (default none)
+  --level PATH       Play a hand authored level instead of generating maps
+  --quiet            Only print the summary";
+
+struct Options {
+    seeds : std::ops::RangeInclusive<u32>,
+    players : u8,
+    bots : Vec<String>,
+    required_win_count : u8,
+    max_frames : u32,
+    map_gen : MapGenPreset,
+    game_mode : GameMode,
+    round_time_limit_us : Option<u32>,
+    level : Option<Level>,
+    quiet : bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            seeds : 0..=99,
+            players : 4,
+            bots : vec!["go_up".to_owned()],
+            required_win_count : 3,
+            max_frames : 20 * 60 * 60,
+            map_gen : MapGenPreset::Standard,
+            game_mode : GameMode::LastFrogStanding,
+            round_time_limit_us : None,
+            level : None,
+            quiet : false,
+        }
+    }
+}
+
+fn parse_args() -> Result<Options, String> {
+    let mut options = Options::default();
+    let mut args = std::env::args().skip(1);
+
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or(format!("Missing value for {}", arg));
+        match arg.as_str() {
+            "--seed" => {
+                let seed : u32 = value()?.parse().map_err(|e| format!("Bad seed: {}", e))?;
+                options.seeds = seed..=seed;
+            },
+            "--seeds" => {
+                let range = value()?;
+                let (a, b) = range.split_once("..").ok_or(format!("Expected A..B, got {}", range))?;
+                let a : u32 = a.parse().map_err(|e| format!("Bad seed range: {}", e))?;
+                let b : u32 = b.parse().map_err(|e| format!("Bad seed range: {}", e))?;
+                if (b <= a) {
+                    return Err(format!("Empty seed range {}", range));
+                }
+                options.seeds = a..=b - 1;
+            },
+            "--players" => {
+                options.players = value()?.parse().map_err(|e| format!("Bad player count: {}", e))?;
+            },
+            "--bots" => {
+                options.bots = value()?.split(',').map(|x| x.to_owned()).collect();
+            },
+            "--wins" => {
+                options.required_win_count = value()?.parse().map_err(|e| format!("Bad win count: {}", e))?;
+            },
+            "--max-minutes" => {
+                let minutes : u32 = value()?.parse().map_err(|e| format!("Bad minutes: {}", e))?;
+                options.max_frames = minutes.checked_mul(60 * 60).ok_or(format!("Max minutes {} is too long", minutes))?;
+            },
+            "--map-gen" => {
+                let name = value()?;
+                options.map_gen = MapGenPreset::from_name(&name)
+                    .ok_or(format!("Unknown map gen preset '{}', expected one of {:?}", name, MAP_GEN_PRESET_NAMES))?;
+            },
+            "--mode" => {
+                let name = value()?;
+                options.game_mode = GameMode::from_name(&name)
+                    .ok_or(format!("Unknown game mode '{}', expected one of {:?}", name, GAME_MODE_NAMES))?;
+            },
+            "--round-time-limit" => {
+                let seconds : u32 = value()?.parse().map_err(|e| format!("Bad time limit: {}", e))?;
+                options.round_time_limit_us = Some(seconds.checked_mul(1_000_000).ok_or(format!("Time limit {}s is too long", seconds))?);
+            },
+            "--level" => {
+                let path = value()?;
+                let text = std::fs::read_to_string(&path).map_err(|e| format!("Couldn't read {}: {}", path, e))?;
+                options.level = Some(Level::parse(&text).map_err(|e| format!("Bad level {}: {}", path, e))?);
+            },
+            "--quiet" => {
+                options.quiet = true;
+            },
+            _ => {
+                return Err(format!("Unknown argument {}", arg));
+            }
+        }
+    }
+
+    if (options.players == 0 || options.players > 8) {
+        return Err(format!("Player count must be between 1 and 8, got {}", options.players));
+    }
+
+    for bot in &options.bots {
+        if (!ai::AGENT_NAMES.contains(&bot.as_str())) {
+            return Err(format!("Unknown bot '{}', expected one of {:?}", bot, ai::AGENT_NAMES));
+        }
+    }
+
+    Ok(options)
+}
+
+fn main() {
+    let options = match parse_args() {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{}", e);
+            eprintln!("{}", USAGE);
+            std::process::exit(1);
+        }
+    };
+
+    let mut summary = Summary::default();
+    for seed in options.seeds.clone() {
+        let result = simulate_match(seed, &options);
+        if (!options.quiet) {
+            result.print();
+        }
+        summary.add(&result);
+    }
+
+    summary.print(&options);
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum MatchOutcome {
+    Winner(PlayerId),
+    AllLeft,
+    TimedOut,
+}
+
+struct RoundResult {
+    duration_frames : u32,
+    winner : Option<PlayerId>,
+}
+
+struct MatchResult {
+    seed : u32,
+    outcome : MatchOutcome,
+    frames : u32,
+    rounds : Vec<RoundResult>,
+    // None when we couldn't work out why someone died, shouldn't happen.
+    deaths : BTreeMap<Option<DeathCause>, u32>,
+}
+
+impl MatchResult {
+    fn print(&self) {
+        let winner = match self.outcome {
+            MatchOutcome::Winner(id) => format!("player {}", id.0),
+            MatchOutcome::AllLeft => "all left".to_owned(),
+            MatchOutcome::TimedOut => "timed out".to_owned(),
+        };
+
+        let deaths : u32 = self.deaths.values().sum();
+        println!("seed {:>6}: {:>2} rounds, {:>7.1}s, {:>3} deaths, winner {}",
+            self.seed,
+            self.rounds.len(),
+            frames_to_secs(self.frames),
+            deaths,
+            winner);
+    }
+}
+
+fn frames_to_secs(frames : u32) -> f64 {
+    frames as f64 * TICK_INTERVAL_US as f64 / 1_000_000.0
+}
+
+fn simulate_match(seed : u32, options : &Options) -> MatchResult {
+    let config = GameConfig {
+        required_win_count : options.required_win_count,
+        minimum_players : options.players.min(2),
+        bypass_lobby : true,
+        map_gen : options.map_gen,
+        game_mode : options.game_mode,
+        round_time_limit_us : options.round_time_limit_us,
+        // --level takes a file rather than a bundled preset, set on the map below.
+        level : None,
+    };
+
+    // Same seeding as the clients so "new <seed>" in the console reproduces a match.
+    let mut timeline = Timeline::from_seed(config, &seed.to_string());
+    if let Some(level) = &options.level {
+        timeline.map = Map::from_level(seed.to_string(), level.clone());
+    }
+
+    let mut bots : Vec<(PlayerId, Box<dyn AIAgent>)> = Vec::new();
+    for i in 0..options.players {
+        let player_id = PlayerId(i + 1);
+        let bot_name = &options.bots[i as usize % options.bots.len()];
+        timeline.add_player(player_id, Pos::new_coord(7 + i as i32, 13));
+        bots.push((player_id, ai::create_agent(bot_name, player_id, seed).unwrap()));
+    }
+
+    let mut result = MatchResult {
+        seed,
+        outcome : MatchOutcome::TimedOut,
+        frames : 0,
+        rounds : Vec::new(),
+        deaths : BTreeMap::new(),
+    };
+
+    let mut round_start_frame = 0;
+    let mut round_end_frame = 0;
+
+    for _ in 0..options.max_frames {
+        let mut inputs = PlayerInputs::new();
+        for (player_id, bot) in bots.iter_mut() {
+            inputs.set(*player_id, bot.think(timeline.top_state(), &timeline.map));
+        }
+
+        timeline.tick(Some(inputs), TICK_INTERVAL_US);
+
+        let top = timeline.top_state();
+        let prev = timeline.try_get_state(top.frame_id - 1).unwrap();
+        result.frames = top.frame_id;
+
+        match (&prev.rules_state.fst, &top.rules_state.fst) {
+            (CrossyRulesetFST::RoundWarmup(_), CrossyRulesetFST::Round(_)) => {
+                round_start_frame = top.frame_id;
+            },
+            (CrossyRulesetFST::Round(_), CrossyRulesetFST::RoundCooldown(_)) => {
+                round_end_frame = top.frame_id;
+            },
+            (CrossyRulesetFST::RoundCooldown(cooldown), next) if !next.same_variant(&prev.rules_state.fst) => {
+                // Races are won by whoever finished first, otherwise it's the survivor
+                let winner = cooldown.round_state.finish_order.first().copied().or_else(|| {
+                    cooldown.round_state.alive_states.iter()
+                        .find(|(_, x)| **x == AliveState::Alive)
+                        .map(|(id, _)| id)
+                });
+
+                result.rounds.push(RoundResult {
+                    duration_frames : round_end_frame - round_start_frame,
+                    winner,
+                });
+            },
+            _ => {},
+        }
+
+        record_deaths(prev, top, &timeline, &mut result.deaths);
+
+        match &top.rules_state.fst {
+            CrossyRulesetFST::EndWinner(state) => {
+                result.outcome = MatchOutcome::Winner(state.winner_id);
+                break;
+            },
+            CrossyRulesetFST::EndAllLeft(_) => {
+                result.outcome = MatchOutcome::AllLeft;
+                break;
+            },
+            _ => {},
+        }
+    }
+
+    result
+}
+
+fn record_deaths(prev : &GameState, top : &GameState, timeline : &Timeline, deaths : &mut BTreeMap<Option<DeathCause>, u32>) {
+    let round_state = match &top.rules_state.fst {
+        CrossyRulesetFST::Round(state) => state,
+        CrossyRulesetFST::RoundCooldown(state) => &state.round_state,
+        _ => return,
+    };
+    let rules = top.rules_state.config.game_mode.rules();
+
+    for (id, player_state) in top.player_states.iter() {
+        let was_alive = prev.rules_state.fst.get_player_alive(id) == AliveState::Alive;
+        let is_dead = top.rules_state.fst.get_player_alive(id) == AliveState::Dead;
+        if (was_alive && is_dead) {
+            // Same arguments the ruleset used when killing the player
+            let cause = rules.death_cause(top.time_us, round_state, &timeline.map, player_state, &prev.rules_state.fst);
+            *deaths.entry(cause).or_default() += 1;
+        }
+    }
+}
+
+#[derive(Default)]
+struct Summary {
+    matches : u32,
+    winners : u32,
+    all_left : u32,
+    timed_out : u32,
+    rounds : u32,
+    draws : u32,
+    wins_by_player : BTreeMap<u8, u32>,
+    deaths : BTreeMap<Option<DeathCause>, u32>,
+    round_durations : Vec<u32>,
+}
+
+impl Summary {
+    fn add(&mut self, result : &MatchResult) {
+        self.matches += 1;
+        match result.outcome {
+            MatchOutcome::Winner(id) => {
+                self.winners += 1;
+                *self.wins_by_player.entry(id.0).or_default() += 1;
+            },
+            MatchOutcome::AllLeft => self.all_left += 1,
+            MatchOutcome::TimedOut => self.timed_out += 1,
+        }
+
+        for round in &result.rounds {
+            self.rounds += 1;
+            if (round.winner.is_none()) {
+                self.draws += 1;
+            }
+            self.round_durations.push(round.duration_frames);
+        }
+
+        for (cause, count) in &result.deaths {
+            *self.deaths.entry(*cause).or_default() += count;
+        }
+    }
+
+    fn print(&mut self, options : &Options) {
+        println!();
+        println!("==== Summary ====");
+        println!("Seeds {}..={}, {} players, bots {:?}, map gen {:?}", options.seeds.start(), options.seeds.end(), options.players, options.bots, options.map_gen);
+        println!("Matches      {}", self.matches);
+        println!("  finished   {}", self.winners);
+        println!("  all left   {}", self.all_left);
+        println!("  timed out  {}", self.timed_out);
+
+        if (self.matches > 0) {
+            println!("Rounds       {} ({:.2} per match, {} with no winner)", self.rounds, self.rounds as f64 / self.matches as f64, self.draws);
+        }
+
+        println!("Match winners");
+        for (player_id, wins) in &self.wins_by_player {
+            let bot = &options.bots[(*player_id as usize - 1) % options.bots.len()];
+            println!("  player {} ({}) {}", player_id, bot, wins);
+        }
+
+        let total_deaths : u32 = self.deaths.values().sum();
+        println!("Deaths       {}", total_deaths);
+        for (cause, count) in &self.deaths {
+            let name = match cause {
+                Some(cause) => format!("{:?}", cause),
+                None => "Unknown".to_owned(),
+            };
+            println!("  {:<18} {:>6} ({:.1}%)", name, count, 100.0 * *count as f64 / total_deaths as f64);
+        }
+
+        if (!self.round_durations.is_empty()) {
+            self.round_durations.sort();
+            let n = self.round_durations.len();
+            let mean = self.round_durations.iter().map(|x| *x as f64).sum::<f64>() / n as f64;
+            println!("Round duration (s)");
+            println!("  min    {:.1}", frames_to_secs(self.round_durations[0]));
+            println!("  median {:.1}", frames_to_secs(self.round_durations[n / 2]));
+            println!("  mean   {:.1}", frames_to_secs(1) * mean);
+            println!("  p90    {:.1}", frames_to_secs(self.round_durations[(n * 9) / 10]));
+            println!("  max    {:.1}", frames_to_secs(self.round_durations[n - 1]));
+        }
+    }
+}
@@ -13,6 +13,12 @@ spr_block.src = '/sprites/spr_block.png';
 let spr_barrier = new Image(SCALE, SCALE);
 spr_barrier.src = '/sprites/spr_barrier.png';
 
+const PICKUP_COLOURS = {
+    SpeedBoost : "#ffec27",
+    Shield : "#29adff",
+    SuperPush : "#ff004d",
+};
+
 function draw_static(froggy_draw_ctx, spr, x, y) {
     const xx = x * SCALE + froggy_draw_ctx.x_off;
     const yy = y * SCALE + froggy_draw_ctx.y_off;
@@ -32,6 +38,7 @@ export function create_background_controller() {
         in_lobby : true,
         in_warmup : false,
         rows : [],
+        pickups : [],
 
         reset : function() {
             this.generated_to_y = 160/8;
@@ -42,8 +49,10 @@ export function create_background_controller() {
             this.in_warmup = in_warmup;
 
             this.rows = []
+            this.pickups = []
             if (!in_lobby) {
                 this.rows = JSON.parse(client.get_rows_json());
+                this.pickups = JSON.parse(client.get_pickups_json());
                 /*
                 const top_row_y = this.rows[0].y;
                 while (top_row_y < this.generated_to_y) {
@@ -101,6 +110,10 @@ export function create_background_controller() {
                     col0 = '#646469';
                     col1 = '#59595d';
                 }
+                else if (row.type === "FinishLine") {
+                    col0 = "#fff1e8";
+                    col1 = "#000000";
+                }
                 else {
                     col0 = "#c4e6b5";
                     col1 = "#d1bfdb";
@@ -152,6 +165,17 @@ export function create_background_controller() {
                     }
                 }
             }
+
+            for (const pickup of this.pickups) {
+                const xx = pickup.x * SCALE + froggy_draw_ctx.x_off + SCALE / 2;
+                const yy = pickup.y * SCALE + froggy_draw_ctx.y_off + SCALE / 2;
+                ctx.fillStyle = PICKUP_COLOURS[pickup.kind];
+                ctx.strokeStyle = "#000000";
+                ctx.beginPath();
+                ctx.arc(xx, yy, 3, 0, 2 * Math.PI);
+                ctx.fill();
+                ctx.stroke();
+            }
         }
     }
 }
\ No newline at end of file
@@ -17,6 +17,9 @@ import { create_graph } from "./graphs";
 
 const PLAY_CROWD_SOUNDS = false;
 
+// Same order as the team tints on desktop.
+const TEAM_NAMES = ["red team", "blue team"];
+
 const audio_crowd = new Audio('/sounds/snd_win.wav');
 const audio_crowd_max = 0.325;
 audio_crowd.addEventListener('timeupdate', function(){
@@ -97,19 +100,30 @@ export function create_game(ctx, client, ws, key_event_source) {
 
                 // Check if ws in ready state
                 // https://developer.mozilla.org/en-US/docs/Web/API/WebSocket/readyState
-                const ws_ready = this.ws.readyState == 1
+                // Replays have no server to talk to.
+                const ws_ready = this.ws && this.ws.readyState == 1
 
                 if (ws_ready)
                 {
-                    const client_tick = this.client.get_client_message();
-                    this.ws.send(client_tick);
+                    if (!this.client.is_spectator())
+                    {
+                        const client_tick = this.client.get_client_message();
+                        this.ws.send(client_tick);
+                    }
 
-                    if (this.client.has_telemetry_messages())
+                    // Telemetry is per player, spectators don't have one
+                    if (!this.client.is_spectator() && this.client.has_telemetry_messages())
                     {
                         const telemetry_message = this.client.get_telemetry_message();
                         this.ws.send(telemetry_message);
                     }
 
+                    if (this.client.has_ack_message())
+                    {
+                        const ack_message = this.client.get_ack_message();
+                        this.ws.send(ack_message);
+                    }
+
                     if (this.client.should_get_time_request())
                     {
                         const time_request = this.client.get_time_request();
@@ -192,7 +206,13 @@ export function create_game(ctx, client, ws, key_event_source) {
                     this.entities = create_entities_container();
                     let winner_name = "";
                     if (this.rules_state && this.rules_state.fst.type === "EndWinner") {
-                        winner_name = this.players.get(this.rules_state.fst.winner_id).name;
+                        const winner_team = this.rules_state.fst.winner_team;
+                        if (winner_team !== undefined && winner_team !== null) {
+                            winner_name = TEAM_NAMES[winner_team % TEAM_NAMES.length];
+                        }
+                        else {
+                            winner_name = this.players.get(this.rules_state.fst.winner_id).name;
+                        }
                     }
                     else
                     {
@@ -213,7 +233,7 @@ export function create_game(ctx, client, ws, key_event_source) {
                 let players_with_values = new Set();
 
                 const local_player_id = this.client.get_local_player_id();
-                if (local_player_id >= 0) {
+                if (local_player_id >= 0 || this.client.is_spectator()) {
                     for (const current_player_state of current_player_states) {
                         if (!this.players.get(current_player_state.id)) {
                             console.log("creating player");
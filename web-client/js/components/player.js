@@ -1,7 +1,7 @@
 import { SCALE} from "./constants.js";
 import { dan_lerp, diff} from "./utils.js";
 import { create_whiteout, create_dust, create_corpse, create_bubble, create_pinwheel } from "./visual_effects.js";
-import { MOVE_T, spr_shadow, sprites_list, colours_list, move_sounds_list, names, hat_sprites, hat_offsets } from "./character_assets.js";
+import { spr_shadow, sprites_list, colours_list, move_sounds_list, names, hat_sprites, hat_offsets } from "./character_assets.js";
 
 let spr_crown = new Image(8, 6);
 spr_crown.src = '/sprites/spr_crown.png';
@@ -86,11 +86,15 @@ export function create_player_remote(client, player_id, audio_manager) {
             let y1 = player_state.y
 
             if (player_state.moving) {
-                const interp_t = (player_state.remaining_move_dur / MOVE_T);
+                const interp_t = (player_state.remaining_move_dur / player_state.move_dur);
                 x1 = player_state.x * (interp_t) + player_state.t_x * (1-interp_t);
                 y1 = player_state.y * (interp_t) + player_state.t_y * (1-interp_t);
             }
 
+            // Blend out corrections from the server
+            x1 += player_state.error_x;
+            y1 += player_state.error_y;
+
             const new_p = lerp_snap(this.x, this.y, x1, y1);
             const x = new_p.x;
             const y = new_p.y;
@@ -141,15 +145,16 @@ export function create_player_local(client, key_event_source, audio_manager) {
         audio_manager : audio_manager,
 
         tick : function(player_state, simple_entities, player_actor) {
-            const x0 = player_state.x;
-            const y0 = player_state.y;
+            // Blend out corrections from the server
+            const x0 = player_state.x + player_state.error_x;
+            const y0 = player_state.y + player_state.error_y;
 
             let x,y;
             if (player_state.moving) {
-                const lerp_t = (1 - player_state.remaining_move_dur / MOVE_T);
+                const lerp_t = (1 - player_state.remaining_move_dur / player_state.move_dur);
 
-                const x1 = player_state.t_x;
-                const y1 = player_state.t_y;
+                const x1 = player_state.t_x + player_state.error_x;
+                const y1 = player_state.t_y + player_state.error_y;
 
                 x = x0 + lerp_t * (x1 - x0);
                 y = y0 + lerp_t * (y1 - y0);
@@ -198,6 +198,10 @@ export function create_prop_controller() {
                                 entities.bushes.push(bush);
                             //}
                         }
+
+                        for (let x of hydrated_bushes.trees) {
+                            entities.simple_entities.push(create_prop(x * SCALE, this.gen_to * SCALE - 2, "tree_top", client));
+                        }
                     }
 
                     let wall_width = client.get_wall_width(this.gen_to);
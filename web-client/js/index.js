@@ -1,7 +1,7 @@
 "use strict";
 
 import { create_game }  from "./components/game.js"
-import { Client } from "../pkg/index.js"
+import { Client, protocol_version } from "../pkg/index.js"
 import ClipboardJS from 'clipboard';
 
 const DEBUG = true;
@@ -12,10 +12,37 @@ const query_string = window.location.search;
 const url_params = new URLSearchParams(query_string);
 var game_id = url_params.get('game_id');
 var debug_bypass_lobby = url_params.get('debug_bypass_lobby');
+// ?game_id=..&spectate=true to watch without playing
+var spectate = url_params.get('spectate') === 'true';
+// ?replay=<game_id> to watch the server's replay of a game
+var replay_game_id = url_params.get('replay');
+
+// eg ?input_delay=3&max_rollback=30&error_correction=0.1
+// anything not set keeps the client's default
+function prediction_settings_from_url() {
+    let settings = {};
+    if (url_params.has('input_delay')) {
+        settings.input_delay_frames = parseInt(url_params.get('input_delay'));
+    }
+    if (url_params.has('max_rollback')) {
+        settings.max_rollback_frames = parseInt(url_params.get('max_rollback'));
+    }
+    if (url_params.has('error_correction')) {
+        settings.error_correction_rate = parseFloat(url_params.get('error_correction'));
+    }
+    return settings;
+}
 
 var player_name = "Dan";
 var socket_id = 0;
 
+const RECONNECT_DELAY_MS = 1000;
+// Server closes the websocket with this when we have been kicked, see web-server main.rs.
+const KICKED_CLOSE_CODE = 4001;
+// /join answers with this when we speak a protocol version the server doesn't, see web-server main.rs.
+const UPGRADE_REQUIRED_STATUS = 426;
+const VERSION_RELOAD_KEY = 'version_reload';
+
 var client = undefined;
 var ws = undefined;
 var estimated_latency_us = 0;
@@ -72,7 +99,11 @@ function write_join_link(game_id) {
 }
 
 function start_game() {
-    if (game_id)
+    if (replay_game_id)
+    {
+        watch_replay();
+    }
+    else if (game_id)
     {
         console.log("Joining game " + game_id);
         write_join_link(game_id);
@@ -99,12 +130,42 @@ function start_game() {
 }
 start_game();
 
+function watch_replay() {
+    console.log("Loading replay " + replay_game_id);
+
+    fetch(endpoint + '/replay?game_id=' + encodeURIComponent(replay_game_id))
+        .then(response => {
+            if (!response.ok) {
+                throw new Error("no replay for " + replay_game_id);
+            }
+            return response.arrayBuffer();
+        })
+        .then(buffer => {
+            client = new Client(replay_game_id, 0, 0, 0);
+            if (client.start_replay(new Uint8Array(buffer))) {
+                setup_game();
+            }
+        })
+        .catch(e => console.error("Could not load replay: " + e.message));
+}
+
 function join() {
 
     console.log("Calling join...");
 
-    fetch_json('/join?game_id=' + game_id + '&name=' + player_name)
-        .then(response => response.json())
+    fetch_json('/join?game_id=' + game_id + '&name=' + player_name + '&version=' + protocol_version() + (spectate ? '&spectate=true' : ''))
+        .then(response => {
+            if (response.status == UPGRADE_REQUIRED_STATUS) {
+                // Server speaks a different protocol version, most likely a stale cached client
+                reload_for_new_version();
+                return response.text().then(text => { throw new Error(text); });
+            }
+            if (!response.ok) {
+                return response.text().then(text => { throw new Error(text); });
+            }
+            window.sessionStorage.removeItem(VERSION_RELOAD_KEY);
+            return response.json();
+        })
         .then(response => {
             console.log("/join response");
             console.log(response);
@@ -113,18 +174,61 @@ function join() {
             console.log("Creating client");
             console.log("JS server_ms=" + response.server_time_us / 1000 + " estimated_latency=" + estimated_latency_us / 1000);
             client = new Client(game_id, response.server_frame_id, response.server_time_us, estimated_latency_us);
+            client.set_prediction_settings_json(JSON.stringify(prediction_settings_from_url()));
+
+            if (spectate) {
+                client.spectate();
+            }
+            else {
+                play();
+            }
 
-            play();
             connect_ws();
-        });
+        })
+        .catch(e => console.error("Could not join: " + e.message));
+}
+
+// Reload once to pick up the new build, if that didn't help the browser is still holding on to
+// the old one so ask for a refresh instead of reloading forever.
+function reload_for_new_version() {
+    if (!window.sessionStorage.getItem(VERSION_RELOAD_KEY)) {
+        window.sessionStorage.setItem(VERSION_RELOAD_KEY, "1");
+        window.location.reload();
+        return;
+    }
+
+    document.getElementById("debug_join").innerText = "A new version of the game is out, please refresh the page.";
+}
+
+// Lets us back into the same game as the same player after a disconnect or reload.
+function reconnect_token_key() {
+    return 'reconnect_token_' + game_id;
 }
 
 function play() {
-    fetch_json('/play?game_id=' + game_id + '&socket_id=' + socket_id)
+    let url = '/play?game_id=' + game_id + '&socket_id=' + socket_id;
+    const reconnect_token = window.sessionStorage.getItem(reconnect_token_key());
+    if (reconnect_token) {
+        url += '&reconnect_token=' + reconnect_token;
+    }
+
+    fetch_json(url)
         .then(response => response.json())
         .then(response => {
             console.log("/play response");
             console.log(response);
+            if (!response) {
+                // Game full, or we were kicked from it, watch instead.
+                if (client) {
+                    client.spectate();
+                }
+                return;
+            }
+
+            if (response.reconnect_token) {
+                window.sessionStorage.setItem(reconnect_token_key(), response.reconnect_token);
+            }
+
             if (client)
             {
                 client.join(response.player_id);
@@ -150,11 +254,37 @@ function connect_ws() {
         }
     };
 
-    ws.onclose = () => {
+    ws.onclose = evt => {
         console.log("WS closed");
+        if (evt.code == KICKED_CLOSE_CODE) {
+            // Our token is banned, reloading would only get us a spectator seat.
+            console.log("Kicked from the game");
+            return;
+        }
+
+        if (!spectate && window.sessionStorage.getItem(reconnect_token_key())) {
+            // Server holds our player for a while, come back in with the token
+            console.log("Reconnecting...");
+            setTimeout(reload_when_server_up, RECONNECT_DELAY_MS);
+        }
     };
 }
 
+// The server may be restarting, its games come back from a snapshot so wait for it rather than
+// reloading into an error page.
+function reload_when_server_up() {
+    fetch_json('/games')
+        .then(response => {
+            if (response.ok) {
+                window.location.reload();
+            }
+            else {
+                setTimeout(reload_when_server_up, RECONNECT_DELAY_MS);
+            }
+        })
+        .catch(_ => setTimeout(reload_when_server_up, RECONNECT_DELAY_MS));
+}
+
 /////////////////////////////////////////////////////////////////////////////////////
 
 let key_event_source = {
@@ -7,24 +7,25 @@ macro_rules! log {
 }
 
 mod wasm_instant;
-mod ai;
 mod realtime_graph;
 mod client_seen_pushes;
 mod round_end_predictor;
-mod draw_commands;
 
 use std::time::Duration;
 use std::cell::RefCell;
 
 use std::collections::{VecDeque, BTreeMap};
 use crossy_multi_core::map::{RowType, RowWithY};
+use crossy_multi_core::map::level::LevelPreset;
 use crossy_multi_core::player::{PushInfo, MoveState};
-use draw_commands::DrawCommands;
+use crossy_multi_core::prediction::{self, ErrorCorrection, PredictionSettings};
+use crossy_multi_core::replay::{Replay, ReplayPlayer};
+use crossy_multi_core::ai;
+use crossy_multi_core::ai::draw_commands::DrawCommands;
 use froggy_rand::FroggyRand;
 use realtime_graph::RealtimeGraph;
 use round_end_predictor::RoundEndPredictor;
 use wasm_instant::{WasmInstant, WasmDateInstant};
-use serde::Deserialize;
 use wasm_bindgen::prelude::*;
 use client_seen_pushes::*;
 
@@ -32,7 +33,7 @@ use crossy_multi_core::*;
 use crossy_multi_core::game::PlayerId;
 use crossy_multi_core::crossy_ruleset::{AliveState, RulesState};
 
-use crate::draw_commands::{DrawCommand, DrawCoords, DrawColour, DrawType};
+use crossy_multi_core::ai::draw_commands::{DrawCommand, DrawCoords, DrawColour, DrawType};
 
 struct ConsoleDebugLogger();
 impl crossy_multi_core::DebugLogger for ConsoleDebugLogger {
@@ -51,6 +52,8 @@ const TICK_INTERVAL_US : u32 = 16_666;
 pub struct LocalPlayerInfo {
     player_id: game::PlayerId,
     buffered_input : Input,
+    // Frame the buffered input was pressed on, for input delay.
+    buffered_frame_id : u32,
 }
 
 //const TIME_REQUEST_INTERVAL : u32 = 13;
@@ -59,6 +62,12 @@ const TIME_REQUEST_INTERVAL : u32 = 2;
 const RUN_TELEMETRY : bool = true;
 const RUN_PING_LATENCY_UPDATES : bool = true;
 
+// Sent in /join so the server knows how to talk to us.
+#[wasm_bindgen]
+pub fn protocol_version() -> u8 {
+    interop::wire::PROTOCOL_VERSION
+}
+
 #[wasm_bindgen]
 pub struct Client {
     client_start : WasmInstant,
@@ -70,8 +79,11 @@ pub struct Client {
     estimated_latency_us_lerping : f32,
 
     timeline: timeline::Timeline,
+    // Bundled level the map was built from, None for a generated map.
+    map_level : Option<LevelPreset>,
 
     local_player_info : Option<LocalPlayerInfo>,
+    spectator : bool,
     last_sent_frame_id : u32,
 
     // This seems like a super hacky solution
@@ -81,6 +93,10 @@ pub struct Client {
     queued_time_info : Option<interop::TimeRequestEnd>,
 
     queued_server_linden_messages : VecDeque<interop::LindenServerTick>,
+    tick_decoder : interop::delta::TickDecoder,
+
+    prediction_settings : PredictionSettings,
+    error_correction : ErrorCorrection,
 
     ai_agent : Option<RefCell<Box<dyn ai::AIAgent>>>,
 
@@ -92,6 +108,9 @@ pub struct Client {
     client_seen_pushes : ClientSeenPushManager,
     round_end_predictor : RoundEndPredictor,
 
+    // When set we are watching a replay, the timeline is driven by it instead of the server.
+    replay : Option<ReplayPlayer>,
+
     tick_id : u32,
 }
 
@@ -130,6 +149,7 @@ impl Client {
 
         Client {
             timeline,
+            map_level : None,
             client_start,
 
             // @TODO REMEMBER MEEEE
@@ -140,9 +160,13 @@ impl Client {
             estimated_latency_us : estimated_latency_us as f32,
             estimated_latency_us_lerping : estimated_latency_us as f32,
             local_player_info : None,
+            spectator : false,
             last_sent_frame_id : server_frame_id as u32,
             queued_time_info: Default::default(),
             queued_server_linden_messages: Default::default(),
+            tick_decoder: Default::default(),
+            prediction_settings: Default::default(),
+            error_correction: Default::default(),
             ai_agent : None,
             telemetry_buffer,
 
@@ -152,6 +176,8 @@ impl Client {
             client_seen_pushes : ClientSeenPushManager::default(),
             round_end_predictor : RoundEndPredictor::default(),
 
+            replay : None,
+
             tick_id : 0,
 
             untrusted_rules_state: None,
@@ -163,9 +189,55 @@ impl Client {
         self.local_player_info = Some(LocalPlayerInfo {
             player_id : PlayerId(player_id as u8),
             buffered_input : Input::None,
+            buffered_frame_id : 0,
         })
     }
 
+    // Watch only, we never get a local player.
+    pub fn spectate(&mut self) {
+        self.spectator = true;
+
+        // Telemetry is per player, the server has nobody to put it against.
+        self.telemetry_buffer.enabled = false;
+        self.telemetry_buffer.buffer.clear();
+    }
+
+    pub fn is_spectator(&self) -> bool {
+        self.spectator
+    }
+
+    // Watch a replay file instead of the server, returns false if it can't be read.
+    pub fn start_replay(&mut self, replay_bytes : &[u8]) -> bool {
+        match Replay::from_bytes(replay_bytes) {
+            Ok(replay) => {
+                log!("Playing replay, {} frames, {} events", replay.last_frame_id, replay.events.len());
+                let mut replay = ReplayPlayer::new(replay);
+                self.timeline = replay.create_timeline();
+                self.replay = Some(replay);
+                self.client_start = WasmInstant::now();
+                self.spectate();
+                true
+            },
+            Err(e) => {
+                log!("Unable to load replay: {}", e);
+                false
+            }
+        }
+    }
+
+    // Partial json, missing fields keep their defaults.
+    pub fn set_prediction_settings_json(&mut self, settings_json : &str) {
+        match serde_json::from_str(settings_json) {
+            Ok(settings) => {
+                self.prediction_settings = settings;
+                log!("Prediction settings {:?}", self.prediction_settings);
+            },
+            Err(e) => {
+                log!("Bad prediction settings {} {:?}", settings_json, e);
+            }
+        }
+    }
+
     pub fn buffer_input_json(&mut self, input_json : &str) {
         if (input_json == "\"Kill\"") {
             panic!("Manual kill signal");
@@ -176,10 +248,12 @@ impl Client {
     }
 
     fn buffer_input(&mut self, input : Input) {
+        let frame_id = self.timeline.top_state().frame_id;
         self.local_player_info.as_mut().map(|x| {
             //if input != Input::None {
             if input != Input::None && x.buffered_input == Input::None {
                 x.buffered_input = input;
+                x.buffered_frame_id = frame_id;
             }
         });
     }
@@ -191,6 +265,20 @@ impl Client {
     pub fn tick(&mut self) {
         self.tick_id += 1;
 
+        if let Some(replay) = self.replay.as_mut() {
+            // Play back in real time, holding on the last frame once done.
+            let current_time_us = self.client_start.elapsed().as_micros() as u32;
+            while (self.timeline.top_state().time_us < current_time_us && replay.tick(&mut self.timeline)) {}
+
+            // Everything is known, there is nothing to predict.
+            let rules_state = self.timeline.top_state().rules_state.clone();
+            self.lkg_rules_state = Some(rules_state.clone());
+            self.untrusted_rules_state = Some(rules_state);
+
+            self.client_seen_pushes.tick(&self.timeline);
+            return;
+        }
+
         if let Some(server_start) = self.server_start {
             //debug_log!("Ticking!");
             loop {
@@ -229,6 +317,8 @@ impl Client {
             self.server_message_count_graph.push(self.queued_server_linden_messages.len() as f32);
 
             let mut requeued_server_messages = VecDeque::new();
+            let visual_positions_before = ErrorCorrection::visual_positions(self.timeline.top_state(), &self.timeline.map);
+            let processing = !self.queued_server_linden_messages.is_empty();
 
             while let Some(linden_server_tick) = self.queued_server_linden_messages.pop_back() {
                 //log!("{:#?}", linden_server_tick);
@@ -249,6 +339,11 @@ impl Client {
             }
 
             self.queued_server_linden_messages = requeued_server_messages;
+
+            if (processing) {
+                let visual_positions_after = ErrorCorrection::visual_positions(self.timeline.top_state(), &self.timeline.map);
+                self.error_correction.correct(&visual_positions_before, &visual_positions_after);
+            }
         }
 
         self.process_time_info();
@@ -280,7 +375,8 @@ impl Client {
                 else 
                 {
                     let local_player_info = self.local_player_info.as_mut().unwrap();
-                    if (local_player_info.buffered_input != Input::None)
+                    let delay_elapsed = self.timeline.top_state().frame_id >= local_player_info.buffered_frame_id + self.prediction_settings.input_delay_frames;
+                    if (local_player_info.buffered_input != Input::None && delay_elapsed)
                     {
                         local_input = local_player_info.buffered_input;
                         local_player_info.buffered_input = Input::None;
@@ -291,7 +387,8 @@ impl Client {
             player_inputs.set(self.local_player_info.as_ref().unwrap().player_id, local_input);
         }
 
-        self.timeline.tick(Some(player_inputs), TICK_INTERVAL_US)
+        self.timeline.tick(Some(player_inputs), TICK_INTERVAL_US);
+        self.error_correction.tick(self.prediction_settings.error_correction_rate);
     }
 
     fn process_time_info(&mut self)
@@ -375,17 +472,56 @@ impl Client {
         //let mut should_reset = self.trusted_rules_state.as_ref().map(|x| !x.same_variant(&linden_server_tick.rules_state)).unwrap_or(false);
         //should_reset |= self.timeline.top_state().player_states.count_populated() != linden_server_tick.latest.states.len();
 
-        let should_reset = false;
+        // We build the map before we know the config, regenerate if the server is using a different profile or level.
+        let config = &linden_server_tick.rules_state.config;
+        if (config.map_profile() != *self.timeline.map.get_profile() || config.level != self.map_level)
+        {
+            log!("Switching map to {:?} level {:?}", config.map_gen, config.level);
+            self.timeline.map = config.create_map(self.timeline.map.get_seed());
+            self.map_level = config.level;
+        }
 
-        if (should_reset)
+        // First tick since we joined, the game may be well under way and we have nothing to roll back to.
+        // Start from the server's lkg and let the tick loop catch us up.
+        if (self.lkg_rules_state.is_none() && self.timeline.try_get_state(linden_server_tick.lkg_state.frame_id).is_none())
         {
-            log!("Resetting!");
+            log!("Late join, starting from frame {}", linden_server_tick.lkg_state.frame_id);
+            let lkg_state = &linden_server_tick.lkg_state;
             self.timeline = timeline::Timeline::from_server_parts_exact_seed(
                 self.timeline.map.get_seed(),
-                linden_server_tick.latest.frame_id,
-                linden_server_tick.latest.time_us,
-                linden_server_tick.latest.states.clone(),
-                linden_server_tick.rules_state.clone());
+                lkg_state.frame_id,
+                lkg_state.time_us,
+                lkg_state.get_valid_player_states(),
+                lkg_state.rules_state.clone());
+        }
+
+        // Rolling back further than this costs too much, take the server's latest instead.
+        let top_frame_id = self.timeline.top_state().frame_id;
+        let rollback_frame_id = prediction::rollback_frame_id(&self.timeline, &linden_server_tick.lkg_state, &linden_server_tick.delta_inputs);
+        let should_reset = rollback_frame_id.map(|x| top_frame_id.saturating_sub(x) > self.prediction_settings.max_rollback_frames).unwrap_or(false);
+
+        if (should_reset)
+        {
+            log!("Resetting! rollback of {} frames", top_frame_id.saturating_sub(rollback_frame_id.unwrap_or(top_frame_id)));
+            if (linden_server_tick.latest.frame_id <= top_frame_id)
+            {
+                // Keep our own inputs since the server's latest
+                let latest = game::GameState::from_server_parts(
+                    linden_server_tick.latest.frame_id,
+                    linden_server_tick.latest.time_us,
+                    linden_server_tick.latest.states.clone(),
+                    linden_server_tick.rules_state.clone());
+                self.timeline = self.timeline.rebase(&latest);
+            }
+            else
+            {
+                self.timeline = timeline::Timeline::from_server_parts_exact_seed(
+                    self.timeline.map.get_seed(),
+                    linden_server_tick.latest.frame_id,
+                    linden_server_tick.latest.time_us,
+                    linden_server_tick.latest.states.clone(),
+                    linden_server_tick.rules_state.clone());
+            }
         }
         else
         {
@@ -428,6 +564,11 @@ impl Client {
         self.untrusted_rules_state = Some(linden_server_tick.rules_state.clone());
         self.lkg_rules_state = Some(linden_server_tick.lkg_state.rules_state.clone());
 
+        // Server picks lkgs from the inputs it has from us so they only move forward,
+        // keep a round trip of slack in case the estimate jumps.
+        let rtt_frames = (2. * self.estimated_latency_us / TICK_INTERVAL_US as f32) as u32;
+        self.timeline.trim_before(linden_server_tick.lkg_state.frame_id.saturating_sub(rtt_frames));
+
         true
     }
 
@@ -466,8 +607,20 @@ impl Client {
             },
 
             interop::CrossyMessage::LindenServerTick(linden_server_tick) => {
+                let linden_server_tick = self.tick_decoder.receive_full(linden_server_tick);
                 self.queued_server_linden_messages.push_front(linden_server_tick);
             }
+            interop::CrossyMessage::LindenServerTickDelta(delta) => {
+                match self.tick_decoder.receive_delta(&delta) {
+                    Some(linden_server_tick) => {
+                        self.queued_server_linden_messages.push_front(linden_server_tick);
+                    },
+                    None => {
+                        // Server will fall back to full ticks once our acks stop moving
+                        log!("Dropping tick {}, missing baseline {}", delta.tick_id, delta.baseline_tick_id);
+                    }
+                }
+            }
             _ => {},
         }
     }
@@ -475,7 +628,7 @@ impl Client {
     pub fn get_client_message(&mut self) -> Vec<u8>
     {
         let message = self.get_client_message_internal();
-        flexbuffers::to_vec(message).unwrap()
+        interop::wire::encode(&message)
     }
 
     fn get_client_message_internal(&mut self) -> interop::CrossyMessage
@@ -524,7 +677,7 @@ impl Client {
     pub fn get_time_request(&self) -> Vec<u8>
     {
         let message = self.get_time_request_internal();
-        flexbuffers::to_vec(message).unwrap()
+        interop::wire::encode(&message)
     }
 
     fn get_time_request_internal(&self) -> interop::CrossyMessage
@@ -548,19 +701,36 @@ impl Client {
     pub fn get_telemetry_message(&mut self) -> Vec<u8>
     {
         let message = self.get_telemetry_message_internal();
-        flexbuffers::to_vec(message).unwrap()
+        interop::wire::encode(&message)
     }
 
     pub fn has_telemetry_messages(&self) -> bool {
         !self.telemetry_buffer.buffer.is_empty()
     }
 
+    pub fn get_ack_message(&mut self) -> Vec<u8>
+    {
+        let tick_id = self.tick_decoder.take_ack().unwrap_or_default();
+        interop::wire::encode(&interop::CrossyMessage::ServerTickAck(tick_id))
+    }
+
+    pub fn has_ack_message(&self) -> bool {
+        self.tick_decoder.has_ack()
+    }
+
     pub fn get_players_json(&self) -> String
     {
         let time_us = self.timeline.top_state().time_us;
         let players : Vec<_> = self.timeline.top_state().get_valid_player_states()
             .iter()
-            .map(|x| x.to_public(self.get_round_id(), time_us, &self.timeline.map))
+            .map(|x| {
+                let error = self.error_correction.offset(x.id);
+                PlayerView {
+                    state : x.to_public(self.get_round_id(), time_us, &self.timeline.map),
+                    error_x : error.x,
+                    error_y : error.y,
+                }
+            })
             .collect();
 
         serde_json::to_string(&players).unwrap()
@@ -601,6 +771,17 @@ impl Client {
         serde_json::to_string(&cars).unwrap()
     }
 
+    // Pickups on screen that nobody has taken yet.
+    pub fn get_pickups_json(&self) -> String {
+        let screen_y = self.untrusted_rules_state.as_ref().map(|x| x.fst.get_screen_y()).unwrap_or(0);
+        let top = self.timeline.top_state();
+        let pickups : Vec<_> = self.timeline.map.get_pickups(self.get_round_id(), screen_y)
+            .into_iter()
+            .filter(|x| !top.pickup_taken(x.y))
+            .collect();
+        serde_json::to_string(&pickups).unwrap()
+    }
+
     pub fn get_lillipads_json(&self) -> String {
         let lillipads = self.timeline.map.get_lillipads(self.get_round_id(), self.timeline.top_state().time_us);
         serde_json::to_string(&lillipads).unwrap()
@@ -662,6 +843,7 @@ impl Client {
             map::RowType::Path{..} => true,
             map::RowType::Stands => true,
             map::RowType::StartingBarrier => true,
+            map::RowType::FinishLine => true,
             _ => false,
         }
     }
@@ -683,7 +865,7 @@ impl Client {
             },
             "go_up" => {
                 log!("Setting ai agent to 'go_up'");
-                self.ai_agent = Some(RefCell::new(Box::new(ai::go_up::GoUpAI::new(local_player_id))));
+                self.ai_agent = Some(RefCell::new(Box::new(ai::go_up::GoUpAI::new(local_player_id).with_debug_draw())));
             },
             "back_and_forth" => {
                 log!("Setting ai agent to 'back_and_forth'");
@@ -702,7 +884,7 @@ impl Client {
                 return None;
             }
 
-            if let Some(commands) = self.ai_agent.as_ref().map(|x| x.borrow().get_drawstate().clone()) {
+            if let Some(commands) = self.ai_agent.as_ref().and_then(|x| x.borrow().get_drawstate().cloned()) {
                 for command in commands.commands {
                     draw_state.commands.push(command);
                 }
@@ -818,6 +1000,15 @@ impl RoundIdentifier {
     }
 }
 
+// Public state plus how far off the authoritative position to draw while blending out corrections.
+#[derive(serde::Serialize, Debug)]
+struct PlayerView {
+    #[serde(flatten)]
+    state : player::PlayerStatePublic,
+    error_x : f32,
+    error_y : f32,
+}
+
 #[derive(serde::Serialize, Debug, Clone)]
 struct LillyOverlay {
     precise_coords : PreciseCoords,
@@ -861,8 +1052,7 @@ fn get_lilly_moves(initial_pos : &PreciseCoords, on_lilly: bool, round_id : u8,
 
 fn try_deserialize_message(buffer : &[u8]) -> Option<interop::CrossyMessage>
 {
-    let reader = flexbuffers::Reader::get_root(buffer).map_err(|e| log!("{:?}", e)).ok()?;
-    interop::CrossyMessage::deserialize(reader).map_err(|e| log!("{:?}", e)).ok()
+    interop::wire::decode(buffer).map_err(|e| log!("{}", e)).ok()
 }
 
 fn dan_lerp(x0 : f32, x : f32, k : f32) -> f32 {
@@ -1,7 +1,7 @@
 use std::io::Write;
 
 use chrono::prelude::*;
-use crossy_multi_core::crossy_ruleset::GameConfig;
+use crossy_multi_core::crossy_ruleset::{CrossyRulesetFST, GameConfig};
 use serde::{Deserialize, Serialize};
 use warp::hyper::client;
 use std::time::{Duration, Instant};
@@ -9,31 +9,158 @@ use tokio::sync::Mutex;
 
 use crossy_multi_core::game;
 use crossy_multi_core::interop::*;
+use crossy_multi_core::interop::delta::TickEncoder;
+use crossy_multi_core::input_validation::{InputLimits, InputValidator};
 use crossy_multi_core::player_id_map::PlayerIdMap;
+use crossy_multi_core::replay::{Replay, ReplayRecorder};
 use crossy_multi_core::timeline::{RemoteInput, RemoteTickState, Timeline, TICK_INTERVAL_US};
 
+use crate::metrics::{self, METRICS};
+
 const SERVER_VERSION: u8 = 1;
 const DESIRED_TICK_TIME: Duration = Duration::from_nanos(16_666_666);
 
+// How long a dropped player's frog idles waiting for them to come back with their reconnect token.
+const RECONNECT_GRACE: Duration = Duration::from_secs(30);
+
+// find_spawn_pos has room for 49, keep well clear of that.
+pub const MAX_PLAYERS: u8 = 16;
+
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct SocketId(pub u32);
 
+// Server side only, chosen at /new.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct LobbyOptions {
+    // Shows up in /games and quickmatch, private games need the id.
+    pub public: bool,
+    pub max_players: u8,
+}
+
+impl Default for LobbyOptions {
+    fn default() -> Self {
+        Self {
+            public: false,
+            max_players: 8,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct GameListing {
+    pub game_id: crate::GameId,
+    pub player_count: u8,
+    pub max_players: u8,
+    pub phase: &'static str,
+}
+
 struct PlayerClient {
     id: game::PlayerId,
     last_tick_us: u32,
+    // Handed out at /play, None once the player has left for good.
+    reconnect_token: Option<String>,
+    disconnected_at: Option<Instant>,
+    // Follows the player across reconnects so strikes don't reset.
+    inputs: InputValidator,
+}
+
+// Someone who has left the game for good, their socket is gone but the id isn't handed out again.
+struct DepartedPlayer {
+    id: game::PlayerId,
+    // Kicked players keep their token so coming back with it is refused.
+    banned_token: Option<String>,
+}
+
+// Written on shutdown so a restart doesn't end the match.
+// Players are restored as disconnected and come back in with their reconnect tokens.
+//
+// On disk it is a small header (magic + version) followed by a flexbuffers body, same as replays.
+const SNAPSHOT_MAGIC: &[u8; 4] = b"crss";
+const SNAPSHOT_VERSION: u8 = 1;
+
+#[derive(Serialize, Deserialize)]
+pub struct ServerSnapshot {
+    pub game_id: crate::GameId,
+    lobby_options: LobbyOptions,
+    start_utc_ms: i64,
+    state: game::GameState,
+    // Includes players who have left, so their ids aren't reused.
+    players: Vec<PlayerSnapshot>,
+    replay: Replay,
+}
+
+#[derive(Serialize, Deserialize)]
+struct PlayerSnapshot {
+    id: game::PlayerId,
+    // For kicked players this is their banned token.
+    reconnect_token: Option<String>,
+    kicked: bool,
+}
+
+impl ServerSnapshot {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(1024);
+        bytes.extend_from_slice(SNAPSHOT_MAGIC);
+        bytes.push(SNAPSHOT_VERSION);
+        bytes.extend(flexbuffers::to_vec(self).unwrap());
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
+        if (bytes.len() < SNAPSHOT_MAGIC.len() + 1 || &bytes[0..4] != SNAPSHOT_MAGIC) {
+            return Err("not a snapshot file".to_owned());
+        }
+
+        if (bytes[4] != SNAPSHOT_VERSION) {
+            return Err(format!("unsupported snapshot version {}", bytes[4]));
+        }
+
+        flexbuffers::from_slice(&bytes[5..]).map_err(|e| e.to_string())
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct PlayResponse {
+    #[serde(flatten)]
+    pub init: InitServerResponse,
+    pub reconnect_token: String,
 }
 
 struct Client {
     player_client: Option<PlayerClient>,
     socket_id: SocketId,
+    // Negotiated in /join, see interop::wire.
+    protocol_version: u8,
+    // Watches without ever getting a player.
+    spectator: bool,
+    ticks: TickEncoder,
+    // Smoothed from tick acks, only for clients that send them.
+    rtt_frames: Option<u32>,
+}
+
+// Messages out to websockets, addressed to a single socket or everyone.
+#[derive(Clone, Debug)]
+pub struct Outbound {
+    pub to: Option<SocketId>,
+    pub message: CrossyMessage,
+}
+
+impl Outbound {
+    fn all(message: CrossyMessage) -> Self {
+        Self { to: None, message }
+    }
+
+    pub fn is_for(&self, socket_id: SocketId) -> bool {
+        self.to.map(|x| x == socket_id).unwrap_or(true)
+    }
 }
 
 pub struct Server {
     queued_messages: Mutex<Vec<(CrossyMessage, SocketId, Instant)>>,
     pub inner: Mutex<ServerInner>,
 
-    outbound_tx: tokio::sync::broadcast::Sender<CrossyMessage>,
-    outbound_rx: tokio::sync::broadcast::Receiver<CrossyMessage>,
+    outbound_tx: tokio::sync::broadcast::Sender<Outbound>,
+    outbound_rx: tokio::sync::broadcast::Receiver<Outbound>,
 }
 
 pub struct ServerInner {
@@ -44,6 +171,7 @@ pub struct ServerInner {
     start_utc: DateTime<Utc>,
 
     clients: Vec<Client>,
+    departed: Vec<DepartedPlayer>,
     next_socket_id: SocketId,
     pub ended: bool,
 
@@ -51,13 +179,79 @@ pub struct ServerInner {
     tracer_tmp_file : std::fs::File,
 
     timeline: Timeline,
+    input_counts: InputCounts,
+    lobby_options: LobbyOptions,
+    input_limits: InputLimits,
     input_history : InputHistory,
+    replay_recorder : ReplayRecorder,
 }
 
 impl Server {
-    pub fn new(config : GameConfig, id: &crate::GameId) -> Self {
-        let start = Instant::now();
-        let start_utc = Utc::now();
+    pub fn new(config : GameConfig, lobby_options : LobbyOptions, input_limits : InputLimits, id: &crate::GameId) -> Self {
+        let timeline = Timeline::from_seed(config, &id.0);
+        let replay_recorder = ReplayRecorder::from_timeline(&timeline);
+        Self::from_parts(id, timeline, replay_recorder, lobby_options, input_limits, Instant::now(), Utc::now())
+    }
+
+    pub fn from_snapshot(snapshot: ServerSnapshot, input_limits : InputLimits) -> Self {
+        let id = &snapshot.game_id;
+        let state = &snapshot.state;
+        let timeline = Timeline::from_server_parts(
+            &id.0,
+            state.frame_id,
+            state.time_us,
+            state.get_valid_player_states(),
+            state.rules_state.clone());
+
+        // Pick up the clock where we left off, clients resync through time requests.
+        let now = Instant::now();
+        let start = now.checked_sub(Duration::from_micros(state.time_us as u64)).unwrap_or(now);
+        let start_utc = Utc.timestamp_millis_opt(snapshot.start_utc_ms).single().unwrap_or_else(Utc::now);
+
+        let mut server = Self::from_parts(
+            id,
+            timeline,
+            ReplayRecorder::resume(snapshot.replay),
+            snapshot.lobby_options,
+            input_limits,
+            start,
+            start_utc);
+
+        let inner = server.inner.get_mut();
+        for player in snapshot.players {
+            if (player.kicked || player.reconnect_token.is_none()) {
+                inner.departed.push(DepartedPlayer {
+                    id: player.id,
+                    banned_token: if (player.kicked) { player.reconnect_token } else { None },
+                });
+                continue;
+            }
+
+            // Placeholder socket nobody connects to, like one that dropped.
+            let socket_id = inner.add_client(wire::PROTOCOL_VERSION, false);
+            let client = inner.get_client_mut_by_addr(socket_id).unwrap();
+            client.player_client = Some(PlayerClient {
+                id: player.id,
+                last_tick_us: state.time_us,
+                disconnected_at: Some(now),
+                reconnect_token: player.reconnect_token,
+                inputs: InputValidator::default(),
+            });
+        }
+
+        println!("[{:?}] Restored at frame {} with {} players", id, state.frame_id, inner.player_count());
+        server
+    }
+
+    fn from_parts(
+        id: &crate::GameId,
+        timeline: Timeline,
+        replay_recorder: ReplayRecorder,
+        lobby_options: LobbyOptions,
+        input_limits: InputLimits,
+        start: Instant,
+        start_utc: DateTime<Utc>,
+    ) -> Self {
         let (outbound_tx, outbound_rx) = tokio::sync::broadcast::channel(1024);
 
         let tracer = crossy_multi_core::telemetry::TelemetryTracer::new(&format!("logs/{}.log", &id.0));
@@ -83,6 +277,7 @@ impl Server {
                 game_id: id.clone(),
                 empty_ticks: 0,
                 clients: Vec::new(),
+                departed: Vec::new(),
                 new_players: Vec::new(),
 
                 start,
@@ -93,8 +288,12 @@ impl Server {
                 tracer,
                 tracer_tmp_file,
 
-                timeline: Timeline::from_seed(config, &id.0),
+                timeline,
+                input_counts: Default::default(),
+                lobby_options,
+                input_limits,
                 input_history: Default::default(),
+                replay_recorder,
             }),
         }
     }
@@ -134,13 +333,87 @@ impl Server {
         }
     }
 
-    pub async fn join(&self) -> SocketId {
+    // None once the game has ended.
+    pub async fn snapshot(&self) -> Option<ServerSnapshot> {
+        let inner = self.inner.lock().await;
+        if (inner.ended) {
+            return None;
+        }
+
+        let players = inner.clients.iter()
+            .filter_map(|x| x.player_client.as_ref())
+            .map(|x| PlayerSnapshot {
+                id: x.id,
+                reconnect_token: x.reconnect_token.clone(),
+                kicked: false,
+            })
+            .chain(inner.departed.iter().map(|x| PlayerSnapshot {
+                id: x.id,
+                reconnect_token: x.banned_token.clone(),
+                kicked: x.banned_token.is_some(),
+            }))
+            .collect();
+
+        Some(ServerSnapshot {
+            game_id: inner.game_id.clone(),
+            lobby_options: inner.lobby_options,
+            start_utc_ms: inner.start_utc.timestamp_millis(),
+            state: inner.timeline.top_state().clone(),
+            players,
+            replay: inner.replay_recorder.replay().clone(),
+        })
+    }
+
+    pub async fn stats(&self) -> Option<metrics::GameStats> {
+        let inner = self.inner.lock().await;
+        if (inner.ended) {
+            return None;
+        }
+
+        let players_connected = inner.clients.iter()
+            .filter_map(|x| x.player_client.as_ref())
+            .filter(|x| x.reconnect_token.is_some() && x.disconnected_at.is_none())
+            .count() as u32;
+
+        Some(metrics::GameStats {
+            game_id: inner.game_id.clone(),
+            players_connected,
+            late_inputs: inner.input_counts.late,
+            future_input_requeues: inner.input_counts.future_requeues,
+            rejected_inputs: inner.input_counts.rejected,
+        })
+    }
+
+    // None for private games and games that have shut down.
+    pub async fn listing(&self) -> Option<GameListing> {
+        let inner = self.inner.lock().await;
+        if (!inner.lobby_options.public || inner.ended) {
+            return None;
+        }
+
+        Some(GameListing {
+            game_id: inner.game_id.clone(),
+            player_count: inner.player_count(),
+            max_players: inner.lobby_options.max_players,
+            phase: inner.timeline.top_state().rules_state.fst.phase_name(),
+        })
+    }
+
+    pub async fn join(&self, hello: &ClientHello, spectator: bool) -> SocketId {
         let mut inner = self.inner.lock().await;
-        let new_socket = inner.add_client();
-        println!("[{:?}] /join - player_id {:?}", inner.game_id, new_socket);
+        let new_socket = inner.add_client(hello.version(), spectator);
+        println!("[{:?}] /join - socket_id {:?} protocol version {} spectator {}", inner.game_id, new_socket, hello.version(), spectator);
         new_socket
     }
 
+    pub async fn protocol_version(&self, socket_id: SocketId) -> u8 {
+        let inner = self.inner.lock().await;
+        inner
+            .get_client_by_addr(socket_id)
+            .map(|x| x.protocol_version)
+            .unwrap_or(wire::PROTOCOL_VERSION)
+    }
+
     pub async fn time_since(&self) -> Duration {
         let inner = self.inner.lock().await;
         let now = Instant::now();
@@ -160,43 +433,85 @@ impl Server {
 
     pub async fn play(
         &self,
-        hello: &ClientHello,
         socket_id: SocketId,
-    ) -> Option<InitServerResponse> {
+        reconnect_token: Option<&str>,
+    ) -> Option<PlayResponse> {
         let mut inner = self.inner.lock().await;
 
         println!(
-            "[{:?}] /play {:?} {:?} looks ok: {}",
+            "[{:?}] /play {:?} reconnecting {}",
             inner.game_id,
             socket_id,
-            &hello,
-            hello.check(1)
+            reconnect_token.is_some(),
         );
 
-        let client_id = game::PlayerId(inner.clients.len() as u8);
-        inner.new_players.push(client_id);
-
         // Fails if socket_id not found
         // In prod version dont crash here?
-        let mut client = inner
-            .get_client_mut_by_addr(socket_id)
+        let client = inner
+            .get_client_by_addr(socket_id)
             .expect("client tried to /play without calling /join");
-        client.player_client = Some(PlayerClient {
-            id: client_id,
-            last_tick_us: 0,
-        });
+        if (client.spectator) {
+            println!("[{:?}] Spectator {:?} tried to /play", inner.game_id, socket_id);
+            return None;
+        }
 
-        Some(InitServerResponse {
-            server_version: SERVER_VERSION,
-            //player_count: inner.timeline.player_count,
-            // unused I think, clean up
-            player_count: 0,
-            seed: inner.timeline.map.get_seed(),
-            player_id: client_id,
+        if (reconnect_token.is_some() && inner.departed.iter().any(|x| x.banned_token.as_deref() == reconnect_token)) {
+            println!("[{:?}] Kicked player tried to /play again on {:?}", inner.game_id, socket_id);
+            return None;
+        }
+
+        // Take back a player we are holding on to, their frog has been idling in the game.
+        // The old socket might not have noticed it's dead yet, the token is enough.
+        let reconnecting = reconnect_token.and_then(|token| inner.clients.iter()
+            .position(|x| x.socket_id != socket_id && x.player_client.as_ref().and_then(|x| x.reconnect_token.as_deref()) == Some(token)))
+            .and_then(|i| inner.clients.remove(i).player_client);
+
+        let player_client = match reconnecting {
+            Some(mut player_client) => {
+                println!("[{:?}] Player {:?} reconnected on {:?}", inner.game_id, player_client.id, socket_id);
+                player_client.disconnected_at = None;
+                player_client
+            }
+            None => {
+                if (inner.player_count() >= inner.lobby_options.max_players) {
+                    println!("[{:?}] Game full, {:?} can't /play", inner.game_id, socket_id);
+                    return None;
+                }
+
+                let client_id = inner.next_player_id();
+                inner.new_players.push(client_id);
+                PlayerClient {
+                    id: client_id,
+                    last_tick_us: 0,
+                    reconnect_token: Some(format!("{:016x}", rand::random::<u64>())),
+                    disconnected_at: None,
+                    inputs: InputValidator::default(),
+                }
+            }
+        };
+
+        let client_id = player_client.id;
+        let reconnect_token = player_client.reconnect_token.clone().unwrap_or_default();
+
+        let client = inner.get_client_mut_by_addr(socket_id).unwrap();
+        client.player_client = Some(player_client);
+        let protocol_version = client.protocol_version;
+
+        Some(PlayResponse {
+            init: InitServerResponse {
+                server_version: SERVER_VERSION,
+                //player_count: inner.timeline.player_count,
+                // unused I think, clean up
+                player_count: 0,
+                seed: inner.timeline.map.get_seed(),
+                player_id: client_id,
+                protocol_version,
+            },
+            reconnect_token,
         })
     }
 
-    pub fn get_listener(&self) -> tokio::sync::broadcast::Receiver<CrossyMessage> {
+    pub fn get_listener(&self) -> tokio::sync::broadcast::Receiver<Outbound> {
         self.outbound_tx.subscribe()
     }
 
@@ -213,193 +528,239 @@ impl Server {
     pub async fn run(&self) {
         loop {
             let tick_start = Instant::now();
-            let mut new_players = Vec::new();
-
-            {
-                let mut inner = self.inner.lock().await;
-
-                // Fetch + clear list of new players
-                new_players = std::mem::take(&mut inner.new_players);
-
-                // Do simulations
-                let current_time = inner.start.elapsed();
-                let current_time_us = current_time.as_micros() as u32;
-
-                loop {
-                    let last_time = inner.timeline.top_state().time_us;
-                    let delta_time = current_time_us.saturating_sub(last_time);
-                    if (delta_time > TICK_INTERVAL_US)
-                    {
-                        inner.timeline.tick(None, TICK_INTERVAL_US);
-                    }
-                    else
-                    {
-                        // @TMP DAN REMOVE MEE
-                        const DEBUG_LOG_ALL_STATES : bool = false;
-                        if (DEBUG_LOG_ALL_STATES)
-                        {
-                            let glah = inner.timeline.top_state().clone();
-                            writeln!(inner.tracer_tmp_file, "LOOP START \n {:#?}", glah).unwrap();
-                        }
-
-                        break;
-                    }
-                }
+            if (!self.tick().await) {
+                return;
             }
 
-            let (client_updates, dropped_players) = self.receive_updates().await;
-            let mut inner = self.inner.lock().await;
+            let now = Instant::now();
+            let elapsed_time = now.saturating_duration_since(tick_start);
+            METRICS.observe_tick_duration(elapsed_time);
+            if let Some(sleep_time) = DESIRED_TICK_TIME.checked_sub(elapsed_time) {
+                tokio::time::sleep(sleep_time).await;
+            }
+        }
+    }
 
-            let mut nonempty_updates = Vec::with_capacity(client_updates.len());
+    // One pass of the game loop, false once the game has shut down.
+    async fn tick(&self) -> bool {
+        let mut new_players = Vec::new();
 
-            let current_frame_id = inner.timeline.top_state().frame_id;
+        {
+            let mut inner = self.inner.lock().await;
 
-            for (update, time) in client_updates.iter() {
+            // Fetch + clear list of new players
+            new_players = std::mem::take(&mut inner.new_players);
 
-                if (update.input == game::Input::None) {
-                    continue;
-                }
+            // Do simulations
+            let current_time = inner.start.elapsed();
+            let current_time_us = current_time.as_micros() as u32;
+            let was_match_end = matches!(inner.timeline.top_state().rules_state.fst, CrossyRulesetFST::EndWinner(_));
 
-                if (update.frame_id > current_frame_id)
+            loop {
+                let last_time = inner.timeline.top_state().time_us;
+                let delta_time = current_time_us.saturating_sub(last_time);
+                if (delta_time > TICK_INTERVAL_US)
                 {
-                    println!("WARNING: Future input, can happen due to latency approximations. Sending back to queue");
-                    let socket_id = inner.get_socket_id_by_player(update.player_id).unwrap();
-                    self.queue_message(CrossyMessage::ClientTick(vec![
-                        crossy_multi_core::interop::ClientTick {
-                            time_us: update.time_us,
-                            frame_id: update.frame_id,
-                            input : update.input,
-                        }
-                    ]), socket_id).await;
-
-                    /*
-                    panic!("Got client update with frame id in the future!!\n\n frame_id {}\n top state {:?}\n\n update {:?}",
-                        update.frame_id,
-                        inner.timeline.top_state(),
-                        update);
-                        */
+                    inner.timeline.tick(None, TICK_INTERVAL_US);
                 }
                 else
                 {
-                    nonempty_updates.push((update.clone(), time));
+                    // @TMP DAN REMOVE MEE
+                    const DEBUG_LOG_ALL_STATES : bool = false;
+                    if (DEBUG_LOG_ALL_STATES)
+                    {
+                        let glah = inner.timeline.top_state().clone();
+                        writeln!(inner.tracer_tmp_file, "LOOP START \n {:#?}", glah).unwrap();
+                    }
+
+                    break;
                 }
             }
 
-            for (update, receive_time) in &nonempty_updates {
-                let receive_time_us = receive_time
-                    .saturating_duration_since(inner.start)
-                    .as_micros() as u32;
-                let delta = (update.time_us as f32 - receive_time_us as f32) / 1000.;
-                //let delta = (update.time_us as i32 - inner.timeline.top_state().time_us as i32) / 1000;
-                println!(
-                    "[{:?}] Update - {:?} at client time {}ms, receive_time {}ms, delta {}ms",
-                    update.player_id,
-                    update.input,
-                    update.time_us / 1000,
-                    receive_time_us / 1000,
-                    delta.floor()
-                );
-            }
+            let top_frame_id = inner.timeline.top_state().frame_id;
+            inner.replay_recorder.record_frame(top_frame_id);
 
-            if (nonempty_updates.len() > 0) {
-                let propagate_result = inner.timeline.try_propagate_inputs(nonempty_updates.into_iter().map(|(x, _)| x).collect(), true);
-                assert!(propagate_result);
+            // Write out each finished match so we still have a replay if the server goes down later.
+            let is_match_end = matches!(inner.timeline.top_state().rules_state.fst, CrossyRulesetFST::EndWinner(_));
+            if (is_match_end && !was_match_end) {
+                inner.write_replay();
             }
+        }
 
-            for new_player in new_players.iter().cloned() {
-                // We need to make sure this gets propagated properly
-                // Weird edge case bugs
-                println!(
-                    "[{:?}] In run, adding a new player {:?}",
-                    inner.game_id, new_player
-                );
-                let spawn_pos = find_spawn_pos(inner.timeline.top_state());
-                println!(
-                    "[{:?}] Spawning new player at {:?}",
-                    inner.game_id, spawn_pos
-                );
-
-                inner.timeline.add_player(new_player, spawn_pos);
-            }
+        let (client_updates, dropped_players) = self.receive_updates().await;
+        let mut inner = self.inner.lock().await;
+
+        let mut nonempty_updates = Vec::with_capacity(client_updates.len());
+
+        let current_frame_id = inner.timeline.top_state().frame_id;
+
+        for (update, time) in client_updates.iter() {
 
-            for dropped_player in dropped_players {
-                println!("[{:?}] Dropping player {:?}", inner.game_id, dropped_player);
-                inner.timeline.remove_player(dropped_player);
+            if (update.input == game::Input::None) {
+                continue;
             }
 
-            // Generate last sent times
-            let mut last_client_sent = PlayerIdMap::new();
-            for client in (&inner.clients)
-                .iter()
-                .filter_map(|x| x.player_client.as_ref())
+            if (update.frame_id > current_frame_id)
             {
-                inner
-                    .timeline
-                    .get_state_before_eq_us(client.last_tick_us)
-                    .map(|x| {
-                        last_client_sent.set(
-                            client.id,
-                            RemoteTickState {
-                                frame_id: x.frame_id,
-                                time_us: x.time_us,
-                                states: x.get_valid_player_states(),
-                            },
-                        );
-                    });
+                println!("WARNING: Future input, can happen due to latency approximations. Sending back to queue");
+                inner.input_counts.future_requeues += 1;
+                // Gone since, nowhere to requeue to
+                let Some(socket_id) = inner.get_socket_id_by_player(update.player_id) else {
+                    continue;
+                };
+                self.queue_message(CrossyMessage::ClientTick(vec![
+                    crossy_multi_core::interop::ClientTick {
+                        time_us: update.time_us,
+                        frame_id: update.frame_id,
+                        input : update.input,
+                    }
+                ]), socket_id).await;
+
+                /*
+                panic!("Got client update with frame id in the future!!\n\n frame_id {}\n top state {:?}\n\n update {:?}",
+                    update.frame_id,
+                    inner.timeline.top_state(),
+                    update);
+                    */
             }
+            else
+            {
+                if (update.frame_id < current_frame_id) {
+                    inner.input_counts.late += 1;
+                }
 
+                nonempty_updates.push((update.clone(), time));
+            }
+        }
 
-            // Send responses
-            let top_state = inner.timeline.top_state();
+        for (update, receive_time) in &nonempty_updates {
+            let receive_time_us = receive_time
+                .saturating_duration_since(inner.start)
+                .as_micros() as u32;
+            let delta = (update.time_us as f32 - receive_time_us as f32) / 1000.;
+            //let delta = (update.time_us as i32 - inner.timeline.top_state().time_us as i32) / 1000;
+            println!(
+                "[{:?}] Update - {:?} at client time {}ms, receive_time {}ms, delta {}ms",
+                update.player_id,
+                update.input,
+                update.time_us / 1000,
+                receive_time_us / 1000,
+                delta.floor()
+            );
+        }
 
-            // FIXME: We currently take -100 frames, we could do something smarter with the min last send time of clients 
-            // Do we need to be smart?
-            let lkg_frame_id = inner.timeline.top_state().frame_id.saturating_sub(100);
-            let delta_inputs = inner.timeline.inputs_since_frame(lkg_frame_id);
+        if (nonempty_updates.len() > 0) {
+            let inputs : Vec<RemoteInput> = nonempty_updates.into_iter().map(|(x, _)| x).collect();
+            let inner_ref = &mut *inner;
+            inner_ref.replay_recorder.record_inputs(&inner_ref.timeline, &inputs);
+            let propagate_result = inner_ref.timeline.try_propagate_inputs(inputs, true);
+            assert!(propagate_result);
+        }
 
-            let lkg_state = inner.timeline.try_get_state(lkg_frame_id).unwrap();
+        for new_player in new_players.iter().cloned() {
+            // We need to make sure this gets propagated properly
+            // Weird edge case bugs
+            println!(
+                "[{:?}] In run, adding a new player {:?}",
+                inner.game_id, new_player
+            );
+            let spawn_pos = find_spawn_pos(inner.timeline.top_state());
+            println!(
+                "[{:?}] Spawning new player at {:?}",
+                inner.game_id, spawn_pos
+            );
+
+            inner.timeline.add_player(new_player, spawn_pos);
+            let frame_id = inner.timeline.top_state().frame_id;
+            inner.replay_recorder.record_join(frame_id, new_player, spawn_pos);
+        }
 
-            let mut last_client_frame_id = PlayerIdMap::new();
-            for (pid, state) in last_client_sent.iter() {
-                last_client_frame_id.set(pid, state.frame_id);
-            }
+        for dropped_player in dropped_players {
+            println!("[{:?}] Dropping player {:?}", inner.game_id, dropped_player);
+            inner.timeline.remove_player(dropped_player);
+            let frame_id = inner.timeline.top_state().frame_id;
+            inner.replay_recorder.record_leave(frame_id, dropped_player);
+        }
 
-            let linden_tick = CrossyMessage::LindenServerTick(LindenServerTick {
-                latest : RemoteTickState::from_gamestate(top_state),
-                lkg_state : lkg_state.clone(),
-                delta_inputs: delta_inputs.iter().cloned().collect(),
-                last_client_frame_id,
-                rules_state: top_state.get_rule_state().clone(),
-            });
+        // Generate last sent times
+        let mut last_client_sent = PlayerIdMap::new();
+        for client in (&inner.clients)
+            .iter()
+            .filter_map(|x| x.player_client.as_ref())
+        {
+            inner
+                .timeline
+                .get_state_before_eq_us(client.last_tick_us)
+                .map(|x| {
+                    last_client_sent.set(
+                        client.id,
+                        RemoteTickState {
+                            frame_id: x.frame_id,
+                            time_us: x.time_us,
+                            states: x.get_valid_player_states(),
+                        },
+                    );
+                });
+        }
 
-            self.outbound_tx.send(linden_tick).unwrap();
 
-            // Timeout logic for when there are no players
-            if (self.outbound_tx.receiver_count() <= 1) {
-                inner.empty_ticks += 1;
-            } else {
-                inner.empty_ticks = 0;
-            }
+        // Send responses
 
-            inner.tracer.flush();
+        let mut last_client_frame_id = PlayerIdMap::new();
+        for (pid, state) in last_client_sent.iter() {
+            last_client_frame_id.set(pid, state.frame_id);
+        }
 
-            const EMPTY_TICKS_THRESHOLD: u32 = 60 * 20;
-            if (inner.empty_ticks > EMPTY_TICKS_THRESHOLD) {
-                // Noone left listening, shut down
-                println!("[{:?}] Shutting down game", inner.game_id);
-                self.outbound_tx.send(CrossyMessage::GoodBye()).unwrap();
-                inner.ended = true;
-                return;
+        // Each client gets a tick from its own lkg as a delta against the last one it acked
+        let inner_ref = &mut *inner;
+        let mut oldest_needed_frame_id = inner_ref.timeline.top_state().frame_id;
+        for client in inner_ref.clients.iter_mut() {
+            let acked_frame_id = client.player_client.as_ref().and_then(|x| last_client_frame_id.get(x.id).copied());
+            let lkg_frame_id = inner_ref.timeline.select_lkg_frame_id(acked_frame_id, client.rtt_frames);
+            oldest_needed_frame_id = oldest_needed_frame_id.min(lkg_frame_id);
+
+            let linden_tick = LindenServerTick::from_timeline(&inner_ref.timeline, lkg_frame_id, last_client_frame_id.clone());
+            // Legacy clients never ack so can't be sent deltas.
+            let message = if (client.protocol_version == wire::LEGACY_FLEXBUFFERS_VERSION) {
+                CrossyMessage::LindenServerTick(linden_tick)
             }
+            else {
+                client.ticks.encode(linden_tick)
+            };
+
+            // Errors when there are no listeners, fine.
+            let _ = self.outbound_tx.send(Outbound {
+                to: Some(client.socket_id),
+                message,
+            });
+        }
 
-            let now = Instant::now();
-            let elapsed_time = now.saturating_duration_since(tick_start);
-            if let Some(sleep_time) = DESIRED_TICK_TIME.checked_sub(elapsed_time) {
-                tokio::time::sleep(sleep_time).await;
-            }
+        // Late inputs land after the acked frames and lkgs are at or before them, so nothing older is needed.
+        inner_ref.timeline.trim_before(oldest_needed_frame_id);
+
+        // Timeout logic for when there are no players
+        // Keep going while anyone could still reconnect.
+        let awaiting_reconnect = inner.clients.iter().any(|x| x.player_client.as_ref().map(|x| x.disconnected_at.is_some()).unwrap_or(false));
+        if (self.outbound_tx.receiver_count() <= 1 && !awaiting_reconnect) {
+            inner.empty_ticks += 1;
+        } else {
+            inner.empty_ticks = 0;
+        }
+
+        inner.tracer.flush();
+
+        const EMPTY_TICKS_THRESHOLD: u32 = 60 * 20;
+        if (inner.empty_ticks > EMPTY_TICKS_THRESHOLD) {
+            // Noone left listening, shut down
+            println!("[{:?}] Shutting down game", inner.game_id);
+            self.outbound_tx.send(Outbound::all(CrossyMessage::GoodBye())).unwrap();
+            inner.write_replay();
+            inner.ended = true;
+            return false;
         }
-    }
 
+        true
+    }
 
     async fn receive_updates(
         &self,
@@ -417,51 +778,115 @@ impl Server {
 
         let mut inner = self.inner.lock().await;
         let mut dropped_players = vec![];
+        let mut rejected_inputs = vec![];
+        let input_limits = inner.input_limits;
+        let top_frame_id = inner.timeline.top_state().frame_id;
 
-        while let Some((message, socket_id, receive_time)) = queued_messages.pop() {
+        // In arrival order, the input validator expects each client's frames in sequence.
+        for (message, socket_id, receive_time) in queued_messages {
             match message {
-                CrossyMessage::ClientTick(client_ticks) => match inner.get_client_mut_by_addr(socket_id) {
-                    Some(client) => {
-                        if let Some(player_client) = client.player_client.as_mut() {
-                            for t in client_ticks
-                            {
-                                let client_time = t.time_us;
-                                player_client.last_tick_us = player_client.last_tick_us.max(client_time);
-
-                                client_updates.push((
-                                    RemoteInput {
-                                        time_us: client_time,
-                                        frame_id: t.frame_id,
-                                        input: t.input,
-                                        player_id: player_client.id,
-                                    },
-                                    receive_time,
-                                ));
+                CrossyMessage::ClientTick(client_ticks) => {
+                    let mid_moves: Vec<bool> = client_ticks.iter().map(|t| inner.mid_move(socket_id, t.frame_id)).collect();
+                    let mut kick = false;
+                    match inner.get_client_mut_by_addr(socket_id) {
+                        Some(client) => {
+                            if let Some(player_client) = client.player_client.as_mut() {
+                                for (t, mid_move) in client_ticks.into_iter().zip(mid_moves)
+                                {
+                                    if let Err(violation) = player_client.inputs.check(&input_limits, t.frame_id, t.input, top_frame_id, mid_move) {
+                                        if (t.input != game::Input::None) {
+                                            println!("[{:?}] Rejected {:?} at frame {} on {}: {}", player_client.id, t.input, t.frame_id, top_frame_id, violation);
+                                            rejected_inputs.push(crossy_multi_core::telemetry::TelemetryEvent {
+                                                player_id: player_client.id,
+                                                event: TelemetryMessage::InputRejected(Telemetry_InputRejected {
+                                                    frame_id: t.frame_id,
+                                                    server_frame_id: top_frame_id,
+                                                    input: t.input,
+                                                    violation,
+                                                    strikes: player_client.inputs.strikes(),
+                                                }),
+                                            });
+                                        }
+                                        continue;
+                                    }
+
+                                    let client_time = t.time_us;
+                                    player_client.last_tick_us = player_client.last_tick_us.max(client_time);
+
+                                    client_updates.push((
+                                        RemoteInput {
+                                            time_us: client_time,
+                                            frame_id: t.frame_id,
+                                            input: t.input,
+                                            player_id: player_client.id,
+                                        },
+                                        receive_time,
+                                    ));
+                                }
+
+                                if (player_client.inputs.should_kick(&input_limits)) {
+                                    println!("[{:?}] Kicking player {:?} after {} rejected inputs", socket_id, player_client.id, player_client.inputs.strikes());
+                                    kick = true;
+                                }
+                            } else if (!client.spectator) {
+                                println!("Received client update from client who has not called /play");
                             }
-                        } else {
-                            println!("Received client update from client who has not called /play");
+                        }
+                        None => {
+                            println!("Did not recognise addr {:?}", &socket_id);
                         }
                     }
-                    None => {
-                        println!("Did not recognise addr {:?}", &socket_id);
+
+                    if (kick) {
+                        dropped_players.extend(inner.remove_client(socket_id, true));
+                        let _ = self.outbound_tx.send(Outbound {
+                            to: Some(socket_id),
+                            message: CrossyMessage::GoodBye(),
+                        });
                     }
-                },
+                }
                 CrossyMessage::ClientDrop() => {
-                    if let Some(client) = inner.get_client_mut_by_addr(socket_id) {
-                        if let Some(player_client) = client.player_client.as_ref() {
-                            dropped_players.push(player_client.id);
+                    // Hold on to the player in case they come back, see RECONNECT_GRACE
+                    match inner.get_client_mut_by_addr(socket_id).map(|x| x.player_client.as_mut()) {
+                        Some(Some(player_client)) => {
+                            println!("Player {:?} disconnected, holding their slot", player_client.id);
+                            player_client.disconnected_at = Some(receive_time);
                         }
+                        Some(None) => {
+                            // Spectators and sockets that never played have nothing to hold on to.
+                            inner.remove_client(socket_id, false);
+                        }
+                        None => {}
                     }
                 }
                 CrossyMessage::TimeRequestIntermediate(time_request) => {
                     // Just forward straight over
                     self.outbound_tx
-                        .send(CrossyMessage::TimeRequestIntermediate(time_request))
+                        .send(Outbound::all(CrossyMessage::TimeRequestIntermediate(time_request)))
                         .unwrap();
                 }
+                CrossyMessage::ServerTickAck(tick_id) => {
+                    let top_frame_id = inner.timeline.top_state().frame_id;
+                    if let Some(client) = inner.get_client_mut_by_addr(socket_id) {
+                        if let Some(sent_frame_id) = client.ticks.ack(tick_id) {
+                            let sample = top_frame_id.saturating_sub(sent_frame_id);
+                            client.rtt_frames = Some(client.rtt_frames.map(|x| (x * 7 + sample) / 8).unwrap_or(sample));
+                        }
+                    }
+                }
                 CrossyMessage::TelemetryMessagePackage(telemetry_messages) => {
-                    let player_id = inner.get_client_by_addr(socket_id).unwrap().player_client.as_ref().unwrap().id;
+                    // Spectators and sockets that haven't called /play have no player to log against.
+                    let Some(player_id) = inner.get_client_by_addr(socket_id).and_then(|x| x.player_client.as_ref()).map(|x| x.id) else {
+                        println!("[{:?}] Ignoring telemetry from {:?} without a player", inner.game_id, socket_id);
+                        continue;
+                    };
+
                     for message in &telemetry_messages.messages {
+                        if let TelemetryMessage::PingOutcome(x) = message {
+                            // The client estimates one way latency as half the round trip.
+                            METRICS.observe_time_sync_rtt_us(x.unlerped_estimated_latency_us * 2);
+                        }
+
                         inner.tracer.push(crossy_multi_core::telemetry::TelemetryEvent {
                             player_id,
                             event: message.clone(),
@@ -472,17 +897,98 @@ impl Server {
             }
         }
 
+        inner.input_counts.rejected += rejected_inputs.len() as u64;
+        for event in rejected_inputs {
+            inner.tracer.push(event);
+        }
+
+        // Players who didn't make it back in time
+        let expired: Vec<SocketId> = inner.clients.iter()
+            .filter(|x| x.player_client.as_ref().and_then(|x| x.disconnected_at).map(|x| x.elapsed() > RECONNECT_GRACE).unwrap_or(false))
+            .map(|x| x.socket_id)
+            .collect();
+        for socket_id in expired {
+            dropped_players.extend(inner.remove_client(socket_id, false));
+        }
+
+        // A kicked player's inputs from earlier in the same tick go with them.
+        client_updates.retain(|(update, _)| !dropped_players.contains(&update.player_id));
+
         (client_updates, dropped_players)
     }
 }
 
+pub const REPLAY_DIR: &str = "replays";
+
+pub fn replay_path(game_id: &crate::GameId) -> String {
+    format!("{}/{}.replay", REPLAY_DIR, &game_id.0)
+}
+
 impl ServerInner {
-    fn add_client(&mut self) -> SocketId {
+    fn write_replay(&self) {
+        let path = replay_path(&self.game_id);
+        let result = std::fs::create_dir_all(REPLAY_DIR)
+            .and_then(|_| std::fs::write(&path, self.replay_recorder.replay().to_bytes()));
+
+        match result {
+            Ok(_) => println!("[{:?}] Wrote replay to {}", self.game_id, path),
+            Err(e) => println!("[{:?}] Failed to write replay {}: {}", self.game_id, path, e),
+        }
+    }
+
+    // Whether the socket's player was partway through their own move going into frame_id.
+    fn mid_move(&self, socket_id: SocketId, frame_id: u32) -> bool {
+        let Some(player_id) = self.get_client_by_addr(socket_id).and_then(|x| x.player_client.as_ref()).map(|x| x.id) else {
+            return false;
+        };
+
+        let (Some(prev), Some(state)) = (self.timeline.try_get_state(frame_id.saturating_sub(1)), self.timeline.try_get_state(frame_id)) else {
+            return false;
+        };
+
+        prev.get_player(player_id).map(|x| x.is_mid_move(state.time_us - prev.time_us)).unwrap_or(false)
+    }
+
+    // Players in the game or holding their slot for a reconnect.
+    fn player_count(&self) -> u8 {
+        self.clients.iter().filter(|x| x.player_client.is_some()).count() as u8
+    }
+
+    // Sockets that joined but never played, and spectators, don't take up ids.
+    // Players who left did, their win counts and teams are still keyed on them.
+    fn next_player_id(&self) -> game::PlayerId {
+        let mut id = 0;
+        while self.clients.iter().any(|x| x.player_client.as_ref().map(|x| x.id.0 == id).unwrap_or(false))
+            || self.departed.iter().any(|x| x.id.0 == id) {
+            id += 1;
+        }
+
+        game::PlayerId(id)
+    }
+
+    // The socket is gone for good, returns its player if it had one.
+    // Kicked players have their token banned so they can't /play their way back in.
+    fn remove_client(&mut self, socket_id: SocketId, kicked: bool) -> Option<game::PlayerId> {
+        let index = self.clients.iter().position(|x| x.socket_id == socket_id)?;
+        let player_client = self.clients.remove(index).player_client?;
+        self.departed.push(DepartedPlayer {
+            id: player_client.id,
+            banned_token: player_client.reconnect_token.filter(|_| kicked),
+        });
+
+        Some(player_client.id)
+    }
+
+    fn add_client(&mut self, protocol_version: u8, spectator: bool) -> SocketId {
         let socket_id = self.next_socket_id;
         self.next_socket_id = SocketId(socket_id.0 + 1);
         self.clients.push(Client {
             player_client: None,
             socket_id,
+            protocol_version,
+            spectator,
+            ticks: TickEncoder::default(),
+            rtt_frames: None,
         });
 
         socket_id
@@ -534,6 +1040,14 @@ fn find_spawn_pos(game_state: &crossy_multi_core::game::GameState) -> crossy_mul
 }
 
 
+// For /metrics.
+#[derive(Default)]
+struct InputCounts {
+    late: u64,
+    future_requeues: u64,
+    rejected: u64,
+}
+
 #[derive(Default)]
 struct InputHistory
 {
@@ -559,4 +1073,244 @@ impl InputHistory {
 
         &self.sorted_inputs[index..]
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_server(name: &str) -> Server {
+        test_server_with_limits(name, InputLimits::default())
+    }
+
+    fn test_server_with_limits(name: &str, input_limits: InputLimits) -> Server {
+        test_server_with_config(name, GameConfig::default(), input_limits)
+    }
+
+    fn test_server_with_config(name: &str, config: GameConfig, input_limits: InputLimits) -> Server {
+        std::fs::create_dir_all("logs").unwrap();
+        Server::new(config, LobbyOptions::default(), input_limits, &crate::GameId(name.to_owned()))
+    }
+
+    async fn client_count(server: &Server) -> usize {
+        server.inner.lock().await.clients.len()
+    }
+
+    fn latency_estimate() -> CrossyMessage {
+        CrossyMessage::TelemetryMessagePackage(TelemetryMessagePackage {
+            messages: vec![TelemetryMessage::LatencyEstimate(Telemetry_LatencyEstimate {
+                estimated_latency_us: 1000,
+                estimated_frame_delta: 0,
+                estimated_server_current_frame_id: 0,
+            })],
+        })
+    }
+
+    #[tokio::test]
+    async fn spectator_telemetry() {
+        let server = test_server("test_spectator_telemetry");
+        let hello = ClientHello::new(wire::PROTOCOL_VERSION);
+
+        let spectator = server.join(&hello, true).await;
+        let player = server.join(&hello, false).await;
+        assert!(server.play(player, None).await.is_some());
+
+        server.queue_message(latency_estimate(), spectator).await;
+        server.queue_message(latency_estimate(), player).await;
+        let (updates, dropped) = server.receive_updates().await;
+        assert!(updates.is_empty());
+        assert!(dropped.is_empty());
+
+        // Game carries on for everyone
+        assert!(server.play(server.join(&hello, false).await, None).await.is_some());
+    }
+
+    #[tokio::test]
+    async fn grace_expiry_removes_client() {
+        let server = test_server("test_grace_expiry");
+        let hello = ClientHello::new(wire::PROTOCOL_VERSION);
+
+        let socket = server.join(&hello, false).await;
+        let play = server.play(socket, None).await.unwrap();
+        let spectator = server.join(&hello, true).await;
+
+        let dropped_at = Instant::now().checked_sub(RECONNECT_GRACE * 2).unwrap();
+        server.queued_messages.lock().await.push((CrossyMessage::ClientDrop(), socket, dropped_at));
+        server.queue_message(CrossyMessage::ClientDrop(), spectator).await;
+        let (_, dropped) = server.receive_updates().await;
+        assert_eq!(dropped, vec![play.init.player_id]);
+        assert_eq!(client_count(&server).await, 0);
+
+        // Too late, the token gets a fresh player and the old id stays retired
+        let rejoin = server.play(server.join(&hello, false).await, Some(&play.reconnect_token)).await.unwrap();
+        assert_ne!(rejoin.init.player_id, play.init.player_id);
+    }
+
+    #[tokio::test]
+    async fn reconnect_replaces_socket() {
+        let server = test_server("test_reconnect_replaces_socket");
+        let hello = ClientHello::new(wire::PROTOCOL_VERSION);
+
+        let play = server.play(server.join(&hello, false).await, None).await.unwrap();
+        let rejoin = server.play(server.join(&hello, false).await, Some(&play.reconnect_token)).await.unwrap();
+        assert_eq!(rejoin.init.player_id, play.init.player_id);
+        assert_eq!(client_count(&server).await, 1);
+    }
+
+    #[tokio::test]
+    async fn kicked_player_is_banned() {
+        let server = test_server_with_limits("test_kicked_player_is_banned", InputLimits {
+            max_strikes: 1,
+            ..Default::default()
+        });
+        let hello = ClientHello::new(wire::PROTOCOL_VERSION);
+        let mut listener = server.get_listener();
+
+        let socket = server.join(&hello, false).await;
+        let play = server.play(socket, None).await.unwrap();
+
+        // Frame 0 is always too old
+        server.queue_message(CrossyMessage::ClientTick(vec![crossy_multi_core::interop::ClientTick {
+            time_us: 0,
+            frame_id: 0,
+            input: game::Input::Up,
+        }]), socket).await;
+        let (updates, dropped) = server.receive_updates().await;
+        assert!(updates.is_empty());
+        assert_eq!(dropped, vec![play.init.player_id]);
+        assert_eq!(client_count(&server).await, 0);
+
+        let goodbye = listener.try_recv().unwrap();
+        assert!(matches!(goodbye.message, CrossyMessage::GoodBye()));
+        assert_eq!(goodbye.to, Some(socket));
+
+        // Reloading with the token doesn't get them back in
+        assert!(server.play(server.join(&hello, false).await, Some(&play.reconnect_token)).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn kicked_with_future_input() {
+        let server = test_server_with_limits("test_kicked_with_future_input", InputLimits {
+            max_strikes: 1,
+            ..Default::default()
+        });
+        let hello = ClientHello::new(wire::PROTOCOL_VERSION);
+
+        let socket = server.join(&hello, false).await;
+        let play = server.play(socket, None).await.unwrap();
+        let top_frame_id = server.frame_id().await;
+
+        // The future input is held back for requeueing, frame 0 gets them kicked
+        server.queue_message(CrossyMessage::ClientTick(vec![
+            crossy_multi_core::interop::ClientTick {
+                time_us: 0,
+                frame_id: top_frame_id + 2,
+                input: game::Input::Up,
+            },
+            crossy_multi_core::interop::ClientTick {
+                time_us: 0,
+                frame_id: 0,
+                input: game::Input::Up,
+            },
+        ]), socket).await;
+        assert!(server.tick().await);
+        assert_eq!(client_count(&server).await, 0);
+        assert!(server.queued_messages.lock().await.is_empty());
+
+        // Game carries on for everyone else
+        let other = server.play(server.join(&hello, false).await, None).await.unwrap();
+        assert_ne!(other.init.player_id, play.init.player_id);
+        assert!(server.tick().await);
+    }
+
+    #[tokio::test]
+    async fn legacy_clients_get_full_ticks() {
+        let server = test_server("test_legacy_clients_get_full_ticks");
+        let mut listener = server.get_listener();
+
+        let legacy = server.join(&ClientHello::new(wire::LEGACY_FLEXBUFFERS_VERSION), false).await;
+        let current = server.join(&ClientHello::new(wire::PROTOCOL_VERSION), false).await;
+        assert!(server.play(legacy, None).await.is_some());
+        assert!(server.play(current, None).await.is_some());
+
+        // Even if a legacy client did ack it couldn't read a delta
+        assert!(server.tick().await);
+        server.queue_message(CrossyMessage::ServerTickAck(0), legacy).await;
+        server.queue_message(CrossyMessage::ServerTickAck(0), current).await;
+        assert!(server.tick().await);
+
+        let (mut legacy_tick, mut current_tick) = (None, None);
+        while let Ok(outbound) = listener.try_recv() {
+            if (outbound.to == Some(legacy)) {
+                legacy_tick = Some(outbound.message);
+            }
+            else if (outbound.to == Some(current)) {
+                current_tick = Some(outbound.message);
+            }
+        }
+        assert!(matches!(legacy_tick, Some(CrossyMessage::LindenServerTick(_))));
+        assert!(matches!(current_tick, Some(CrossyMessage::LindenServerTickDelta(_))));
+    }
+
+    #[tokio::test]
+    async fn snapshot_restore_reconnect() {
+        let config = GameConfig {
+            bypass_lobby: true,
+            minimum_players: 1,
+            ..Default::default()
+        };
+        let limits = InputLimits {
+            max_strikes: 1,
+            ..Default::default()
+        };
+        let server = test_server_with_config("test_snapshot_restore", config, limits);
+        let hello = ClientHello::new(wire::PROTOCOL_VERSION);
+
+        let first = server.play(server.join(&hello, false).await, None).await.unwrap();
+        let second = server.play(server.join(&hello, false).await, None).await.unwrap();
+
+        // Get someone kicked, frame 0 is always too old
+        let kicked_socket = server.join(&hello, false).await;
+        let kicked = server.play(kicked_socket, None).await.unwrap();
+        server.queue_message(CrossyMessage::ClientTick(vec![crossy_multi_core::interop::ClientTick {
+            time_us: 0,
+            frame_id: 0,
+            input: game::Input::Up,
+        }]), kicked_socket).await;
+        server.receive_updates().await;
+
+        {
+            let mut inner = server.inner.lock().await;
+            for _ in 0..30 {
+                inner.timeline.tick(None, TICK_INTERVAL_US);
+            }
+        }
+
+        let snapshot = server.snapshot().await.unwrap();
+        let restored = Server::from_snapshot(ServerSnapshot::from_bytes(&snapshot.to_bytes()).unwrap(), limits);
+
+        {
+            let before = server.inner.lock().await;
+            let after = restored.inner.lock().await;
+            let (before, after) = (before.timeline.top_state(), after.timeline.top_state());
+            assert_eq!(after.frame_id, before.frame_id);
+            assert_eq!(after.time_us, before.time_us);
+            assert_eq!(after.player_states, before.player_states);
+            assert_eq!(after.rules_state, before.rules_state);
+            assert_eq!(after.rules_state.config, config);
+        }
+        assert_eq!(restored.get_server_description().await.seed, server.get_server_description().await.seed);
+
+        // Everyone comes back in as themselves
+        for play in [&first, &second] {
+            let rejoin = restored.play(restored.join(&hello, false).await, Some(&play.reconnect_token)).await.unwrap();
+            assert_eq!(rejoin.init.player_id, play.init.player_id);
+            assert_eq!(rejoin.reconnect_token, play.reconnect_token);
+        }
+
+        // Still banned, and nobody's id is handed out again
+        assert!(restored.play(restored.join(&hello, false).await, Some(&kicked.reconnect_token)).await.is_none());
+        let fresh = restored.play(restored.join(&hello, false).await, None).await.unwrap();
+        assert!(![first.init.player_id, second.init.player_id, kicked.init.player_id].contains(&fresh.init.player_id));
+    }
+}
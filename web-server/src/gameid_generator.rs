@@ -26,6 +26,11 @@ impl GameIdGenerator
         }
     }
 
+    // Ids from before a restart, the sequence starts over so we'd hand them out again.
+    pub fn mark_used(&mut self, id : GameId) {
+        self.used.insert(id);
+    }
+
     pub fn next(&mut self) -> GameId {
         loop {
             self.i += 1;
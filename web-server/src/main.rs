@@ -6,6 +6,10 @@ extern crate lazy_static;
 
 use crossy_multi_core::*;
 use crossy_multi_core::crossy_ruleset::GameConfig;
+use crossy_multi_core::game_rules::{GameMode, GAME_MODE_NAMES};
+use crossy_multi_core::input_validation::InputLimits;
+use crossy_multi_core::map::level::{LevelPreset, LEVEL_PRESET_NAMES};
+use crossy_multi_core::map::profile::{MapGenPreset, MAP_GEN_PRESET_NAMES};
 use std::sync::Arc;
 
 use warp::Filter;
@@ -20,6 +24,7 @@ use futures::{SinkExt, StreamExt};
 
 mod crossy_server;
 mod gameid_generator;
+mod metrics;
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Hash)]
 pub struct GameId(String);
@@ -34,36 +39,84 @@ struct GameDbInner {
 struct GameDb {
     games : Arc<Mutex<Vec<GameDbInner>>>,
     gameid_generator : Arc<Mutex<gameid_generator::GameIdGenerator>>,
+    input_limits : InputLimits,
 }
 
 impl GameDb {
-    fn new() -> Self {
+    fn new(input_limits : InputLimits) -> Self {
         GameDb {
             games: Arc::new(Mutex::new(Vec::new())),
             gameid_generator: Arc::new(Mutex::new(gameid_generator::GameIdGenerator::new())),
+            input_limits,
         }
     }
 
-    async fn new_game(&self, config : GameConfig) -> GameId {
-        let mut games = self.games.lock().await;
-
+    async fn new_game(&self, config : GameConfig, lobby_options : crossy_server::LobbyOptions) -> GameId {
         let id = {
             let mut idgen_lock = self.gameid_generator.lock().await;
             idgen_lock.next()
         };
 
-        let game = Arc::new(crossy_server::Server::new(config, &id));
+        let game = crossy_server::Server::new(config, lobby_options, self.input_limits, &id);
+        self.start_game(id.clone(), game).await;
+        id
+    }
+
+    async fn start_game(&self, id : GameId, game : crossy_server::Server) {
+        let mut games = self.games.lock().await;
+        let game = Arc::new(game);
 
         games.push(GameDbInner {
-            id: id.clone(),
+            id,
             game : game.clone(),
         });
 
         tokio::task::spawn(async move {
             game.run().await;
         });
+    }
 
-        id
+    async fn snapshot_all(&self) {
+        if let Err(e) = std::fs::create_dir_all(SNAPSHOT_DIR) {
+            println!("Failed to create {}: {}", SNAPSHOT_DIR, e);
+            return;
+        }
+
+        for game in self.all_games().await {
+            if let Some(snapshot) = game.game.snapshot().await {
+                let path = format!("{}/{}.snapshot", SNAPSHOT_DIR, &game.id.0);
+                match std::fs::write(&path, snapshot.to_bytes()) {
+                    Ok(_) => println!("[{:?}] Wrote snapshot to {}", game.id, path),
+                    Err(e) => println!("[{:?}] Failed to write snapshot {}: {}", game.id, path, e),
+                }
+            }
+        }
+    }
+
+    // Snapshots are removed once loaded, a crash later on shouldn't bring back stale games.
+    async fn restore_all(&self) {
+        let entries = match std::fs::read_dir(SNAPSHOT_DIR) {
+            Ok(x) => x,
+            Err(_) => return,
+        };
+
+        for entry in entries.filter_map(|x| x.ok()) {
+            let path = entry.path();
+            let snapshot = std::fs::read(&path)
+                .map_err(|e| e.to_string())
+                .and_then(|bytes| crossy_server::ServerSnapshot::from_bytes(&bytes));
+            let _ = std::fs::remove_file(&path);
+
+            match snapshot {
+                Ok(snapshot) => {
+                    let id = snapshot.game_id.clone();
+                    self.gameid_generator.lock().await.mark_used(id.clone());
+                    let game = crossy_server::Server::from_snapshot(snapshot, self.input_limits);
+                    self.start_game(id, game).await;
+                }
+                Err(e) => println!("Failed to restore {:?}: {}", path, e),
+            }
+        }
     }
 
     async fn cleanup(&self)
@@ -86,6 +139,52 @@ impl GameDb {
         *games_inner = games_swap;
     }
 
+    // Copied out so we don't hold up every other request while waiting on each game's lock.
+    async fn all_games(&self) -> Vec<GameDbInner> {
+        self.games.lock().await.clone()
+    }
+
+    async fn render_metrics(&self) -> String {
+        let mut stats = Vec::new();
+        for game in self.all_games().await {
+            if let Some(x) = game.game.stats().await {
+                stats.push(x);
+            }
+        }
+
+        metrics::METRICS.render(&stats)
+    }
+
+    async fn list_public(&self) -> Vec<crossy_server::GameListing> {
+        let mut listings = Vec::new();
+        for game in self.all_games().await {
+            if let Some(listing) = game.game.listing().await {
+                listings.push(listing);
+            }
+        }
+
+        listings
+    }
+
+    // Fullest public lobby with room, or a fresh public game.
+    // Two players racing for the last slot can both be sent to it, the loser fails at /play and tries again.
+    async fn quickmatch(&self) -> GameId {
+        let best = self.list_public().await.into_iter()
+            .filter(|x| x.phase == "Lobby" && x.player_count < x.max_players)
+            .max_by_key(|x| x.player_count);
+
+        match best {
+            Some(listing) => listing.game_id,
+            None => {
+                let lobby_options = crossy_server::LobbyOptions {
+                    public: true,
+                    ..Default::default()
+                };
+                self.new_game(GameConfig::default(), lobby_options).await
+            }
+        }
+    }
+
     async fn get(&self, game_id : GameId) -> Result<GameDbInner, Rejection> {
         let games = self.games.lock().await;
         let m_game = games.iter().filter(|x| x.id == game_id).next();
@@ -100,11 +199,21 @@ impl GameDb {
     }
 }
 
+const SNAPSHOT_DIR : &'static str = "snapshots";
+// Keeps round_time_limit_us in a u32
+const MAX_ROUND_TIME_LIMIT_S : u32 = 60 * 60;
+
+// Websocket close code for kicked players, matches index.js.
+const KICKED_CLOSE_CODE : u16 = 4001;
+
 const SERVE_DIR_DEV : &'static str = "C:\\Users\\Dan\\crossy_multi\\web-client\\dist";
 
 #[tokio::main(flavor = "multi_thread", worker_threads = 10)]
 async fn main() {
-    let games = GameDb::new();
+    let input_limits = input_limits_from_env();
+    println!("Input limits {:?}", input_limits);
+    let games = GameDb::new(input_limits);
+    games.restore_all().await;
 
     let serve_dir = if let Some(arg) = std::env::args().nth(1) {
         String::from(arg)
@@ -126,9 +235,24 @@ async fn main() {
         }
     });
 
+    // Deploys send SIGTERM, save everything so the games carry on after the restart.
+    #[cfg(unix)]
+    {
+        let games1 = games.clone();
+        tokio::task::spawn(async move {
+            let mut sigterm = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()).unwrap();
+            sigterm.recv().await;
+            println!("SIGTERM, snapshotting games");
+            games1.snapshot_all().await;
+            std::process::exit(0);
+        });
+    }
+
     let site = warp::fs::dir(serve_dir).with(warp::compression::gzip()).boxed();
 
     // GET /new
+    // GET /new?public=true&max_players=4&game_mode=last_frog_standing&round_time_limit=90
+    // GET /new?level=tutorial
     let get_new = warp::path!("new")
         .and(warp::get())
         .and(warp::query::<NewGameOptions>())
@@ -137,6 +261,7 @@ async fn main() {
         .and_then(new_game_handler).boxed();
 
     // GET /join?game_id=1&name=dan
+    // GET /join?game_id=1&name=dan&spectate=true
     let get_join = warp::path!("join")
         .and(warp::get())
         .and(warp::query::<JoinOptions>())
@@ -144,6 +269,7 @@ async fn main() {
         .and_then(join_handler).boxed();
 
     // GET /play?game_id=1&socket_id=1
+    // GET /play?game_id=1&socket_id=1&reconnect_token=abc
     let get_play = warp::path!("play")
         .and(warp::get())
         .and(warp::query::<PlayOptions>())
@@ -157,7 +283,35 @@ async fn main() {
         .and(with_db(games.clone()))
         .and_then(ws_handler).boxed();
    
+    // GET /games
+    let get_games = warp::path!("games")
+        .and(warp::get())
+        .and(with_db(games.clone()))
+        .and_then(list_games_handler).boxed();
+
+    // GET /quickmatch
+    let get_quickmatch = warp::path!("quickmatch")
+        .and(warp::get())
+        .and(with_db(games.clone()))
+        .and_then(quickmatch_handler).boxed();
+
+    // GET /replay?game_id=1
+    let get_replay = warp::path!("replay")
+        .and(warp::get())
+        .and(warp::query::<ReplayOptions>())
+        .and_then(replay_handler).boxed();
+
+    // GET /metrics
+    let get_metrics = warp::path!("metrics")
+        .and(warp::get())
+        .and(with_db(games.clone()))
+        .and_then(metrics_handler).boxed();
+
     let routes = get_new
+        .or(get_metrics)
+        .or(get_games)
+        .or(get_quickmatch)
+        .or(get_replay)
         .or(get_join)
         .or(get_play)
         .or(site)
@@ -179,6 +333,13 @@ fn with_db(db: GameDb) -> impl Filter<Extract = (GameDb,), Error = std::convert:
 #[derive(Debug, Clone, Deserialize)]
 struct NewGameOptions {
     debug_bypass_lobby : Option<bool>,
+    map_gen : Option<String>,
+    level : Option<String>,
+    game_mode : Option<String>,
+    // Seconds
+    round_time_limit : Option<u32>,
+    public : Option<bool>,
+    max_players : Option<u8>,
 }
 
 #[derive(Debug, Clone, Serialize)]
@@ -187,6 +348,11 @@ pub struct NewGameResponse
     pub game_id : GameId,
 }
 
+fn bad_request(error : String) -> Response {
+    println!("Rejecting /new: {}", error);
+    reply::with_status(error, warp::http::StatusCode::BAD_REQUEST).into_response()
+}
+
 async fn new_game_handler(options : NewGameOptions, db: GameDb) -> Result<Response, std::convert::Infallible>  {
     let mut config = GameConfig::default();
 
@@ -195,17 +361,90 @@ async fn new_game_handler(options : NewGameOptions, db: GameDb) -> Result<Respon
         config.minimum_players = 1;
     }
 
-    let game_id = db.new_game(config).await;
+    if let Some(name) = options.map_gen.as_deref() {
+        match MapGenPreset::from_name(name) {
+            Some(map_gen) => config.map_gen = map_gen,
+            None => return Ok(bad_request(format!("Unknown map_gen {:?}, expected one of {:?}", name, MAP_GEN_PRESET_NAMES))),
+        }
+    }
+
+    if let Some(name) = options.level.as_deref() {
+        match LevelPreset::from_name(name) {
+            Some(level) => config.level = Some(level),
+            None => return Ok(bad_request(format!("Unknown level {:?}, expected one of {:?}", name, LEVEL_PRESET_NAMES))),
+        }
+    }
+
+    if let Some(name) = options.game_mode.as_deref() {
+        match GameMode::from_name(name) {
+            Some(game_mode) => config.game_mode = game_mode,
+            None => return Ok(bad_request(format!("Unknown game_mode {:?}, expected one of {:?}", name, GAME_MODE_NAMES))),
+        }
+    }
+
+    if let Some(round_time_limit) = options.round_time_limit {
+        config.round_time_limit_us = Some(round_time_limit.min(MAX_ROUND_TIME_LIMIT_S) * 1_000_000);
+    }
+
+    let mut lobby_options = crossy_server::LobbyOptions::default();
+    if let Some(public) = options.public {
+        lobby_options.public = public;
+    }
+
+    if let Some(max_players) = options.max_players {
+        lobby_options.max_players = max_players.clamp(1, crossy_server::MAX_PLAYERS);
+    }
+
+    let game_id = db.new_game(config, lobby_options).await;
     let new_game_response = NewGameResponse { game_id };
     let response = warp::reply::json(&new_game_response).into_response();
     println!("/new {:?}", &response);
     Ok(response)
 }
 
+async fn metrics_handler(db: GameDb) -> Result<Response, std::convert::Infallible>  {
+    let body = db.render_metrics().await;
+    Ok(reply::with_header(body, "content-type", "text/plain; version=0.0.4").into_response())
+}
+
+async fn list_games_handler(db: GameDb) -> Result<Response, std::convert::Infallible>  {
+    let listings = db.list_public().await;
+    Ok(warp::reply::json(&listings).into_response())
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct ReplayOptions {
+    game_id : GameId,
+}
+
+// Replays are written when each match ends, see ServerInner::write_replay.
+async fn replay_handler(options : ReplayOptions) -> Result<Response, Rejection>  {
+    // Game ids are words from GameIdGenerator, make sure one can't reach outside the replay dir.
+    let id = &options.game_id.0;
+    if (id.is_empty() || id.contains(['/', '\\']) || id.contains("..")) {
+        return Err(reject::not_found());
+    }
+
+    match std::fs::read(crossy_server::replay_path(&options.game_id)) {
+        Ok(bytes) => Ok(reply::with_header(bytes, "content-type", "application/octet-stream").into_response()),
+        Err(_) => Err(reject::not_found()),
+    }
+}
+
+async fn quickmatch_handler(db: GameDb) -> Result<Response, std::convert::Infallible>  {
+    let game_id = db.quickmatch().await;
+    println!("/quickmatch {:?}", &game_id);
+    Ok(warp::reply::json(&NewGameResponse { game_id }).into_response())
+}
+
 #[derive(Debug, Clone, Deserialize)]
 struct JoinOptions {
     pub game_id : GameId, 
     pub name : String, 
+    // Clients from before the binary protocol don't send a version.
+    pub version : Option<u8>,
+    // Watch without a player, never /play.
+    pub spectate : Option<bool>,
 }
 
 #[derive(Debug, Clone, Serialize)]
@@ -218,10 +457,17 @@ struct JoinResponse {
 
 async fn join_handler(options : JoinOptions, db: GameDb) -> Result<Response, Rejection>  {
     println!("Join with options {options:?}");
+    let hello = interop::ClientHello::new(options.version.unwrap_or(interop::wire::LEGACY_FLEXBUFFERS_VERSION));
+    if (!hello.check()) {
+        let error = interop::wire::WireError::UnsupportedVersion(hello.version()).to_string();
+        println!("Rejecting join: {}", error);
+        return Ok(reply::with_status(error, warp::http::StatusCode::UPGRADE_REQUIRED).into_response());
+    }
+
     let dbinner = db.get(options.game_id).await?;
     let server_description = dbinner.game.get_server_description().await;
     //let last_frame_time_us = dbinner.game.get_last_frame_time_us().await;
-    let socket_id = dbinner.game.join().await;
+    let socket_id = dbinner.game.join(&hello, options.spectate.unwrap_or(false)).await;
     let server_time_us = dbinner.game.time_since().await;
     let server_frame_id = dbinner.game.frame_id().await;
     let response = JoinResponse {
@@ -238,14 +484,15 @@ async fn join_handler(options : JoinOptions, db: GameDb) -> Result<Response, Rej
 struct PlayOptions {
     pub game_id : GameId, 
     pub socket_id : crossy_server::SocketId,
+    // From an earlier /play, to get the same player back after a disconnect.
+    pub reconnect_token : Option<String>,
 }
 
 async fn play_handler(options: PlayOptions, db: GameDb) -> Result<Response, Rejection>  {
     println!("Play with options {options:?}");
     let dbinner = db.get(options.game_id).await?;
-    let hello = interop::ClientHello::default();
-    let init_server_response = dbinner.game.play(&hello, options.socket_id).await;
-    Ok(reply::json(&init_server_response).into_response())
+    let play_response = dbinner.game.play(options.socket_id, options.reconnect_token.as_deref()).await;
+    Ok(reply::json(&play_response).into_response())
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -282,17 +529,31 @@ async fn websocket_main(ws: WebSocket, db : GameDbInner, socket_id : crossy_serv
 
     let mut tick_listener = db.game.get_listener();
     let game_start = db.game.get_start_time().await;
+    let protocol_version = db.game.protocol_version(socket_id).await;
     let (mut ws_tx, mut ws_rx) = ws.split();
 
 
     tokio::task::spawn(async move {
         loop {
             match tick_listener.recv().await {
-                Ok(crossy_multi_core::interop::CrossyMessage::GoodBye()) => {
-                    println!("Game ended cleaning up WS listener");
+                Ok(outbound) if !outbound.is_for(socket_id) => {
+                    continue;
+                },
+                Ok(crossy_server::Outbound { message : crossy_multi_core::interop::CrossyMessage::GoodBye(), to }) => {
+                    // Addressed goodbyes are kicks, the game carries on for everyone else.
+                    // The close code tells the client not to reload back in.
+                    if (to.is_some()) {
+                        println!("[{:?}] Kicked, closing WS", socket_id);
+                        let _ = ws_tx.send(Message::close_with(KICKED_CLOSE_CODE, "kicked")).await;
+                    }
+                    else {
+                        println!("Game ended cleaning up WS listener");
+                        let _ = ws_tx.close().await;
+                    }
+
                     break;
                 },
-                Ok(mut to_send) => {
+                Ok(crossy_server::Outbound { message : mut to_send, .. }) => {
 
                     // Special case handling for time request responses
                     if let interop::CrossyMessage::TimeRequestIntermediate(time_request_state) = &to_send {
@@ -312,7 +573,8 @@ async fn websocket_main(ws: WebSocket, db : GameDbInner, socket_id : crossy_serv
                         });
                     }
 
-                    let serialized = flexbuffers::to_vec(&to_send).unwrap();
+                    let serialized = interop::wire::encode_for_version(&to_send, protocol_version);
+                    metrics::METRICS.observe_sent(&to_send, serialized.len());
                     match ws_tx.send(Message::binary(serialized)).await
                     {
                         Ok(_) => {},
@@ -350,9 +612,103 @@ async fn websocket_main(ws: WebSocket, db : GameDbInner, socket_id : crossy_serv
     db.game.queue_message(interop::CrossyMessage::ClientDrop{}, socket_id).await;
 }
 
+// Overrides for the defaults, eg CROSSY_MAX_INPUT_AGE_FRAMES=20
+// CROSSY_MAX_STRIKES=0 turns off kicking.
+fn input_limits_from_env() -> InputLimits {
+    fn var(name : &str, default : u32) -> u32 {
+        std::env::var(name).ok().and_then(|x| x.parse().ok()).unwrap_or(default)
+    }
+
+    let defaults = InputLimits::default();
+    InputLimits {
+        max_input_age_frames: var("CROSSY_MAX_INPUT_AGE_FRAMES", defaults.max_input_age_frames),
+        max_input_lead_frames: var("CROSSY_MAX_INPUT_LEAD_FRAMES", defaults.max_input_lead_frames),
+        max_inputs_per_second: var("CROSSY_MAX_INPUTS_PER_SECOND", defaults.max_inputs_per_second),
+        max_strikes: var("CROSSY_MAX_STRIKES", defaults.max_strikes),
+    }
+}
+
 fn parse_client_message(ws_message : &warp::ws::Message) -> Option<interop::CrossyMessage>
 {
-    let bytes = ws_message.as_bytes();
-    let r = flexbuffers::Reader::get_root(bytes).map_err(|e| println!("{e}")).ok()?;
-    interop::CrossyMessage::deserialize(r).map_err(|e| println!("{e}")).ok()
-}
\ No newline at end of file
+    interop::wire::decode(ws_message.as_bytes()).map_err(|e| println!("{e}")).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_db() -> GameDb {
+        std::fs::create_dir_all("logs").unwrap();
+        GameDb::new(InputLimits::default())
+    }
+
+    fn public(max_players : u8) -> crossy_server::LobbyOptions {
+        crossy_server::LobbyOptions {
+            public : true,
+            max_players,
+        }
+    }
+
+    async fn add_player(db : &GameDb, game_id : &GameId) {
+        let game = db.get(game_id.clone()).await.unwrap().game;
+        let hello = interop::ClientHello::new(interop::wire::PROTOCOL_VERSION);
+        let socket_id = game.join(&hello, false).await;
+        assert!(game.play(socket_id, None).await.is_some());
+    }
+
+    #[tokio::test]
+    async fn list_public_games() {
+        let db = test_db();
+        db.new_game(GameConfig::default(), crossy_server::LobbyOptions::default()).await;
+        let public_id = db.new_game(GameConfig::default(), public(4)).await;
+        add_player(&db, &public_id).await;
+
+        let listings = db.list_public().await;
+        assert_eq!(listings.len(), 1);
+        assert_eq!(listings[0].game_id, public_id);
+        assert_eq!(listings[0].player_count, 1);
+        assert_eq!(listings[0].max_players, 4);
+        assert_eq!(listings[0].phase, "Lobby");
+    }
+
+    #[tokio::test]
+    async fn quickmatch_fills_fullest_lobby() {
+        let db = test_db();
+
+        // Nothing to join, makes a public game
+        let created = db.quickmatch().await;
+        assert_eq!(db.list_public().await.len(), 1);
+
+        let fuller = db.new_game(GameConfig::default(), public(2)).await;
+        add_player(&db, &fuller).await;
+        assert_eq!(db.quickmatch().await, fuller);
+
+        // Full games are skipped, private ones never show up
+        add_player(&db, &fuller).await;
+        db.new_game(GameConfig::default(), crossy_server::LobbyOptions::default()).await;
+        assert_eq!(db.quickmatch().await, created);
+        assert_eq!(db.list_public().await.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn new_game_with_level() {
+        let db = test_db();
+        let options = |level : &str| NewGameOptions {
+            debug_bypass_lobby : None,
+            map_gen : None,
+            level : Some(level.to_owned()),
+            game_mode : None,
+            round_time_limit : None,
+            public : Some(true),
+            max_players : None,
+        };
+
+        let response = new_game_handler(options("gauntlet"), db.clone()).await.unwrap();
+        assert_eq!(response.status(), warp::http::StatusCode::OK);
+        assert_eq!(db.list_public().await.len(), 1);
+
+        let response = new_game_handler(options("maze"), db.clone()).await.unwrap();
+        assert_eq!(response.status(), warp::http::StatusCode::BAD_REQUEST);
+        assert_eq!(db.list_public().await.len(), 1);
+    }
+}
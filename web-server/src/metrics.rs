@@ -0,0 +1,212 @@
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+use crossy_multi_core::interop::CrossyMessage;
+
+use crate::GameId;
+
+// Served at /metrics in the Prometheus text format.
+//
+// Histograms and byte counts are shared by every game, per game counters live on the Server and
+// are collected at scrape time, so a game's series go away when it ends.
+
+lazy_static! {
+    pub static ref METRICS : Metrics = Metrics::new();
+}
+
+pub struct Histogram {
+    bounds : &'static [f64],
+    counts : Vec<u64>,
+    sum : f64,
+    count : u64,
+}
+
+impl Histogram {
+    pub fn new(bounds : &'static [f64]) -> Self {
+        Self {
+            bounds,
+            counts : vec![0; bounds.len()],
+            sum : 0.0,
+            count : 0,
+        }
+    }
+
+    pub fn observe(&mut self, value : f64) {
+        if let Some(i) = self.bounds.iter().position(|x| value <= *x) {
+            self.counts[i] += 1;
+        }
+
+        self.sum += value;
+        self.count += 1;
+    }
+
+    fn write(&self, out : &mut String, name : &str, help : &str) {
+        writeln!(out, "# HELP {} {}", name, help).unwrap();
+        writeln!(out, "# TYPE {} histogram", name).unwrap();
+
+        let mut cumulative = 0;
+        for (bound, count) in self.bounds.iter().zip(&self.counts) {
+            cumulative += count;
+            writeln!(out, "{}_bucket{{le=\"{}\"}} {}", name, bound, cumulative).unwrap();
+        }
+
+        writeln!(out, "{}_bucket{{le=\"+Inf\"}} {}", name, self.count).unwrap();
+        writeln!(out, "{}_sum {}", name, self.sum).unwrap();
+        writeln!(out, "{}_count {}", name, self.count).unwrap();
+    }
+}
+
+pub struct Metrics {
+    tick_duration_seconds : Mutex<Histogram>,
+    tick_bytes : Mutex<Histogram>,
+    time_sync_rtt_seconds : Mutex<Histogram>,
+    bytes_sent : AtomicU64,
+}
+
+impl Metrics {
+    fn new() -> Self {
+        Self {
+            tick_duration_seconds : Mutex::new(Histogram::new(&[0.0005, 0.001, 0.002, 0.004, 0.008, 0.016, 0.032, 0.064])),
+            tick_bytes : Mutex::new(Histogram::new(&[32.0, 64.0, 128.0, 256.0, 512.0, 1024.0, 2048.0, 4096.0, 8192.0])),
+            time_sync_rtt_seconds : Mutex::new(Histogram::new(&[0.01, 0.025, 0.05, 0.075, 0.1, 0.15, 0.2, 0.3, 0.5, 1.0])),
+            bytes_sent : AtomicU64::new(0),
+        }
+    }
+
+    // Time spent in one Server::run loop, not counting the sleep.
+    pub fn observe_tick_duration(&self, duration : std::time::Duration) {
+        self.tick_duration_seconds.lock().unwrap().observe(duration.as_secs_f64());
+    }
+
+    // Encoded size of a message going out on a websocket.
+    pub fn observe_sent(&self, message : &CrossyMessage, bytes : usize) {
+        self.bytes_sent.fetch_add(bytes as u64, Ordering::Relaxed);
+
+        if let CrossyMessage::LindenServerTick(_) | CrossyMessage::LindenServerTickDelta(_) = message {
+            self.tick_bytes.lock().unwrap().observe(bytes as f64);
+        }
+    }
+
+    pub fn observe_time_sync_rtt_us(&self, rtt_us : i64) {
+        if (rtt_us >= 0) {
+            self.time_sync_rtt_seconds.lock().unwrap().observe(rtt_us as f64 / 1_000_000.0);
+        }
+    }
+
+    pub fn render(&self, games : &[GameStats]) -> String {
+        let mut out = String::new();
+
+        write_gauge(&mut out, "crossy_games_active", "Games running on this server.", games.len() as u64);
+        write_gauge(&mut out, "crossy_players_connected", "Players with a live connection, across all games.",
+            games.iter().map(|x| x.players_connected as u64).sum());
+
+        write_per_game(&mut out, "crossy_game_players_connected", "gauge", "Players with a live connection.",
+            games, |x| x.players_connected as u64);
+        write_per_game(&mut out, "crossy_late_inputs_total", "counter", "Inputs for a frame the server had already simulated.",
+            games, |x| x.late_inputs);
+        write_per_game(&mut out, "crossy_future_input_requeues_total", "counter", "Times an input from ahead of the server was held back a tick.",
+            games, |x| x.future_input_requeues);
+        write_per_game(&mut out, "crossy_rejected_inputs_total", "counter", "Inputs that failed validation.",
+            games, |x| x.rejected_inputs);
+
+        writeln!(out, "# HELP crossy_bytes_sent_total Bytes written to websockets.").unwrap();
+        writeln!(out, "# TYPE crossy_bytes_sent_total counter").unwrap();
+        writeln!(out, "crossy_bytes_sent_total {}", self.bytes_sent.load(Ordering::Relaxed)).unwrap();
+
+        self.tick_duration_seconds.lock().unwrap().write(&mut out, "crossy_tick_duration_seconds", "Time to run one server tick.");
+        self.tick_bytes.lock().unwrap().write(&mut out, "crossy_tick_bytes", "Size of each tick sent to a client.");
+        self.time_sync_rtt_seconds.lock().unwrap().write(&mut out, "crossy_time_sync_rtt_seconds", "Round trip times clients measured syncing their clocks.");
+
+        out
+    }
+}
+
+// Collected from each game at scrape time.
+pub struct GameStats {
+    pub game_id : GameId,
+    pub players_connected : u32,
+    pub late_inputs : u64,
+    pub future_input_requeues : u64,
+    pub rejected_inputs : u64,
+}
+
+fn write_gauge(out : &mut String, name : &str, help : &str, value : u64) {
+    writeln!(out, "# HELP {} {}", name, help).unwrap();
+    writeln!(out, "# TYPE {} gauge", name).unwrap();
+    writeln!(out, "{} {}", name, value).unwrap();
+}
+
+fn write_per_game(out : &mut String, name : &str, metric_type : &str, help : &str, games : &[GameStats], f : impl Fn(&GameStats) -> u64) {
+    writeln!(out, "# HELP {} {}", name, help).unwrap();
+    writeln!(out, "# TYPE {} {}", name, metric_type).unwrap();
+    for game in games {
+        writeln!(out, "{}{{game=\"{}\"}} {}", name, game.game_id.0, f(game)).unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn game_stats(name : &str, players_connected : u32, late_inputs : u64) -> GameStats {
+        GameStats {
+            game_id : GameId(name.to_owned()),
+            players_connected,
+            late_inputs,
+            future_input_requeues : 0,
+            rejected_inputs : 1,
+        }
+    }
+
+    #[test]
+    fn histogram_buckets_are_cumulative() {
+        let mut histogram = Histogram::new(&[1.0, 2.0, 4.0]);
+        for value in [0.5, 1.0, 1.5, 3.0, 3.5, 10.0] {
+            histogram.observe(value);
+        }
+
+        let mut out = String::new();
+        histogram.write(&mut out, "test", "Test.");
+        let lines : Vec<&str> = out.lines().collect();
+        assert_eq!(lines, vec![
+            "# HELP test Test.",
+            "# TYPE test histogram",
+            "test_bucket{le=\"1\"} 2",
+            "test_bucket{le=\"2\"} 3",
+            "test_bucket{le=\"4\"} 5",
+            "test_bucket{le=\"+Inf\"} 6",
+            "test_sum 19.5",
+            "test_count 6",
+        ]);
+    }
+
+    #[test]
+    fn render_labels_each_game() {
+        let metrics = Metrics::new();
+        metrics.observe_tick_duration(std::time::Duration::from_micros(1500));
+        metrics.observe_tick_duration(std::time::Duration::from_millis(100));
+
+        let out = metrics.render(&[game_stats("frog_log_pond", 2, 5), game_stats("toad_road_car", 1, 0)]);
+        let lines : Vec<&str> = out.lines().collect();
+
+        assert!(lines.contains(&"crossy_games_active 2"));
+        assert!(lines.contains(&"crossy_players_connected 3"));
+        assert!(lines.contains(&"crossy_game_players_connected{game=\"frog_log_pond\"} 2"));
+        assert!(lines.contains(&"crossy_game_players_connected{game=\"toad_road_car\"} 1"));
+        assert!(lines.contains(&"crossy_late_inputs_total{game=\"frog_log_pond\"} 5"));
+        assert!(lines.contains(&"crossy_late_inputs_total{game=\"toad_road_car\"} 0"));
+        assert!(lines.contains(&"crossy_rejected_inputs_total{game=\"toad_road_car\"} 1"));
+
+        // 1.5ms lands in the 2ms bucket and everything above, 100ms only in +Inf.
+        assert!(lines.contains(&"crossy_tick_duration_seconds_bucket{le=\"0.001\"} 0"));
+        assert!(lines.contains(&"crossy_tick_duration_seconds_bucket{le=\"0.002\"} 1"));
+        assert!(lines.contains(&"crossy_tick_duration_seconds_bucket{le=\"0.064\"} 1"));
+        assert!(lines.contains(&"crossy_tick_duration_seconds_bucket{le=\"+Inf\"} 2"));
+        assert!(lines.contains(&"crossy_tick_duration_seconds_count 2"));
+
+        // Nothing observed still writes every bucket.
+        assert!(lines.contains(&"crossy_tick_bytes_bucket{le=\"32\"} 0"));
+        assert!(lines.contains(&"crossy_tick_bytes_count 0"));
+    }
+}
@@ -3,7 +3,7 @@ use std::pin;
 use crossy_multi_core::{crossy_ruleset::{AliveState, CrossyRulesetFST, RulesState, INTRO_COUNTDOWN_TIME_US}, math::V2, timeline::{self, Timeline}, PlayerId};
 use raylib_sys::Ray;
 
-use crate::{audio, client::{StateTransition, VisualEffects}, entities::EntityContainer, player_local::{PlayerLocal, PlayerSkin, Skin}, to_vector2};
+use crate::{audio, client::{StateTransition, VisualEffects}, entities::EntityContainer, player_local::{g_team_names, g_team_tints, PlayerLocal, PlayerSkin, Skin}, to_vector2};
 
 struct Face {
     sprite: &'static str,
@@ -110,6 +110,7 @@ pub struct BigTextController {
     face: Option<Face>,
     last_rule_state_fst: Option<CrossyRulesetFST>,
     pinwheel: Option<Pinwheel>,
+    winner_team: Option<u8>,
 }
 
 impl BigTextController {
@@ -184,6 +185,10 @@ impl BigTextController {
         }
 
         if transitions.into_winner {
+            if let CrossyRulesetFST::EndWinner(state) = rules {
+                self.winner_team = state.winner_team;
+            }
+
             self.text = Some(BigText {
                 sprite: "champion",
                 image_index: 0,
@@ -203,15 +208,27 @@ impl BigTextController {
         else {
             if let CrossyRulesetFST::EndWinner(state) = rules {
                 if let Some(pinwheel) = self.pinwheel.as_mut() {
-                    if let Some(winner) = players.inner.iter().find(|x| x.player_id == state.winner_id) {
-                        pinwheel.pos = winner.pos * 8.0 + V2::new(4.0, 4.0);
-                        pinwheel.color = winner.skin.color;
+                    // A winning team shares the pinwheel, centred between them.
+                    let winners: Vec<&PlayerLocal> = players.inner.iter().filter(|x| state.is_winner(x.player_id)).collect();
+                    if let Some(first) = winners.first() {
+                        let mut centre = V2::default();
+                        for winner in &winners {
+                            centre += winner.pos;
+                        }
+                        centre *= 1.0 / winners.len() as f32;
+
+                        pinwheel.pos = centre * 8.0 + V2::new(4.0, 4.0);
+                        pinwheel.color = match state.winner_team {
+                            Some(team) => g_team_tints[team as usize % g_team_tints.len()],
+                            None => first.skin.color,
+                        };
                         pinwheel.visible = true;
                     }
                 }
             }
             else {
                 self.pinwheel = None;
+                self.winner_team = None;
             }
         }
 
@@ -312,6 +329,17 @@ impl BigTextController {
             // @Perf double lookup
             let pos = V2::new(80.0 - w_sprite as f32 * 0.5, 60.0) + text.offset;
             crate::sprites::draw(text.sprite, text.image_index, pos.x, pos.y);
+
+            if let (Some(team), "champion") = (self.winner_team, text.sprite) {
+                let font_size = 10;
+                let team_index = team as usize % g_team_names.len();
+                unsafe {
+                    let c_str = crate::c_str_temp(g_team_names[team_index]);
+                    let w_text = raylib_sys::MeasureText(c_str, font_size);
+                    let h_sprite = crate::sprites::get_sprite(&text.sprite)[0].height;
+                    raylib_sys::DrawText(c_str, 80 - w_text / 2, (pos.y as i32) + h_sprite + 2, font_size, g_team_tints[team_index]);
+                }
+            }
         }
 
         if let Some(face) = self.face.as_ref() {
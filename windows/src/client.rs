@@ -1,4 +1,4 @@
-use crossy_multi_core::{crossy_ruleset::{CrossyRulesetFST, GameConfig, RulesState}, map::RowType, math::V2, ring_buffer::RingBuffer, timeline::{Timeline, TICK_INTERVAL_US}, CoordPos, Input, PlayerId, PlayerInputs, Pos};
+use crossy_multi_core::{ai::AIAgent, crossy_ruleset::{CrossyRulesetFST, GameConfig, RulesState}, map::{pickup::PickupKind, RowType}, math::V2, replay::ReplayPlayer, ring_buffer::RingBuffer, timeline::{Timeline, TICK_INTERVAL_US}, CoordPos, Input, PlayerId, PlayerInputs, Pos};
 use crate::{audio::{self, g_music_volume}, dan_lerp, entities::{self, create_dust, Entity, EntityContainer, EntityManager, OutfitSwitcher, PropController}, gamepad_pressed, hex_color, key_pressed, lerp_color_rgba, pause::{Pause, PauseResult}, player_local::{PlayerInputController, PlayerLocal, Skin}, rope::NodeType, sprites, title_screen::{self, ActorController, TitleScreen}, to_vector2, BLACK, WHITE};
 use froggy_rand::FroggyRand;
 
@@ -29,6 +29,12 @@ pub struct Client {
     pub big_text_controller: crate::bigtext::BigTextController,
     pub player_input_controller: PlayerInputController,
 
+    // When set we are watching a replay, the timeline is driven by it instead of local input.
+    pub replay: Option<ReplayPlayer>,
+
+    // Players driven by an AI agent rather than a controller.
+    pub bots: Vec<(PlayerId, Box<dyn AIAgent>)>,
+
     prev_rules: Option<CrossyRulesetFST>,
 
     actor_controller: ActorController,
@@ -48,6 +54,9 @@ pub const road_col_0: raylib_sys::Color = hex_color("646469".as_bytes());
 pub const road_col_1: raylib_sys::Color = hex_color("59595d".as_bytes());
 pub const icy_col_0: raylib_sys::Color = hex_color("cbdbfc".as_bytes());
 pub const icy_col_1: raylib_sys::Color = hex_color("9badb7".as_bytes());
+pub const pickup_speed_boost_col: raylib_sys::Color = hex_color("ffec27".as_bytes());
+pub const pickup_shield_col: raylib_sys::Color = hex_color("29adff".as_bytes());
+pub const pickup_super_push_col: raylib_sys::Color = hex_color("ff004d".as_bytes());
 
 impl Client {
     pub fn new(debug: bool, seed: &str) -> Self {
@@ -76,6 +85,8 @@ impl Client {
             screen_shader: crate::ScreenShader::new(),
             big_text_controller: Default::default(),
             player_input_controller: PlayerInputController::default(),
+            replay: None,
+            bots: Vec::new(),
             prev_rules: Default::default(),
             pause: None,
             title_screen: Some(TitleScreen::default()),
@@ -87,6 +98,46 @@ impl Client {
         }
     }
 
+    pub fn start_replay(&mut self, mut replay: ReplayPlayer) {
+        self.timeline = replay.create_timeline();
+        self.replay = Some(replay);
+        self.bots.clear();
+
+        self.player_input_controller = PlayerInputController::default();
+        self.entities.clear_round_entities();
+        self.entities.players.inner.clear();
+
+        self.pause = None;
+        self.title_screen = None;
+
+        self.visual_effects.noise();
+        self.visual_effects.whiteout();
+    }
+
+    // Replays add players through the timeline directly, so we need to make
+    // local entities for them to draw.
+    fn create_replay_players(&mut self) -> Vec<PlayerId> {
+        let mut new_players = Vec::new();
+
+        let top = self.timeline.top_state();
+        for player_id in top.player_states.valid_ids() {
+            if self.entities.players.inner.iter().any(|x| x.player_id == player_id) {
+                continue;
+            }
+
+            let rand = FroggyRand::new(player_id.0 as u64);
+            let skin = Skin::rand_not_overlapping(rand, &self.entities.players.inner, &self.entities.outfit_switchers.inner);
+            let player_state = top.player_states.get(player_id).unwrap().to_public(top.get_round_id(), top.time_us, &self.timeline.map, &top.rules_state.fst);
+            let player_local = self.entities.players.create(Pos::Absolute(V2::default()));
+            player_local.set_from(&player_state);
+            player_local.skin = skin;
+
+            new_players.push(player_id);
+        }
+
+        new_players
+    }
+
     pub fn goto_loby_seed(&mut self, seed: &str, bypass_lobby: Option<bool>) {
         let mut config = self.timeline.top_state().rules_state.config.clone();
         if let Some(bl) =  bypass_lobby {
@@ -98,6 +149,8 @@ impl Client {
         }
 
         self.timeline = Timeline::from_seed(config, &self.seed);
+        self.replay = None;
+        self.bots.clear();
 
         self.player_input_controller = PlayerInputController::default();
         self.entities.clear_round_entities();
@@ -169,8 +222,22 @@ impl Client {
             }
         }
 
-        let (inputs, new_players) = self.player_input_controller.tick(&mut self.timeline, &mut self.entities.players, &self.entities.outfit_switchers);
-        self.timeline.tick(Some(inputs), TICK_INTERVAL_US);
+        let new_players = if let Some(replay) = self.replay.as_mut() {
+            // Hold on the last frame once the replay is done.
+            replay.tick(&mut self.timeline);
+            self.create_replay_players()
+        }
+        else {
+            let (mut inputs, new_players) = self.player_input_controller.tick(&mut self.timeline, &mut self.entities.players, &self.entities.outfit_switchers);
+            for (player_id, bot) in self.bots.iter_mut() {
+                let input = bot.think(self.timeline.top_state(), &self.timeline.map);
+                if let Some(player) = self.entities.players.inner.iter_mut().find(|x| x.player_id == *player_id) {
+                    player.update_inputs(&self.timeline, &mut inputs, input);
+                }
+            }
+            self.timeline.tick(Some(inputs), TICK_INTERVAL_US);
+            new_players
+        };
 
         let transitions = {
             let top = self.timeline.top_state();
@@ -241,6 +308,7 @@ impl Client {
 
         for (remove_player_id, remove_entity_id) in to_remove {
             self.player_input_controller.remove(remove_player_id);
+            self.bots.retain(|(id, _)| *id != remove_player_id);
             self.entities.players.delete_entity_id(remove_entity_id);
         }
 
@@ -383,6 +451,9 @@ impl Client {
                     RowType::IcyRow{..} => {
                         (icy_col_0, icy_col_1)
                     },
+                    RowType::FinishLine => {
+                        (WHITE, BLACK)
+                    },
                     RowType::Lobby => {
                         let t = if y > 0 {
                             //println!("y = {} t = 0", y);
@@ -419,7 +490,14 @@ impl Client {
                         sprites::draw("tree_top", 1, i as f32 * 8.0, y as f32 * 8.0);
                         sprites::draw("tree_top", 1, (19 - i) as f32 * 8.0, y as f32 * 8.0);
                     }
-                    //let hydrated = bush_descr.hydrate();
+
+                    let hydrated = bush_descr.hydrate();
+                    for x in hydrated.trees {
+                        sprites::draw("tree_top", 1, x as f32 * 8.0, y as f32 * 8.0);
+                    }
+                    for x in hydrated.bushes {
+                        sprites::draw("foliage", 0, x as f32 * 8.0, y as f32 * 8.0);
+                    }
                 }
 
                 if let RowType::LobbyRiver = &row.row_type {
@@ -486,6 +564,19 @@ impl Client {
                         }
                     }
                 }
+
+                if let Some(pickup) = self.timeline.map.get_pickup(round_id, y) {
+                    if !top.pickup_taken(y) {
+                        let col = match pickup.kind {
+                            PickupKind::SpeedBoost => pickup_speed_boost_col,
+                            PickupKind::Shield => pickup_shield_col,
+                            PickupKind::SuperPush => pickup_super_push_col,
+                        };
+
+                        raylib_sys::DrawCircle(pickup.x * 8 + 4, y * 8 + 4, 3.0, col);
+                        raylib_sys::DrawCircleLines(pickup.x * 8 + 4, y * 8 + 4, 3.0, BLACK);
+                    }
+                }
             }
         }
 
@@ -1,6 +1,6 @@
 use std::{io::BufWriter, mem::MaybeUninit, str::FromStr};
 
-use crossy_multi_core::{crossy_ruleset::{CrossyRulesetFST, EndWinnerState, WINNER_TIME_US}, ring_buffer::RingBuffer, timeline::Timeline, DebugLogger, Input, PlayerId, PlayerInputs, Pos};
+use crossy_multi_core::{ai, crossy_ruleset::{CrossyRulesetFST, EndWinnerState, WINNER_TIME_US}, replay::{Replay, ReplayPlayer}, ring_buffer::RingBuffer, timeline::Timeline, DebugLogger, Input, PlayerId, PlayerInputs, Pos};
 
 use crate::{player_local::{PlayerInputController, Skin}, Client};
 
@@ -96,6 +96,14 @@ pub fn init_console() {
             name: "seed".to_owned(),
             lambda: Box::new(do_seed),
         });
+        command_set.commands.push(Command {
+            name: "add_bot".to_owned(),
+            lambda: Box::new(do_add_bot),
+        });
+        command_set.commands.push(Command {
+            name: "play_replay".to_owned(),
+            lambda: Box::new(do_play_replay),
+        });
         command_set.commands.push(Command::new(
             "dump_controllers",
             Box::new(do_dump_controllers),
@@ -503,6 +511,7 @@ fn do_new(args: &[&str], client: &mut Client) {
     client.seed = seed;
 
     client.player_input_controller = PlayerInputController::default();
+    client.bots.clear();
     client.entities.clear_round_entities();
     client.entities.players.inner.clear();
 }
@@ -579,6 +588,8 @@ fn do_win(args: &[&str], client: &mut Client) {
     let state = CrossyRulesetFST::EndWinner(EndWinnerState {
         winner_id: player_id,
         remaining_us: WINNER_TIME_US,
+        winner_team: None,
+        teams: Default::default(),
     });
 
     client.timeline.states.front_mut().unwrap().rules_state.fst = state;
@@ -759,6 +770,41 @@ fn do_add_player(args: &[&str], client: &mut Client) {
         None);
 }
 
+fn do_add_bot(args: &[&str], client: &mut Client) {
+    if (args.len() > 1) {
+        err!("Expected 0 or 1 arguments to add_bot, got {}", args.len());
+        info!("Usage: add_bot [{}]", ai::AGENT_NAMES.join("|"));
+        return;
+    }
+
+    let agent_name = args.first().copied().unwrap_or("go_up");
+    if (!ai::AGENT_NAMES.contains(&agent_name)) {
+        err!("Unknown bot '{}'", agent_name);
+        info!("Usage: add_bot [{}]", ai::AGENT_NAMES.join("|"));
+        return;
+    }
+
+    let mut registration = None;
+    let mut dummy_player_inputs = PlayerInputs::default();
+    let mut new_players = Vec::new();
+    let created = PlayerInputController::create_player(
+        &mut registration,
+        Input::None,
+        &mut dummy_player_inputs,
+        &mut client.timeline,
+        &mut client.entities.players,
+        &client.entities.outfit_switchers,
+        &mut new_players,
+        None,
+        None);
+
+    if let Some(player_id) = created {
+        let agent = ai::create_agent(agent_name, player_id, client.timeline.map.get_seed()).unwrap();
+        client.bots.push((player_id, agent));
+        info!("Added '{}' bot as player {}", agent_name, player_id.0);
+    }
+}
+
 fn do_toggle_trailer_mode(args: &[&str], client: &mut Client) {
     if (args.len() != 0) {
         err!("Expected no arguments to trailer_mode, got {}", args.len());
@@ -794,6 +840,33 @@ fn do_seed(args: &[&str], client: &mut Client) {
     info!("Seed: {}", client.seed);
 }
 
+fn do_play_replay(args: &[&str], client: &mut Client) {
+    if (args.len() != 1) {
+        err!("Expected one argument to play_replay, got {}", args.len());
+        info("Usage: play_replay replays/some_game.replay");
+        return;
+    }
+
+    let bytes = match std::fs::read(args[0]) {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            err!("Unable to read '{}': {}", args[0], e);
+            return;
+        }
+    };
+
+    match Replay::from_bytes(&bytes) {
+        Ok(replay) => {
+            big!("Playing replay '{}'", args[0]);
+            info!("{} frames, {} events", replay.last_frame_id, replay.events.len());
+            client.start_replay(ReplayPlayer::new(replay));
+        },
+        Err(e) => {
+            err!("Unable to load replay '{}': {}", args[0], e);
+        }
+    }
+}
+
 fn do_dump_controllers(args: &[&str], _client: &mut Client) {
     if (args.len() != 0) {
         err!("Expected no arguments to dump_controllers, got {}", args.len());
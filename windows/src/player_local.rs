@@ -1,4 +1,4 @@
-use crossy_multi_core::{crossy_ruleset::{player_in_lobby_ready_zone, AliveState, CrossyRulesetFST}, game, map::RowType, math::V2, player::PlayerStatePublic, timeline::{Timeline, TICK_INTERVAL_US}, CoordPos, GameState, Input, PlayerId, PlayerInputs, Pos};
+use crossy_multi_core::{crossy_ruleset::{player_in_lobby_ready_zone, AliveState, CrossyRulesetFST}, game, map::RowType, math::V2, player::{PlayerStatePublic, Powerup}, timeline::{Timeline, TICK_INTERVAL_US}, CoordPos, GameState, Input, PlayerId, PlayerInputs, Pos};
 use froggy_rand::FroggyRand;
 use strum_macros::EnumString;
 
@@ -21,9 +21,10 @@ pub struct PlayerLocal {
     pub controller_id: Option<i32>,
     pub steam_controller_id: Option<u64>,
     pub alive_state: AliveState,
+    pub team: Option<u8>,
+    pub shielded: bool,
 }
 
-const MOVE_T : i32 = 7 * (1000 * 1000 / 60);
 const PLAYER_FRAME_COUNT: i32 = 5;
 
 #[derive(Default)]
@@ -227,6 +228,18 @@ pub const g_all_skins: [PlayerSkin; 9] = [
     PlayerSkin::Sausage,
 ];
 
+// Multiplied over skins in team modes so you can tell who's with who.
+pub const g_team_tints: [raylib_sys::Color; 2] = [
+    crate::hex_color("ff9d8a".as_bytes()),
+    crate::hex_color("8ab4ff".as_bytes()),
+];
+
+// Same order as the tints, and as TEAM_NAMES in the web client.
+pub const g_team_names: [&str; 2] = [
+    "RED TEAM",
+    "BLUE TEAM",
+];
+
 impl Default for Skin {
     fn default() -> Self {
         Self::from_enum(PlayerSkin::Frog)
@@ -365,6 +378,8 @@ impl PlayerLocal {
             controller_id: None,
             steam_controller_id: None,
             alive_state: AliveState::NotInGame,
+            team: None,
+            shielded: false,
         }
     }
 
@@ -414,6 +429,8 @@ impl PlayerLocal {
         outfit_switchers: &mut EntityContainer<OutfitSwitcher>
     ) {
         self.alive_state = alive_state;
+        self.team = timeline.top_state().rules_state.fst.get_player_team(self.player_id);
+        self.shielded = matches!(player_state.powerup, Some(Powerup::Shield));
         if (alive_state == AliveState::NotInGame) {
             return;
         }
@@ -421,7 +438,7 @@ impl PlayerLocal {
         self.t += 1;
 
         if let CrossyRulesetFST::EndWinner(state) = &timeline.top_state().rules_state.fst {
-            if (state.winner_id != self.player_id) {
+            if (!state.is_winner(self.player_id)) {
                 self.visible = false;
                 return;
             }
@@ -433,7 +450,7 @@ impl PlayerLocal {
         let mut x: f32 = 0.0;
         let mut y: f32 = 0.0;
         if (player_state.moving) {
-            let tt = (player_state.remaining_move_dur as f32 / MOVE_T as f32);
+            let tt = (player_state.remaining_move_dur as f32 / player_state.move_dur as f32);
             let lerp_t = 1.0 - tt;
 
             let x1 = player_state.t_x as f32;
@@ -638,7 +655,12 @@ impl IsEntity for PlayerLocal {
         //if (self.image_index != 0) {
         //    println!("image index {}", self.image_index);
         //}
-        sprites::draw_with_flip(&self.skin.sprite, self.image_index as usize, self.pos.x * 8.0, self.pos.y * 8.0 - 2.0, self.x_flip);
+        let tint = self.team.map(|team| g_team_tints[team as usize % g_team_tints.len()]).unwrap_or(crate::WHITE);
+        sprites::draw_with_flip_tinted(&self.skin.sprite, self.image_index as usize, self.pos.x * 8.0, self.pos.y * 8.0 - 2.0, self.x_flip, tint);
+
+        if (self.shielded) {
+            raylib_sys::DrawCircleLines((self.pos.x * 8.0) as i32 + 4, (self.pos.y * 8.0) as i32 + 2, 6.0, crate::client::pickup_shield_col);
+        }
 
         //export const hat_offsets = [
         //    [3, 4, 2, 1, 2, 2],
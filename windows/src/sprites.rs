@@ -194,6 +194,10 @@ pub fn draw_ex(name: &str, image_index: usize, pos: V2, rotation: f32, scale: f3
 }
 
 pub fn draw_with_flip(name: &str, image_index: usize, x: f32, y: f32, x_flip: bool) {
+    draw_with_flip_tinted(name, image_index, x, y, x_flip, crate::WHITE);
+}
+
+pub fn draw_with_flip_tinted(name: &str, image_index: usize, x: f32, y: f32, x_flip: bool, tint: Color) {
     let sprite = get_sprite(name)[image_index];
     let x_flip_f = if x_flip {-1.0} else {1.0};
     let rect = raylib_sys::Rectangle{
@@ -217,7 +221,7 @@ pub fn draw_with_flip(name: &str, image_index: usize, x: f32, y: f32, x_flip: bo
             dest,
             raylib_sys::Vector2::zero(),
             0.0,
-            crate::WHITE);
+            tint);
     }
 }
 